@@ -0,0 +1,22 @@
+use candid::utils::{ArgumentDecoder, ArgumentEncoder};
+use candid::Principal;
+use ic_cdk::call::{Call, CallResult, OnewayError};
+
+// Inter-canister calls, kept to the argument and reply tuples the ledger,
+// minter and management canister interfaces are written against.
+
+/// Calls `method` and waits for the reply however long it takes, so a reply
+/// that changes state on the other side is never lost.
+pub(crate) async fn call<A: ArgumentEncoder, R: for<'de> ArgumentDecoder<'de>>(
+    canister: Principal,
+    method: &str,
+    args: A,
+) -> CallResult<R> {
+    let response = Call::unbounded_wait(canister, method).with_args(&args).await?;
+    Ok(response.candid_tuple()?)
+}
+
+/// Sends `method` without waiting for a reply.
+pub(crate) fn notify<A: ArgumentEncoder>(canister: Principal, method: &str, args: A) -> Result<(), OnewayError> {
+    Call::unbounded_wait(canister, method).with_args(&args).oneway()
+}
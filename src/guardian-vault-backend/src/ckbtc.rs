@@ -1,8 +1,16 @@
 use candid::{CandidType, Deserialize, Principal};
-use ic_cdk::api::call::call;
-use crate::state::{with_state, update_state};
+use crate::canister_call::call;
+use crate::state::{
+    with_state, with_state_mut, update_state, NamedSubaccount, TransactionKind, TransactionRecord,
+    TransactionStatus, TransferIntent, VaultStateV2,
+};
+use crate::proposals::{fail_stalled_proposals, open_proposal, requires_guardian_approval, ProposalAction, ProposalStatus, TransferOutcome};
+use crate::whitelist::{ensure_whitelisted, WhitelistDestination};
+use crate::freeze::ensure_not_frozen;
+use crate::vault_id::vault_id_of;
+use sha2::{Sha256, Digest};
 use crate::types::{
-    Icrc1Account, TransferError, GetDepositAddressArgs, RetrieveBtcArgs, 
+    Icrc1Account, TransferError, TransferFromError, GetDepositAddressArgs, RetrieveBtcArgs, 
     RetrieveBtcWithApprovalArgs, RetrieveBtcError, DepositAddressError,
     UtxoStatus, PendingUtxo
 };
@@ -20,36 +28,66 @@ struct Icrc1TransferArg {
     created_at_time: Option<u64>,
 }
 
+#[derive(CandidType, Deserialize)]
+struct Icrc2TransferFromArg {
+    spender_subaccount: Option<Vec<u8>>,
+    from: Icrc1Account,
+    to: Icrc1Account,
+    amount: candid::Nat,
+    fee: Option<candid::Nat>,
+    memo: Option<Vec<u8>>,
+    created_at_time: Option<u64>,
+}
+
+/// Ledger calls made per `ckbtc_transfer` before a transient error is returned to the caller.
+const MAX_TRANSFER_ATTEMPTS: u32 = 3;
+
+/// Domain separator for SHA-256 subaccount derivation. Changing it moves every derived subaccount.
+const SUBACCOUNT_DOMAIN: &[u8] = b"guardian_vault_subaccount_v1";
+
+const MAX_SUBACCOUNTS_PER_USER: usize = 32;
+const MAX_SUBACCOUNT_NAME_LEN: usize = 64;
+
+/// The ledger's deduplication window. Idempotency keys are kept past it, but an
+/// unresolved one is no longer retried since the ledger could not catch a repeat.
+const TRANSFER_DEDUP_WINDOW_NANOS: u64 = 24 * 60 * 60 * 1_000_000_000;
+
 // Subaccount derivation utilities
-pub fn derive_subaccount_from_principal(principal: &Principal) -> Vec<u8> {
+/// Default subaccount of a vault. For a vault id that is still its owner's
+/// principal bytes this is the usual principal-derived subaccount.
+pub fn derive_subaccount_from_vault_id(vault_id: &[u8]) -> Vec<u8> {
     let mut subaccount = [0u8; 32];
-    let principal_bytes = principal.as_slice();
-    let len = principal_bytes.len().min(29);
+    let len = vault_id.len().min(29);
     subaccount[0] = len as u8;
-    subaccount[1..=len].copy_from_slice(&principal_bytes[..len]);
+    subaccount[1..=len].copy_from_slice(&vault_id[..len]);
     subaccount.to_vec()
 }
 
 pub fn derive_subaccount_from_seed(seed: &str) -> Vec<u8> {
-    // In production, use proper key derivation
-    let mut hasher = std::collections::hash_map::DefaultHasher::new();
-    use std::hash::{Hash, Hasher};
-    seed.hash(&mut hasher);
-    let hash = hasher.finish();
-    let mut subaccount = [0u8; 32];
-    subaccount[0..8].copy_from_slice(&hash.to_be_bytes());
-    subaccount.to_vec()
+    let mut hasher = Sha256::new();
+    hasher.update(SUBACCOUNT_DOMAIN);
+    hasher.update(seed.as_bytes());
+    hasher.finalize().to_vec()
+}
+
+pub fn derive_named_subaccount(vault_id: &[u8], name: &str) -> Vec<u8> {
+    let mut hasher = Sha256::new();
+    hasher.update(SUBACCOUNT_DOMAIN);
+    // Length prefix keeps (vault, name) pairs from colliding
+    hasher.update([vault_id.len() as u8]);
+    hasher.update(vault_id);
+    hasher.update(name.as_bytes());
+    hasher.finalize().to_vec()
 }
 
 // ICRC-1 Ledger Functions
+/// Balance of one of the caller's vault subaccounts, by default the vault's
+/// default subaccount.
 #[ic_cdk::query]
 pub async fn ckbtc_balance_of(subaccount: Option<Vec<u8>>) -> Result<candid::Nat, String> {
     let cfg = with_state(|state| state.config.clone().ok_or("config not set"))?;
-    let account = Icrc1Account { owner: ic_cdk::caller(), subaccount };
-    let (balance,): (candid::Nat,) = call(cfg.ckbtc_ledger, "icrc1_balance_of", (Icrc1BalanceOfArg { account },))
-        .await
-        .map_err(|e| format!("icrc1_balance_of failed: {:?}", e))?;
-    Ok(balance)
+    let account = with_state(|state| vault_account(state, &ic_cdk::api::msg_caller(), ic_cdk::api::canister_self(), subaccount));
+    ledger_balance_of(cfg.ckbtc_ledger, account).await
 }
 
 #[ic_cdk::update]
@@ -60,43 +98,253 @@ pub async fn ckbtc_transfer(
     fee: Option<candid::Nat>,
     from_subaccount: Option<Vec<u8>>,
     memo: Option<Vec<u8>>,
+    idempotency_key: Option<String>,
+) -> Result<TransferOutcome, String> {
+    let caller = ic_cdk::api::msg_caller();
+    let to = Icrc1Account { owner: to_owner, subaccount: to_sub };
+    with_state(|state| ensure_not_frozen(state, ic_cdk::api::time()))?;
+    with_state(|state| check_spend_subaccount(state, &caller, &from_subaccount))?;
+
+    let destination = WhitelistDestination::Icrc { account: to.clone() };
+    with_state(|state| ensure_whitelisted(state, &destination, ic_cdk::api::time()))?;
+
+    if with_state(|state| requires_guardian_approval(state, &amount)) {
+        let proposal_id = with_state_mut(|state| {
+            fail_stalled_proposals(state, ic_cdk::api::time());
+            // A retried request with the same key reports the proposal it already opened
+            if let Some(key) = &idempotency_key {
+                let args_hash = transfer_args_hash(&to, &amount, &fee, &from_subaccount, &memo);
+                if let Some(id) = keyed_transfer_proposal(state, caller, key, &args_hash)? {
+                    return Ok(id);
+                }
+            }
+            let action = ProposalAction::CkbtcTransfer { to, amount, fee, from_subaccount, memo, idempotency_key };
+            open_proposal(state, caller, action, ic_cdk::api::time())
+        })?;
+        return Ok(TransferOutcome::PendingApproval { proposal_id });
+    }
+
+    let block_index = execute_ckbtc_transfer(caller, to, amount, fee, from_subaccount, memo, idempotency_key).await?;
+    Ok(TransferOutcome::Completed { block_index })
+}
+
+pub(crate) async fn execute_ckbtc_transfer(
+    caller: Principal,
+    to: Icrc1Account,
+    amount: candid::Nat,
+    fee: Option<candid::Nat>,
+    from_subaccount: Option<Vec<u8>>,
+    memo: Option<Vec<u8>>,
+    idempotency_key: Option<String>,
 ) -> Result<u128, String> {
+    send_ckbtc_transfer(caller, to, amount, fee, from_subaccount, memo, idempotency_key)
+        .await
+        .map(|(block_index, _)| block_index)
+}
+
+/// Sends the transfer and returns its block index, along with whether this
+/// call created it rather than finding an earlier transfer under the same key.
+async fn send_ckbtc_transfer(
+    caller: Principal,
+    to: Icrc1Account,
+    amount: candid::Nat,
+    fee: Option<candid::Nat>,
+    from_subaccount: Option<Vec<u8>>,
+    memo: Option<Vec<u8>>,
+    idempotency_key: Option<String>,
+) -> Result<(u128, bool), String> {
+    // Checked here as well as at each entry point, so no payout path skips it
+    with_state(|state| ensure_not_frozen(state, ic_cdk::api::time()))?;
     let cfg = with_state(|state| state.config.clone().ok_or("config not set"))?;
-    let arg = Icrc1TransferArg {
-        from_subaccount,
-        to: Icrc1Account { owner: to_owner, subaccount: to_sub },
-        amount,
-        fee,
+
+    // With an idempotency key, every retry reuses the same created_at_time and memo
+    // so the ledger's deduplication catches a transfer that already went through.
+    let (mut created_at_time, memo) = match &idempotency_key {
+        Some(key) => {
+            let args_hash = transfer_args_hash(&to, &amount, &fee, &from_subaccount, &memo);
+            let memo = memo.or_else(|| Some(idempotency_memo(&caller, key)));
+            let intent = with_state_mut(|state| {
+                resolve_transfer_intent(state, caller, key, args_hash, memo, ic_cdk::api::time())
+            })?;
+            if let Some(block_index) = intent.block_index {
+                return Ok((block_index, false));
+            }
+            (intent.created_at_time, intent.memo)
+        }
+        None => (ic_cdk::api::time(), memo),
+    };
+
+    let mut attempt = 0;
+    loop {
+        attempt += 1;
+        let arg = Icrc1TransferArg {
+            from_subaccount: from_subaccount.clone(),
+            to: to.clone(),
+            amount: amount.clone(),
+            fee: fee.clone(),
+            memo: memo.clone(),
+            created_at_time: Some(created_at_time),
+        };
+        let (res,): (Result<candid::Nat, TransferError>,) = call(cfg.ckbtc_ledger, "icrc1_transfer", (arg,))
+            .await
+            .map_err(|e| format!("icrc1_transfer failed: {:?}", e))?;
+        let (block_index, is_new) = match res {
+            Ok(height) => (nat_to_u128(height), true),
+            // The ledger already executed this exact transfer; report the original block.
+            Err(TransferError::Duplicate { duplicate_of }) => (nat_to_u128(duplicate_of), false),
+            Err(TransferError::TemporarilyUnavailable) if attempt < MAX_TRANSFER_ATTEMPTS => continue,
+            // Past the ledger's deduplication window an earlier attempt may still have
+            // gone through, so a keyed transfer is never re-sent under a new timestamp.
+            Err(TransferError::TooOld) if idempotency_key.is_some() => {
+                return Err("transfer is too old for the ledger to deduplicate; check the ledger for the original transfer before retrying with a new idempotency key".to_string());
+            }
+            Err(TransferError::TooOld) if attempt < MAX_TRANSFER_ATTEMPTS => {
+                created_at_time = ic_cdk::api::time();
+                continue;
+            }
+            Err(e) => return Err(format!("transfer error: {:?}", e)),
+        };
+        if let Some(key) = &idempotency_key {
+            update_state(|state| {
+                if let Some(intent) = state.transfer_intents.get_mut(&(caller, key.clone())) {
+                    intent.block_index = Some(block_index);
+                }
+            });
+        }
+        return Ok((block_index, is_new));
+    }
+}
+
+/// Pulls `amount` from `from`'s default account into one of the vault's own
+/// subaccounts, spending an ICRC-2 allowance granted to this canister.
+pub(crate) async fn collect_with_allowance(
+    from: Principal,
+    to_subaccount: Vec<u8>,
+    amount: u64,
+    memo: Option<Vec<u8>>,
+) -> Result<u128, String> {
+    let cfg = with_state(|state| state.config.clone().ok_or("config not set"))?;
+    let arg = Icrc2TransferFromArg {
+        spender_subaccount: None,
+        from: Icrc1Account { owner: from, subaccount: None },
+        to: Icrc1Account { owner: ic_cdk::api::canister_self(), subaccount: Some(to_subaccount) },
+        amount: candid::Nat::from(amount),
+        fee: None,
         memo,
         created_at_time: Some(ic_cdk::api::time()),
     };
-    let (res,): (Result<candid::Nat, TransferError>,) = call(cfg.ckbtc_ledger, "icrc1_transfer", (arg,))
+    let (res,): (Result<candid::Nat, TransferFromError>,) = call(cfg.ckbtc_ledger, "icrc2_transfer_from", (arg,))
         .await
-        .map_err(|e| format!("icrc1_transfer failed: {:?}", e))?;
-    match res { 
-        Ok(height) => Ok(nat_to_u128(height)), 
-        Err(e) => Err(format!("transfer error: {:?}", e)) 
+        .map_err(|e| format!("icrc2_transfer_from failed: {:?}", e))?;
+    match res {
+        Ok(height) => Ok(nat_to_u128(height)),
+        Err(TransferFromError::Duplicate { duplicate_of }) => Ok(nat_to_u128(duplicate_of)),
+        Err(e) => Err(format!("transfer_from error: {:?}", e)),
     }
 }
 
+/// Pays out `amount` held in a vault subaccount, less the ledger fee. Keyed by
+/// `idempotency_key` so a retried payout is deduplicated by the ledger. Returns
+/// `None` when the amount does not cover the fee and nothing was sent.
+pub(crate) async fn release_from_subaccount(
+    idempotency_key: String,
+    from_subaccount: Vec<u8>,
+    to: Icrc1Account,
+    amount: u64,
+) -> Result<Option<u128>, String> {
+    let cfg = with_state(|state| state.config.clone().ok_or("config not set"))?;
+    let fee = ledger_fee(cfg.ckbtc_ledger).await?;
+    let amount = candid::Nat::from(amount);
+    if amount <= fee {
+        return Ok(None);
+    }
+    let net = amount - fee.clone();
+    execute_ckbtc_transfer(ic_cdk::api::canister_self(), to, net, Some(fee), Some(from_subaccount), None, Some(idempotency_key))
+        .await
+        .map(Some)
+}
+
 #[ic_cdk::query]
 pub async fn get_transaction_fee() -> Result<candid::Nat, String> {
     let cfg = with_state(|state| state.config.clone().ok_or("config not set"))?;
-    let (fee,): (candid::Nat,) = call(cfg.ckbtc_ledger, "icrc1_fee", ())
-        .await
-        .map_err(|e| format!("icrc1_fee failed: {:?}", e))?;
-    Ok(fee)
+    ledger_fee(cfg.ckbtc_ledger).await
+}
+
+// Pockets
+/// Moves ckBTC between two of the caller's named subaccounts. Funds never leave
+/// the vault, so the whitelist and guardian approval threshold do not apply.
+#[ic_cdk::update]
+pub async fn move_between_pockets(
+    from_pocket: String,
+    to_pocket: String,
+    amount: candid::Nat,
+    idempotency_key: Option<String>,
+) -> Result<u128, String> {
+    let cfg = with_state(|state| state.config.clone().ok_or("config not set"))?;
+    let caller = ic_cdk::api::msg_caller();
+    let (from_sub, to_sub) = with_state(|state| resolve_pockets(state, &caller, &from_pocket, &to_pocket))?;
+    let amount_e8s = pocket_move_amount(&amount)?;
+
+    let vault = ic_cdk::api::canister_self();
+    let fee = ledger_fee(cfg.ckbtc_ledger).await?;
+    let fee_e8s = nat_to_u64(&fee).ok_or("ledger fee does not fit in 64 bits")?;
+    let balance = ledger_balance_of(cfg.ckbtc_ledger, Icrc1Account { owner: vault, subaccount: Some(from_sub.clone()) }).await?;
+    check_pocket_funds(&balance, &amount, &fee)?;
+
+    let (block_index, is_new) = send_ckbtc_transfer(
+        caller,
+        Icrc1Account { owner: vault, subaccount: Some(to_sub) },
+        amount.clone(),
+        Some(fee.clone()),
+        Some(from_sub),
+        None,
+        idempotency_key,
+    ).await?;
+
+    // A retry that resolved to an earlier transfer is already in the history
+    if is_new {
+        update_state(|state| {
+            let id = state.transaction_history.len() as u64 + 1;
+            state.transaction_history.push(TransactionRecord {
+                id,
+                from: caller,
+                to: caller,
+                amount: amount_e8s,
+                fee: fee_e8s,
+                memo: None,
+                timestamp: ic_cdk::api::time(),
+                status: TransactionStatus::Confirmed,
+                kind: TransactionKind::InternalTransfer { from_pocket, to_pocket },
+            });
+        });
+    }
+
+    Ok(block_index)
+}
+
+#[ic_cdk::query]
+pub fn get_transaction_history() -> Vec<TransactionRecord> {
+    let caller = ic_cdk::api::msg_caller();
+    with_state(|state| {
+        state.transaction_history.iter()
+            .filter(|t| t.from == caller || t.to == caller)
+            .cloned()
+            .collect()
+    })
 }
 
 // ckBTC Minter Functions
+/// BTC deposit address that mints ckBTC into one of the caller's vault
+/// subaccounts, so the funds follow the vault through recovery.
 #[ic_cdk::update]
 pub async fn get_deposit_address(subaccount: Option<Vec<u8>>) -> Result<String, String> {
     let cfg = with_state(|state| state.config.clone().ok_or("config not set"))?;
-    let caller = ic_cdk::caller();
+    let caller = ic_cdk::api::msg_caller();
     
+    let account = with_state(|state| vault_account(state, &caller, ic_cdk::api::canister_self(), subaccount.clone()));
     let args = GetDepositAddressArgs {
-        owner: Some(caller),
-        subaccount: subaccount.clone(),
+        owner: Some(account.owner),
+        subaccount: account.subaccount,
     };
     
     let (result,): (Result<String, DepositAddressError>,) = 
@@ -106,12 +354,10 @@ pub async fn get_deposit_address(subaccount: Option<Vec<u8>>) -> Result<String,
     
     match result {
         Ok(address) => {
-            // Store the subaccount mapping
-            update_state(|state| {
-                if let Some(sub) = subaccount {
-                    state.subaccounts.insert(caller, sub);
-                }
-            });
+            // Remember deposit subaccounts so they show up when listing the caller's subaccounts
+            if let Some(sub) = subaccount {
+                update_state(|state| record_deposit_subaccount(state, caller, sub, ic_cdk::api::time()));
+            }
             Ok(address)
         },
         Err(e) => Err(format!("deposit address error: {:?}", e))
@@ -119,7 +365,25 @@ pub async fn get_deposit_address(subaccount: Option<Vec<u8>>) -> Result<String,
 }
 
 #[ic_cdk::update]
-pub async fn retrieve_btc(address: String, amount: u64) -> Result<u64, String> {
+pub async fn retrieve_btc(address: String, amount: u64) -> Result<TransferOutcome, String> {
+    let caller = ic_cdk::api::msg_caller();
+    with_state(|state| check_vault_owner(state, &caller))?;
+    with_state(|state| ensure_not_frozen(state, ic_cdk::api::time()))?;
+    let destination = WhitelistDestination::Bitcoin { address: address.clone() };
+    with_state(|state| ensure_whitelisted(state, &destination, ic_cdk::api::time()))?;
+
+    if with_state(|state| requires_guardian_approval(state, &candid::Nat::from(amount))) {
+        let action = ProposalAction::RetrieveBtc { address, amount };
+        let proposal_id = with_state_mut(|state| open_proposal(state, caller, action, ic_cdk::api::time()))?;
+        return Ok(TransferOutcome::PendingApproval { proposal_id });
+    }
+
+    let block_index = execute_retrieve_btc(address, amount).await?;
+    Ok(TransferOutcome::Completed { block_index: block_index.into() })
+}
+
+pub(crate) async fn execute_retrieve_btc(address: String, amount: u64) -> Result<u64, String> {
+    with_state(|state| ensure_not_frozen(state, ic_cdk::api::time()))?;
     let cfg = with_state(|state| state.config.clone().ok_or("config not set"))?;
     
     let args = RetrieveBtcArgs { address, amount };
@@ -140,7 +404,30 @@ pub async fn retrieve_btc_with_approval(
     address: String, 
     amount: u64, 
     from_subaccount: Option<Vec<u8>>
+) -> Result<TransferOutcome, String> {
+    let caller = ic_cdk::api::msg_caller();
+    with_state(|state| check_vault_owner(state, &caller))?;
+    with_state(|state| ensure_not_frozen(state, ic_cdk::api::time()))?;
+    let destination = WhitelistDestination::Bitcoin { address: address.clone() };
+    with_state(|state| ensure_whitelisted(state, &destination, ic_cdk::api::time()))?;
+    with_state(|state| check_spend_subaccount(state, &caller, &from_subaccount))?;
+
+    if with_state(|state| requires_guardian_approval(state, &candid::Nat::from(amount))) {
+        let action = ProposalAction::RetrieveBtcWithApproval { address, amount, from_subaccount };
+        let proposal_id = with_state_mut(|state| open_proposal(state, caller, action, ic_cdk::api::time()))?;
+        return Ok(TransferOutcome::PendingApproval { proposal_id });
+    }
+
+    let block_index = execute_retrieve_btc_with_approval(address, amount, from_subaccount).await?;
+    Ok(TransferOutcome::Completed { block_index: block_index.into() })
+}
+
+pub(crate) async fn execute_retrieve_btc_with_approval(
+    address: String, 
+    amount: u64, 
+    from_subaccount: Option<Vec<u8>>
 ) -> Result<u64, String> {
+    with_state(|state| ensure_not_frozen(state, ic_cdk::api::time()))?;
     let cfg = with_state(|state| state.config.clone().ok_or("config not set"))?;
     
     let args = RetrieveBtcWithApprovalArgs { 
@@ -163,11 +450,10 @@ pub async fn retrieve_btc_with_approval(
 #[ic_cdk::query]
 pub async fn get_utxos(subaccount: Option<Vec<u8>>) -> Result<Vec<UtxoStatus>, String> {
     let cfg = with_state(|state| state.config.clone().ok_or("config not set"))?;
-    let caller = ic_cdk::caller();
-    
+    let account = with_state(|state| vault_account(state, &ic_cdk::api::msg_caller(), ic_cdk::api::canister_self(), subaccount));
     let args = GetDepositAddressArgs {
-        owner: Some(caller),
-        subaccount,
+        owner: Some(account.owner),
+        subaccount: account.subaccount,
     };
     
     let (utxos,): (Vec<UtxoStatus>,) = call(cfg.ckbtc_minter, "get_utxos", (args,))
@@ -180,11 +466,10 @@ pub async fn get_utxos(subaccount: Option<Vec<u8>>) -> Result<Vec<UtxoStatus>, S
 #[ic_cdk::query]
 pub async fn get_pending_utxos(subaccount: Option<Vec<u8>>) -> Result<Vec<PendingUtxo>, String> {
     let cfg = with_state(|state| state.config.clone().ok_or("config not set"))?;
-    let caller = ic_cdk::caller();
-    
+    let account = with_state(|state| vault_account(state, &ic_cdk::api::msg_caller(), ic_cdk::api::canister_self(), subaccount));
     let args = GetDepositAddressArgs {
-        owner: Some(caller),
-        subaccount,
+        owner: Some(account.owner),
+        subaccount: account.subaccount,
     };
     
     let (pending_utxos,): (Vec<PendingUtxo>,) = call(cfg.ckbtc_minter, "get_pending_utxos", (args,))
@@ -195,31 +480,274 @@ pub async fn get_pending_utxos(subaccount: Option<Vec<u8>>) -> Result<Vec<Pendin
 }
 
 // Subaccount Management
+/// Creates (or restores) the caller's subaccount with the given name. The same
+/// name always derives the same subaccount.
 #[ic_cdk::update]
-pub fn create_subaccount(seed: String) -> Vec<u8> {
-    let caller = ic_cdk::caller();
-    let subaccount = derive_subaccount_from_seed(&format!("{}:{}", caller.to_text(), seed));
-    
-    update_state(|state| {
-        state.subaccounts.insert(caller, subaccount.clone());
-    });
-    
-    subaccount
+pub fn create_subaccount(name: String) -> Result<Vec<u8>, String> {
+    let caller = ic_cdk::api::msg_caller();
+    with_state_mut(|state| register_subaccount(state, caller, &name, ic_cdk::api::time()))
+}
+
+#[ic_cdk::update]
+pub fn archive_subaccount(name: String) -> Result<(), String> {
+    let caller = ic_cdk::api::msg_caller();
+    with_state_mut(|state| {
+        let entry = state.subaccounts.get_mut(&caller)
+            .and_then(|subs| subs.iter_mut().find(|s| s.name == name))
+            .ok_or("subaccount not found")?;
+        entry.archived = true;
+        Ok(())
+    })
 }
 
+#[ic_cdk::query]
+pub fn list_subaccounts(include_archived: bool) -> Vec<NamedSubaccount> {
+    let caller = ic_cdk::api::msg_caller();
+    with_state(|state| {
+        state.subaccounts.get(&caller)
+            .map(|subs| subs.iter().filter(|s| include_archived || !s.archived).cloned().collect())
+            .unwrap_or_default()
+    })
+}
+
+/// Returns the caller's first active subaccount.
 #[ic_cdk::query]
 pub fn get_user_subaccount() -> Option<Vec<u8>> {
-    let caller = ic_cdk::caller();
-    with_state(|state| state.subaccounts.get(&caller).cloned())
+    let caller = ic_cdk::api::msg_caller();
+    with_state(|state| default_subaccount(state, &caller))
 }
 
+/// Default subaccount of the caller's vault.
 #[ic_cdk::query]
 pub fn get_principal_subaccount() -> Vec<u8> {
-    let caller = ic_cdk::caller();
-    derive_subaccount_from_principal(&caller)
+    let caller = ic_cdk::api::msg_caller();
+    with_state(|state| derive_subaccount_from_vault_id(&vault_id_of(state, &caller)))
+}
+
+/// The account the caller's version 1 deposits went to. It belongs to the
+/// caller rather than the vault, so only the caller can move funds out of it.
+#[ic_cdk::query]
+pub fn get_legacy_account() -> Option<Icrc1Account> {
+    let caller = ic_cdk::api::msg_caller();
+    with_state(|state| state.legacy_accounts.get(&caller).cloned())
+}
+
+// Subaccount helpers
+pub(crate) fn default_subaccount(state: &VaultStateV2, owner: &Principal) -> Option<Vec<u8>> {
+    state.subaccounts.get(owner)?
+        .iter()
+        .find(|s| !s.archived)
+        .map(|s| s.subaccount.clone())
+}
+
+/// Account of a vault subaccount on the ledger: `subaccount`, or the vault's
+/// default subaccount when none is given.
+pub(crate) fn vault_account(state: &VaultStateV2, caller: &Principal, vault: Principal, subaccount: Option<Vec<u8>>) -> Icrc1Account {
+    let subaccount = subaccount.unwrap_or_else(|| derive_subaccount_from_vault_id(&vault_id_of(state, caller)));
+    Icrc1Account { owner: vault, subaccount: Some(subaccount) }
+}
+
+/// Withdrawals to Bitcoin spend the vault's funds, so only its owner may make them.
+fn check_vault_owner(state: &VaultStateV2, caller: &Principal) -> Result<(), String> {
+    let g = state.guardian_state.as_ref().ok_or("guardian state not initialized")?;
+    if g.owner != *caller {
+        return Err("only owner can retrieve btc".to_string());
+    }
+    Ok(())
+}
+
+/// Only the caller's own vault subaccounts may be spent from; the canister's
+/// main account belongs to the vault owner.
+pub(crate) fn check_spend_subaccount(
+    state: &VaultStateV2,
+    caller: &Principal,
+    from_subaccount: &Option<Vec<u8>>,
+) -> Result<(), String> {
+    match from_subaccount {
+        None => {
+            let g = state.guardian_state.as_ref().ok_or("guardian state not initialized")?;
+            if g.owner != *caller {
+                return Err("only owner can spend from the vault's main account".to_string());
+            }
+            Ok(())
+        }
+        Some(sub) => {
+            let is_default = *sub == derive_subaccount_from_vault_id(&vault_id_of(state, caller));
+            let is_named = state.subaccounts.get(caller).is_some_and(|subs| subs.iter().any(|s| s.subaccount == *sub));
+            if !is_default && !is_named {
+                return Err("subaccount does not belong to the caller's vault".to_string());
+            }
+            Ok(())
+        }
+    }
+}
+
+fn register_subaccount(state: &mut VaultStateV2, owner: Principal, name: &str, now: u64) -> Result<Vec<u8>, String> {
+    let name = name.trim();
+    if name.is_empty() || name.len() > MAX_SUBACCOUNT_NAME_LEN {
+        return Err(format!("subaccount name must be 1-{} bytes", MAX_SUBACCOUNT_NAME_LEN));
+    }
+    let vault_id = vault_id_of(state, &owner);
+
+    let subs = state.subaccounts.entry(owner).or_default();
+    if let Some(existing) = subs.iter_mut().find(|s| s.name == name) {
+        existing.archived = false;
+        return Ok(existing.subaccount.clone());
+    }
+    if subs.len() >= MAX_SUBACCOUNTS_PER_USER {
+        return Err("subaccount limit reached".to_string());
+    }
+
+    let subaccount = derive_named_subaccount(&vault_id, name);
+    subs.push(NamedSubaccount {
+        name: name.to_string(),
+        subaccount: subaccount.clone(),
+        created_at: now,
+        archived: false,
+    });
+    Ok(subaccount)
+}
+
+fn resolve_pockets(
+    state: &VaultStateV2,
+    owner: &Principal,
+    from_pocket: &str,
+    to_pocket: &str,
+) -> Result<(Vec<u8>, Vec<u8>), String> {
+    if from_pocket == to_pocket {
+        return Err("source and destination pockets must differ".to_string());
+    }
+    let subs = state.subaccounts.get(owner).ok_or("no pockets found")?;
+    // Archived pockets can still be emptied, but not paid into
+    let from = subs.iter()
+        .find(|s| s.name == from_pocket)
+        .ok_or_else(|| format!("pocket '{}' not found", from_pocket))?;
+    let to = subs.iter()
+        .find(|s| s.name == to_pocket && !s.archived)
+        .ok_or_else(|| format!("pocket '{}' not found or archived", to_pocket))?;
+    Ok((from.subaccount.clone(), to.subaccount.clone()))
+}
+
+/// Pocket moves are recorded in e8s, so amounts beyond u64 are refused before
+/// anything is sent.
+fn pocket_move_amount(amount: &candid::Nat) -> Result<u64, String> {
+    match nat_to_u64(amount) {
+        Some(0) => Err("amount must be positive".to_string()),
+        Some(amount) => Ok(amount),
+        None => Err("amount does not fit in 64 bits".to_string()),
+    }
+}
+
+fn check_pocket_funds(balance: &candid::Nat, amount: &candid::Nat, fee: &candid::Nat) -> Result<(), String> {
+    if amount.0 == 0u32.into() {
+        return Err("amount must be positive".to_string());
+    }
+    let required = amount.clone() + fee.clone();
+    if *balance < required {
+        return Err(format!("insufficient funds: balance {}, required {} including fee", balance, required));
+    }
+    Ok(())
+}
+
+fn record_deposit_subaccount(state: &mut VaultStateV2, owner: Principal, subaccount: Vec<u8>, now: u64) {
+    let subs = state.subaccounts.entry(owner).or_default();
+    if subs.iter().any(|s| s.subaccount == subaccount) || subs.len() >= MAX_SUBACCOUNTS_PER_USER {
+        return;
+    }
+    let name = format!("deposit-{}", subaccount.iter().take(4).map(|b| format!("{:02x}", b)).collect::<String>());
+    subs.push(NamedSubaccount { name, subaccount, created_at: now, archived: false });
+}
+
+// Idempotent transfer helpers
+fn transfer_args_hash(
+    to: &Icrc1Account,
+    amount: &candid::Nat,
+    fee: &Option<candid::Nat>,
+    from_subaccount: &Option<Vec<u8>>,
+    memo: &Option<Vec<u8>>,
+) -> Vec<u8> {
+    let mut hasher = Sha256::new();
+    hasher.update(to.owner.as_slice());
+    hasher.update(to.subaccount.as_deref().unwrap_or_default());
+    hasher.update(amount.0.to_bytes_be());
+    hasher.update(fee.as_ref().map(|f| f.0.to_bytes_be()).unwrap_or_default());
+    hasher.update(from_subaccount.as_deref().unwrap_or_default());
+    hasher.update(memo.as_deref().unwrap_or_default());
+    hasher.finalize().to_vec()
+}
+
+fn idempotency_memo(caller: &Principal, key: &str) -> Vec<u8> {
+    let mut hasher = Sha256::new();
+    hasher.update(b"guardian_vault_transfer_");
+    hasher.update(caller.as_slice());
+    hasher.update(key.as_bytes());
+    hasher.finalize().to_vec()
+}
+
+fn resolve_transfer_intent(
+    state: &mut VaultStateV2,
+    caller: Principal,
+    key: &str,
+    args_hash: Vec<u8>,
+    memo: Option<Vec<u8>>,
+    now: u64,
+) -> Result<TransferIntent, String> {
+    let intent = state.transfer_intents
+        .entry((caller, key.to_string()))
+        .or_insert_with(|| TransferIntent {
+            args_hash: args_hash.clone(),
+            created_at_time: now,
+            memo,
+            block_index: None,
+        });
+    if intent.args_hash != args_hash {
+        return Err("idempotency key already used with different transfer arguments".to_string());
+    }
+    if intent.block_index.is_none() && now.saturating_sub(intent.created_at_time) >= TRANSFER_DEDUP_WINDOW_NANOS {
+        return Err("idempotency key expired; check the ledger for the original transfer before retrying with a new key".to_string());
+    }
+    Ok(intent.clone())
+}
+
+/// The live proposal `caller` opened for `key`, if any. Failed, cancelled and
+/// expired proposals do not hold the key.
+fn keyed_transfer_proposal(
+    state: &VaultStateV2,
+    caller: Principal,
+    key: &str,
+    args_hash: &[u8],
+) -> Result<Option<u64>, String> {
+    let existing = state.transfer_proposals.iter().find(|p| {
+        p.proposer == caller
+            && matches!(&p.action, ProposalAction::CkbtcTransfer { idempotency_key: Some(k), .. } if k == key)
+            && matches!(p.status, ProposalStatus::Pending | ProposalStatus::Executing | ProposalStatus::Executed { .. })
+    });
+    let Some(proposal) = existing else {
+        return Ok(None);
+    };
+    if let ProposalAction::CkbtcTransfer { to, amount, fee, from_subaccount, memo, .. } = &proposal.action {
+        if transfer_args_hash(to, amount, fee, from_subaccount, memo) != args_hash {
+            return Err("idempotency key already used with different transfer arguments".to_string());
+        }
+    }
+    Ok(Some(proposal.id))
 }
 
 // Utility Functions
+async fn ledger_balance_of(ledger: Principal, account: Icrc1Account) -> Result<candid::Nat, String> {
+    let (balance,): (candid::Nat,) = call(ledger, "icrc1_balance_of", (Icrc1BalanceOfArg { account },))
+        .await
+        .map_err(|e| format!("icrc1_balance_of failed: {:?}", e))?;
+    Ok(balance)
+}
+
+async fn ledger_fee(ledger: Principal) -> Result<candid::Nat, String> {
+    let (fee,): (candid::Nat,) = call(ledger, "icrc1_fee", ())
+        .await
+        .map_err(|e| format!("icrc1_fee failed: {:?}", e))?;
+    Ok(fee)
+}
+
 fn nat_to_u128(n: candid::Nat) -> u128 {
     use num_bigint::BigUint;
     use num_traits::cast::ToPrimitive;
@@ -227,10 +755,219 @@ fn nat_to_u128(n: candid::Nat) -> u128 {
     b.to_u128().unwrap_or(0)
 }
 
-fn nat_to_u64(n: candid::Nat) -> u64 {
-    use num_bigint::BigUint;
+fn nat_to_u64(n: &candid::Nat) -> Option<u64> {
     use num_traits::cast::ToPrimitive;
-    let b: BigUint = n.0; 
-    b.to_u64().unwrap_or(0)
+    n.0.to_u64()
 }
 
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::GuardianState;
+    use std::collections::BTreeMap;
+
+    fn caller_principal() -> Principal {
+        Principal::from_text("rdmx6-jaaaa-aaaaa-aaadq-cai").unwrap()
+    }
+
+    const PINNED_SAVINGS_SUBACCOUNT: &str = "6aefcd86003bba9b5777de2993adc48e1d34e2f347794e3ba233686f7153e4a3";
+
+    fn hex_string(bytes: &[u8]) -> String {
+        bytes.iter().map(|b| format!("{:02x}", b)).collect()
+    }
+
+    fn recipient_account() -> Icrc1Account {
+        Icrc1Account {
+            owner: Principal::from_text("rrkah-fqaaa-aaaaa-aaaaq-cai").unwrap(),
+            subaccount: None,
+        }
+    }
+
+    fn owned_state() -> VaultStateV2 {
+        VaultStateV2 {
+            guardian_state: Some(GuardianState {
+                guardians: vec![recipient_account().owner],
+                quorum: 1,
+                owner: caller_principal(),
+                weights: BTreeMap::new(),
+                categories: BTreeMap::new(),
+                policy: None,
+            }),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_subaccount_derivation_is_pinned() {
+        // Fixed vector: derived subaccounts hold funds, so the derivation must never drift
+        let vault_id = caller_principal().as_slice().to_vec();
+        let sub = derive_named_subaccount(&vault_id, "savings");
+        assert_eq!(sub.len(), 32);
+        assert_eq!(sub, derive_named_subaccount(&vault_id, "savings"));
+        assert_eq!(hex_string(&sub), PINNED_SAVINGS_SUBACCOUNT);
+
+        assert_ne!(sub, derive_named_subaccount(&vault_id, "spending"));
+        assert_ne!(sub, derive_named_subaccount(recipient_account().owner.as_slice(), "savings"));
+        assert_eq!(derive_subaccount_from_seed("seed").len(), 32);
+    }
+
+    #[test]
+    fn test_multiple_named_subaccounts() {
+        let mut state = VaultStateV2::default();
+        let caller = caller_principal();
+
+        let savings = register_subaccount(&mut state, caller, "savings", 1).unwrap();
+        let spending = register_subaccount(&mut state, caller, "spending", 2).unwrap();
+        assert_ne!(savings, spending);
+        assert_eq!(register_subaccount(&mut state, caller, "savings", 3).unwrap(), savings);
+        assert_eq!(state.subaccounts[&caller].len(), 2);
+        assert!(register_subaccount(&mut state, caller, "  ", 4).is_err());
+
+        state.subaccounts.get_mut(&caller).unwrap()[0].archived = true;
+        assert_eq!(default_subaccount(&state, &caller), Some(spending));
+
+        // Re-creating an archived name restores it
+        register_subaccount(&mut state, caller, "savings", 5).unwrap();
+        assert!(!state.subaccounts[&caller][0].archived);
+    }
+
+    #[test]
+    fn test_pocket_resolution() {
+        let mut state = VaultStateV2::default();
+        let caller = caller_principal();
+        let savings = register_subaccount(&mut state, caller, "savings", 1).unwrap();
+        let spending = register_subaccount(&mut state, caller, "spending", 2).unwrap();
+
+        assert_eq!(resolve_pockets(&state, &caller, "savings", "spending"), Ok((savings.clone(), spending.clone())));
+        assert!(resolve_pockets(&state, &caller, "savings", "savings").is_err());
+        assert!(resolve_pockets(&state, &caller, "savings", "travel").is_err());
+        assert!(resolve_pockets(&state, &recipient_account().owner, "savings", "spending").is_err());
+
+        state.subaccounts.get_mut(&caller).unwrap()[0].archived = true;
+        assert!(resolve_pockets(&state, &caller, "spending", "savings").is_err());
+        assert_eq!(resolve_pockets(&state, &caller, "savings", "spending"), Ok((savings, spending)));
+    }
+
+    #[test]
+    fn test_spending_is_limited_to_own_vault() {
+        let mut state = VaultStateV2::default();
+        let caller = caller_principal();
+        let other = recipient_account().owner;
+        let savings = register_subaccount(&mut state, caller, "savings", 1).unwrap();
+        let default = derive_subaccount_from_vault_id(caller.as_slice());
+
+        assert!(check_spend_subaccount(&state, &caller, &Some(savings.clone())).is_ok());
+        assert!(check_spend_subaccount(&state, &caller, &Some(default.clone())).is_ok());
+        assert!(check_spend_subaccount(&state, &other, &Some(savings.clone())).is_err());
+        assert!(check_spend_subaccount(&state, &other, &Some(default)).is_err());
+        assert!(check_spend_subaccount(&state, &caller, &None).is_err());
+
+        // After recovery the new owner spends the old subaccounts and the old owner cannot
+        crate::vault_id::rebind_vault(&mut state, caller, other, 1);
+        assert!(check_spend_subaccount(&state, &other, &Some(savings.clone())).is_ok());
+        assert!(check_spend_subaccount(&state, &caller, &Some(savings)).is_err());
+        assert_eq!(vault_account(&state, &other, other, None).subaccount, Some(derive_subaccount_from_vault_id(caller.as_slice())));
+    }
+
+    #[test]
+    fn test_pocket_funds_include_fee() {
+        let fee = candid::Nat::from(10u64);
+        assert!(check_pocket_funds(&candid::Nat::from(110u64), &candid::Nat::from(100u64), &fee).is_ok());
+        assert!(check_pocket_funds(&candid::Nat::from(109u64), &candid::Nat::from(100u64), &fee).is_err());
+        assert!(check_pocket_funds(&candid::Nat::from(109u64), &candid::Nat::from(0u64), &fee).is_err());
+    }
+
+    #[test]
+    fn test_pocket_move_amount_fits_u64() {
+        assert_eq!(pocket_move_amount(&candid::Nat::from(100u64)), Ok(100));
+        assert!(pocket_move_amount(&candid::Nat::from(0u64)).is_err());
+        let too_large = candid::Nat::from(u64::MAX) + candid::Nat::from(1u64);
+        assert_eq!(pocket_move_amount(&too_large), Err("amount does not fit in 64 bits".to_string()));
+    }
+
+    #[test]
+    fn test_transfer_intent_is_stable_across_retries() {
+        let mut state = VaultStateV2::default();
+        let caller = caller_principal();
+        let hash = transfer_args_hash(&recipient_account(), &candid::Nat::from(1_000u64), &None, &None, &None);
+        let memo = Some(idempotency_memo(&caller, "pay-1"));
+
+        let first = resolve_transfer_intent(&mut state, caller, "pay-1", hash.clone(), memo.clone(), 100).unwrap();
+        let retry = resolve_transfer_intent(&mut state, caller, "pay-1", hash, memo, 500).unwrap();
+
+        assert_eq!(first.created_at_time, 100);
+        assert_eq!(retry.created_at_time, 100);
+        assert_eq!(first.memo, retry.memo);
+        assert_eq!(state.transfer_intents.len(), 1);
+    }
+
+    #[test]
+    fn test_transfer_intent_rejects_changed_arguments() {
+        let mut state = VaultStateV2::default();
+        let caller = caller_principal();
+        let hash1 = transfer_args_hash(&recipient_account(), &candid::Nat::from(1_000u64), &None, &None, &None);
+        let hash2 = transfer_args_hash(&recipient_account(), &candid::Nat::from(2_000u64), &None, &None, &None);
+
+        assert!(resolve_transfer_intent(&mut state, caller, "pay-1", hash1, None, 100).is_ok());
+        let result = resolve_transfer_intent(&mut state, caller, "pay-1", hash2, None, 200);
+        assert!(result.unwrap_err().contains("different transfer arguments"));
+    }
+
+    #[test]
+    fn test_transfer_intents_outlive_dedup_window() {
+        let mut state = VaultStateV2::default();
+        let caller = caller_principal();
+        let hash = transfer_args_hash(&recipient_account(), &candid::Nat::from(1_000u64), &None, &None, &None);
+
+        resolve_transfer_intent(&mut state, caller, "pay-1", hash.clone(), None, 100).unwrap();
+        resolve_transfer_intent(&mut state, caller, "pay-2", hash.clone(), None, 100).unwrap();
+        state.transfer_intents.get_mut(&(caller, "pay-1".to_string())).unwrap().block_index = Some(7);
+        let later = 100 + TRANSFER_DEDUP_WINDOW_NANOS;
+
+        // A late retry gets the recorded outcome, or an error instead of a second send
+        let done = resolve_transfer_intent(&mut state, caller, "pay-1", hash.clone(), None, later).unwrap();
+        assert_eq!((done.created_at_time, done.block_index), (100, Some(7)));
+        let expired = resolve_transfer_intent(&mut state, caller, "pay-2", hash.clone(), None, later);
+        assert!(expired.unwrap_err().contains("expired"));
+
+        let fresh = resolve_transfer_intent(&mut state, caller, "pay-3", hash, None, later).unwrap();
+        assert_eq!(fresh.created_at_time, later);
+        assert_eq!(state.transfer_intents.len(), 3);
+    }
+
+    #[test]
+    fn test_only_owner_retrieves_btc() {
+        let state = owned_state();
+        assert!(check_vault_owner(&state, &caller_principal()).is_ok());
+        assert!(check_vault_owner(&state, &recipient_account().owner).is_err());
+        assert!(check_vault_owner(&VaultStateV2::default(), &caller_principal()).is_err());
+    }
+
+    #[test]
+    fn test_keyed_proposal_is_reused_on_retry() {
+        let mut state = owned_state();
+        let caller = caller_principal();
+        let amount = candid::Nat::from(1_000u64);
+        let hash = transfer_args_hash(&recipient_account(), &amount, &None, &None, &None);
+        let action = ProposalAction::CkbtcTransfer {
+            to: recipient_account(),
+            amount: amount.clone(),
+            fee: None,
+            from_subaccount: None,
+            memo: None,
+            idempotency_key: Some("pay-1".to_string()),
+        };
+        let id = open_proposal(&mut state, caller, action, 100).unwrap();
+
+        assert_eq!(keyed_transfer_proposal(&state, caller, "pay-1", &hash), Ok(Some(id)));
+        assert_eq!(keyed_transfer_proposal(&state, caller, "pay-2", &hash), Ok(None));
+        assert_eq!(keyed_transfer_proposal(&state, recipient_account().owner, "pay-1", &hash), Ok(None));
+        let other = transfer_args_hash(&recipient_account(), &candid::Nat::from(2_000u64), &None, &None, &None);
+        assert!(keyed_transfer_proposal(&state, caller, "pay-1", &other).is_err());
+
+        // A failed proposal releases the key
+        state.transfer_proposals[0].status = ProposalStatus::Failed { reason: "ledger".to_string() };
+        assert_eq!(keyed_transfer_proposal(&state, caller, "pay-1", &hash), Ok(None));
+    }
+}
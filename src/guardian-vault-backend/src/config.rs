@@ -1,10 +1,11 @@
 use candid::Principal;
 use crate::state::{update_state, with_state};
 use crate::types::{Config, GuardianState};
+use std::collections::BTreeMap;
 
 #[ic_cdk::update]
 pub fn init_config(ckbtc_ledger: Principal, ckbtc_minter: Principal, ecdsa_key_name: String) -> Result<(), String> {
-    let caller = ic_cdk::caller();
+    let caller = ic_cdk::api::msg_caller();
     
     // Check if already initialized
     let already_init = with_state(|state| state.config.is_some());
@@ -14,7 +15,14 @@ pub fn init_config(ckbtc_ledger: Principal, ckbtc_minter: Principal, ecdsa_key_n
     
     update_state(|state| {
         state.config = Some(Config { ckbtc_ledger, ckbtc_minter, ecdsa_key_name });
-        state.guardian_state = Some(GuardianState { guardians: vec![], quorum: 0, owner: caller });
+        state.guardian_state = Some(GuardianState {
+            guardians: vec![],
+            quorum: 0,
+            owner: caller,
+            weights: BTreeMap::new(),
+            categories: BTreeMap::new(),
+            policy: None,
+        });
     });
     
     Ok(())
@@ -22,7 +30,7 @@ pub fn init_config(ckbtc_ledger: Principal, ckbtc_minter: Principal, ecdsa_key_n
 
 #[ic_cdk::update]
 pub fn set_config(ckbtc_ledger: Principal, ckbtc_minter: Principal, ecdsa_key_name: String) -> Result<(), String> {
-    let caller = ic_cdk::caller();
+    let caller = ic_cdk::api::msg_caller();
     
     // Validate permissions
     with_state(|state| {
@@ -0,0 +1,347 @@
+use candid::{CandidType, Deserialize, Principal};
+use serde::Serialize;
+use crate::notifications::{notify_vault, NotificationKind};
+use crate::state::{with_state, with_state_mut, VaultStateV2};
+use crate::vetkd::check_issued_share;
+
+/// How long guardians have to respond to a drill.
+const DRILL_DURATION_NANOS: u64 = 7 * 24 * 60 * 60 * 1_000_000_000;
+/// Oldest drills are dropped once this many are kept.
+const MAX_DRILLS: usize = 20;
+
+// A drill rehearses recovery without a recovery request: guardians approve and
+// fetch their VetKD share of a recovery secret as they would for a real one,
+// and the drill records who responded and how long they took. The canister
+// cannot verify a decrypted share, so a drill shows that a guardian can still
+// reach their share, not that they can decrypt it. Drills never touch the
+// guardian state, the one-active-request slot or any funds.
+
+#[derive(Clone, Debug, PartialEq, CandidType, Deserialize, Serialize)]
+pub enum DrillStatus {
+    Running,
+    Finished { finished_at: u64 },
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize, Serialize)]
+pub struct DrillResponse {
+    pub guardian: Principal,
+    pub approved_at: Option<u64>,
+    pub share_fetched_at: Option<u64>,
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize, Serialize)]
+pub struct RecoveryDrill {
+    pub id: u64,
+    pub secret_id: Vec<u8>,
+    pub started_at: u64,
+    pub ends_at: u64,
+    pub responses: Vec<DrillResponse>,
+    pub quorum_reached_at: Option<u64>,
+    pub status: DrillStatus,
+}
+
+/// Response times are measured from the start of the drill.
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct DrillGuardianReport {
+    pub guardian: Principal,
+    pub approved_after: Option<u64>,
+    pub share_fetched_after: Option<u64>,
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct DrillReport {
+    pub drill_id: u64,
+    pub status: DrillStatus,
+    pub quorum_reached_after: Option<u64>, // None means the drill failed or is still short of quorum
+    pub responded: Vec<DrillGuardianReport>,
+    pub silent: Vec<Principal>,
+}
+
+/// Starts a drill against one of the vault's recovery secrets and notifies
+/// the guardians. Only one drill runs at a time.
+#[ic_cdk::update]
+pub fn start_recovery_drill(secret_id: Vec<u8>) -> Result<u64, String> {
+    let caller = ic_cdk::api::msg_caller();
+    with_state_mut(|state| start_drill(state, caller, secret_id, ic_cdk::api::time()))
+}
+
+#[ic_cdk::update]
+pub fn approve_recovery_drill(id: u64) -> Result<(), String> {
+    let caller = ic_cdk::api::msg_caller();
+    with_state_mut(|state| record_drill_response(state, caller, id, None, ic_cdk::api::time()))
+}
+
+/// Submits the guardian's share of the drill's secret exactly as
+/// `get_guardian_share` returned it, checked against the commitment stored
+/// when the share was issued. It is reported as fetched, not decrypted. The
+/// share also counts as the guardian's approval and is not stored.
+#[ic_cdk::update]
+pub fn submit_drill_share(id: u64, share: Vec<u8>) -> Result<(), String> {
+    let caller = ic_cdk::api::msg_caller();
+    with_state_mut(|state| record_drill_response(state, caller, id, Some(&share), ic_cdk::api::time()))
+}
+
+#[ic_cdk::update]
+pub fn end_recovery_drill(id: u64) -> Result<DrillReport, String> {
+    let caller = ic_cdk::api::msg_caller();
+    with_state_mut(|state| {
+        let now = ic_cdk::api::time();
+        end_drill(state, caller, id, now)?;
+        drill_report(state, id, now)
+    })
+}
+
+#[ic_cdk::query]
+pub fn get_recovery_drill_report(id: u64) -> Result<DrillReport, String> {
+    let caller = ic_cdk::api::msg_caller();
+    with_state(|state| {
+        let g = state.guardian_state.as_ref().ok_or("guardian state not initialized")?;
+        if caller != g.owner && !g.guardians.contains(&caller) {
+            return Err("only owner or guardian may view drills".to_string());
+        }
+        drill_report(state, id, ic_cdk::api::time())
+    })
+}
+
+// Helper functions
+fn start_drill(state: &mut VaultStateV2, caller: Principal, secret_id: Vec<u8>, now: u64) -> Result<u64, String> {
+    let g = state.guardian_state.as_ref().ok_or("guardian state not initialized")?;
+    if g.owner != caller {
+        return Err("only owner can start a recovery drill".to_string());
+    }
+    if !state.recovery_secrets.contains_key(&secret_id) {
+        return Err("recovery secret not found".to_string());
+    }
+    if state.recovery_drills.iter().any(|d| is_running(d, now)) {
+        return Err("a recovery drill is already running".to_string());
+    }
+
+    let id = state.next_drill_id;
+    state.next_drill_id += 1;
+    state.recovery_drills.push(RecoveryDrill {
+        id,
+        secret_id,
+        started_at: now,
+        ends_at: now.saturating_add(DRILL_DURATION_NANOS),
+        responses: vec![],
+        quorum_reached_at: None,
+        status: DrillStatus::Running,
+    });
+    if state.recovery_drills.len() > MAX_DRILLS {
+        state.recovery_drills.remove(0);
+    }
+    notify_vault(state, NotificationKind::RecoveryDrillStarted { drill_id: id }, Some(caller), now);
+    Ok(id)
+}
+
+fn record_drill_response(
+    state: &mut VaultStateV2,
+    caller: Principal,
+    id: u64,
+    share: Option<&[u8]>,
+    now: u64,
+) -> Result<(), String> {
+    let g = state.guardian_state.clone().ok_or("guardian state not initialized")?;
+    if !g.guardians.contains(&caller) {
+        return Err("only guardian may respond to a drill".to_string());
+    }
+    let drill = state.recovery_drills.iter_mut()
+        .find(|d| d.id == id)
+        .ok_or("recovery drill not found")?;
+    if !is_running(drill, now) {
+        return Err("recovery drill has finished".to_string());
+    }
+    if let Some(share) = share {
+        let secret = state.recovery_secrets.get(&drill.secret_id).ok_or("recovery secret not found")?;
+        check_issued_share(secret, &caller, share)?;
+    }
+
+    let index = match drill.responses.iter().position(|r| r.guardian == caller) {
+        Some(index) => index,
+        None => {
+            drill.responses.push(DrillResponse { guardian: caller, approved_at: None, share_fetched_at: None });
+            drill.responses.len() - 1
+        }
+    };
+    let response = &mut drill.responses[index];
+    response.approved_at.get_or_insert(now);
+    if share.is_some() {
+        response.share_fetched_at.get_or_insert(now);
+    }
+    if drill.quorum_reached_at.is_none() && g.quorum_reached(drill.responses.iter().map(|r| &r.guardian)) {
+        drill.quorum_reached_at = Some(now);
+    }
+    Ok(())
+}
+
+fn end_drill(state: &mut VaultStateV2, caller: Principal, id: u64, now: u64) -> Result<(), String> {
+    let g = state.guardian_state.as_ref().ok_or("guardian state not initialized")?;
+    if g.owner != caller {
+        return Err("only owner can end a recovery drill".to_string());
+    }
+    let drill = state.recovery_drills.iter_mut()
+        .find(|d| d.id == id)
+        .ok_or("recovery drill not found")?;
+    if !is_running(drill, now) {
+        return Err("recovery drill has finished".to_string());
+    }
+    drill.status = DrillStatus::Finished { finished_at: now };
+    Ok(())
+}
+
+/// Drills past `ends_at` are reported as finished without a write.
+fn is_running(drill: &RecoveryDrill, now: u64) -> bool {
+    drill.status == DrillStatus::Running && now < drill.ends_at
+}
+
+fn drill_report(state: &VaultStateV2, id: u64, now: u64) -> Result<DrillReport, String> {
+    let g = state.guardian_state.as_ref().ok_or("guardian state not initialized")?;
+    let drill = state.recovery_drills.iter()
+        .find(|d| d.id == id)
+        .ok_or("recovery drill not found")?;
+    let status = match drill.status {
+        DrillStatus::Running if now >= drill.ends_at => DrillStatus::Finished { finished_at: drill.ends_at },
+        ref status => status.clone(),
+    };
+    let after = |at: Option<u64>| at.map(|at| at.saturating_sub(drill.started_at));
+    Ok(DrillReport {
+        drill_id: id,
+        status,
+        quorum_reached_after: after(drill.quorum_reached_at),
+        responded: drill.responses.iter()
+            .map(|r| DrillGuardianReport {
+                guardian: r.guardian,
+                approved_after: after(r.approved_at),
+                share_fetched_after: after(r.share_fetched_at),
+            })
+            .collect(),
+        silent: g.guardians.iter()
+            .filter(|p| !drill.responses.iter().any(|r| r.guardian == **p))
+            .copied()
+            .collect(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::GuardianState;
+    use crate::vetkd::{share_commitment, GuardianShare, RecoverySecret};
+    use std::collections::{BTreeMap, HashMap};
+
+    fn owner_principal() -> Principal {
+        Principal::from_text("rdmx6-jaaaa-aaaaa-aaadq-cai").unwrap()
+    }
+
+    fn guardian1_principal() -> Principal {
+        Principal::from_text("rrkah-fqaaa-aaaaa-aaaaq-cai").unwrap()
+    }
+
+    fn guardian2_principal() -> Principal {
+        Principal::from_text("ryjl3-tyaaa-aaaaa-aaaba-cai").unwrap()
+    }
+
+    fn guardian3_principal() -> Principal {
+        Principal::from_text("rno2w-sqaaa-aaaaa-aaacq-cai").unwrap()
+    }
+
+    fn issued_share(guardian: Principal) -> Vec<u8> {
+        guardian.as_slice().to_vec()
+    }
+
+    fn share_for(guardian: Principal) -> GuardianShare {
+        GuardianShare {
+            guardian,
+            commitment: share_commitment(&issued_share(guardian)),
+            encrypted_share: issued_share(guardian),
+            share_index: 0,
+            derivation_path: vec![],
+            epoch: 0,
+            weight: 1,
+        }
+    }
+
+    /// Three guardians with quorum 2; only the first two hold shares of the secret.
+    fn setup_test_state_with_secret() -> VaultStateV2 {
+        let mut state = VaultStateV2 {
+            guardian_state: Some(GuardianState {
+                guardians: vec![guardian1_principal(), guardian2_principal(), guardian3_principal()],
+                quorum: 2,
+                owner: owner_principal(),
+                weights: BTreeMap::new(),
+                categories: BTreeMap::new(),
+                policy: None,
+            }),
+            ..Default::default()
+        };
+        let guardian_shares: HashMap<Principal, GuardianShare> = [guardian1_principal(), guardian2_principal()]
+            .into_iter()
+            .map(|p| (p, share_for(p)))
+            .collect();
+        state.recovery_secrets.insert(b"secret".to_vec(), RecoverySecret {
+            secret_id: b"secret".to_vec(),
+            guardian_shares,
+            threshold: 2,
+            created_at: 0,
+            epoch: 0,
+            epoch_history: vec![],
+        });
+        state
+    }
+
+    #[test]
+    fn test_drill_reports_responders_without_changing_owner() {
+        let mut state = setup_test_state_with_secret();
+        let (g1, g2, g3) = (guardian1_principal(), guardian2_principal(), guardian3_principal());
+        assert!(start_drill(&mut state, g1, b"secret".to_vec(), 100).is_err());
+        assert!(start_drill(&mut state, owner_principal(), b"missing".to_vec(), 100).is_err());
+        let id = start_drill(&mut state, owner_principal(), b"secret".to_vec(), 100).unwrap();
+        assert!(start_drill(&mut state, owner_principal(), b"secret".to_vec(), 100).is_err());
+
+        record_drill_response(&mut state, g1, id, None, 150).unwrap();
+        record_drill_response(&mut state, g1, id, Some(&issued_share(g1)), 180).unwrap();
+        assert!(record_drill_response(&mut state, g3, id, Some(&issued_share(g3)), 190).is_err());
+        record_drill_response(&mut state, g2, id, Some(&issued_share(g2)), 300).unwrap();
+
+        let report = drill_report(&state, id, 400).unwrap();
+        assert_eq!(report.status, DrillStatus::Running);
+        assert_eq!(report.quorum_reached_after, Some(200));
+        assert_eq!(report.responded[0].approved_after, Some(50));
+        assert_eq!(report.responded[0].share_fetched_after, Some(80));
+        assert_eq!(report.responded[1].approved_after, Some(200));
+        assert_eq!(report.silent, vec![g3]);
+
+        end_drill(&mut state, owner_principal(), id, 500).unwrap();
+        assert!(record_drill_response(&mut state, g3, id, None, 600).is_err());
+        assert_eq!(state.guardian_state.as_ref().unwrap().owner, owner_principal());
+        assert!(state.recovery_reqs.is_empty());
+    }
+
+    #[test]
+    fn test_drill_rejects_a_wrong_share() {
+        let mut state = setup_test_state_with_secret();
+        let (g1, g2) = (guardian1_principal(), guardian2_principal());
+        let id = start_drill(&mut state, owner_principal(), b"secret".to_vec(), 0).unwrap();
+
+        // Another guardian's share, or garbage, does not count as a response
+        assert!(record_drill_response(&mut state, g1, id, Some(&issued_share(g2)), 1).is_err());
+        assert!(record_drill_response(&mut state, g1, id, Some(&[]), 1).is_err());
+        assert!(drill_report(&state, id, 2).unwrap().responded.is_empty());
+
+        record_drill_response(&mut state, g1, id, Some(&issued_share(g1)), 3).unwrap();
+        assert_eq!(drill_report(&state, id, 4).unwrap().responded[0].share_fetched_after, Some(3));
+    }
+
+    #[test]
+    fn test_drill_finishes_after_its_window() {
+        let mut state = setup_test_state_with_secret();
+        let id = start_drill(&mut state, owner_principal(), b"secret".to_vec(), 0).unwrap();
+        record_drill_response(&mut state, guardian1_principal(), id, None, 1).unwrap();
+
+        let report = drill_report(&state, id, DRILL_DURATION_NANOS).unwrap();
+        assert_eq!(report.status, DrillStatus::Finished { finished_at: DRILL_DURATION_NANOS });
+        assert_eq!(report.quorum_reached_after, None);
+        assert!(record_drill_response(&mut state, guardian2_principal(), id, None, DRILL_DURATION_NANOS).is_err());
+        assert!(start_drill(&mut state, owner_principal(), b"secret".to_vec(), DRILL_DURATION_NANOS).is_ok());
+    }
+}
@@ -1,8 +1,10 @@
 use candid::{CandidType, Deserialize, Principal};
-use ic_cdk::api::call::call;
-use crate::state::{with_state, update_state};
+use crate::canister_call::call;
+use crate::state::{with_state, update_state, VaultStateV2};
+use crate::ckbtc::default_subaccount;
+use crate::vault_id::vault_id_of;
+use crate::freeze::ensure_not_frozen;
 use sha2::{Sha256, Digest};
-use std::collections::HashMap;
 
 // ECDSA Management Canister Types
 #[derive(CandidType, Deserialize)]
@@ -75,7 +77,7 @@ pub async fn ecdsa_public_key(derivation_path: Vec<Vec<u8>>) -> Result<Vec<u8>,
     let cfg = with_state(|state| state.config.clone().ok_or("config not set"))?;
     
     let args = EcdsaPublicKeyArgs {
-        canister_id: Some(ic_cdk::id()),
+        canister_id: Some(ic_cdk::api::canister_self()),
         derivation_path,
         key_id: EcdsaKeyId { 
             curve: "secp256k1".to_string(), 
@@ -94,11 +96,11 @@ pub async fn ecdsa_public_key(derivation_path: Vec<Vec<u8>>) -> Result<Vec<u8>,
     Ok(res.public_key)
 }
 
-#[ic_cdk::update]
-pub async fn sign_with_ecdsa(
+async fn sign_with_ecdsa(
     message_hash: Vec<u8>, 
     derivation_path: Vec<Vec<u8>>
 ) -> Result<Vec<u8>, String> {
+    with_state(|state| ensure_not_frozen(state, ic_cdk::api::time()))?;
     let cfg = with_state(|state| state.config.clone().ok_or("config not set"))?;
     
     let args = SignWithEcdsaArgs { 
@@ -124,10 +126,10 @@ pub async fn sign_with_ecdsa(
 // Bitcoin Address Generation
 #[ic_cdk::update]
 pub async fn generate_bitcoin_address() -> Result<String, String> {
-    let caller = ic_cdk::caller();
+    let caller = ic_cdk::api::msg_caller();
     
-    // Create unique derivation path for this user
-    let derivation_path = create_user_derivation_path(&caller);
+    // Derivation path of the caller's vault
+    let derivation_path = with_state(|state| create_user_derivation_path(&vault_id_of(state, &caller)));
     
     // Get the public key from threshold ECDSA
     let public_key = ecdsa_public_key(derivation_path.clone()).await?;
@@ -145,13 +147,13 @@ pub async fn generate_bitcoin_address() -> Result<String, String> {
 
 #[ic_cdk::query]
 pub fn get_bitcoin_address() -> Option<String> {
-    let caller = ic_cdk::caller();
+    let caller = ic_cdk::api::msg_caller();
     with_state(|state| state.btc_addresses.get(&caller).cloned())
 }
 
 #[ic_cdk::update]
 pub async fn get_or_create_bitcoin_address() -> Result<String, String> {
-    let caller = ic_cdk::caller();
+    let caller = ic_cdk::api::msg_caller();
     
     // Check if address already exists
     if let Some(existing_address) = with_state(|state| state.btc_addresses.get(&caller).cloned()) {
@@ -168,13 +170,10 @@ pub async fn sign_bitcoin_transaction(
     transaction: BitcoinTransaction,
     input_index: u32
 ) -> Result<Vec<u8>, String> {
-    let caller = ic_cdk::caller();
-    
-    // Verify caller owns this transaction (basic check)
-    // In production, implement proper authorization
+    let caller = ic_cdk::api::msg_caller();
     
-    // Create derivation path for the caller
-    let derivation_path = create_user_derivation_path(&caller);
+    // Only the owner signs, and only with the vault's key
+    let derivation_path = with_state(|state| owner_derivation_path(state, &caller))?;
     
     // Serialize transaction for signing
     let tx_hash = create_transaction_hash(&transaction, input_index)?;
@@ -188,10 +187,10 @@ pub async fn sign_bitcoin_transaction(
 // Wallet Management
 #[ic_cdk::update]
 pub async fn derive_child_key(child_index: u32) -> Result<Vec<u8>, String> {
-    let caller = ic_cdk::caller();
+    let caller = ic_cdk::api::msg_caller();
     
     // Create child derivation path
-    let mut derivation_path = create_user_derivation_path(&caller);
+    let mut derivation_path = with_state(|state| create_user_derivation_path(&vault_id_of(state, &caller)));
     derivation_path.push(child_index.to_be_bytes().to_vec());
     
     // Get child public key
@@ -202,17 +201,19 @@ pub async fn derive_child_key(child_index: u32) -> Result<Vec<u8>, String> {
 
 #[ic_cdk::query]
 pub fn get_wallet_info() -> Result<WalletInfo, String> {
-    let caller = ic_cdk::caller();
+    let caller = ic_cdk::api::msg_caller();
     
     with_state(|state| {
         let btc_address = state.btc_addresses.get(&caller).cloned();
-        let subaccount = state.subaccounts.get(&caller).cloned();
+        let subaccount = default_subaccount(state, &caller);
+        let vault_id = vault_id_of(state, &caller);
         
         Ok(WalletInfo {
             owner: caller,
             bitcoin_address: btc_address,
             subaccount,
-            derivation_path: create_user_derivation_path(&caller),
+            derivation_path: create_user_derivation_path(&vault_id),
+            vault_id,
         })
     })
 }
@@ -223,13 +224,22 @@ pub struct WalletInfo {
     pub bitcoin_address: Option<String>,
     pub subaccount: Option<Vec<u8>>,
     pub derivation_path: Vec<Vec<u8>>,
+    pub vault_id: Vec<u8>,
 }
 
 // Helper Functions
-fn create_user_derivation_path(user: &Principal) -> Vec<Vec<u8>> {
+fn owner_derivation_path(state: &VaultStateV2, caller: &Principal) -> Result<Vec<Vec<u8>>, String> {
+    let g = state.guardian_state.as_ref().ok_or("guardian state not initialized")?;
+    if g.owner != *caller {
+        return Err("only owner can sign bitcoin transactions".to_string());
+    }
+    Ok(create_user_derivation_path(&vault_id_of(state, caller)))
+}
+
+fn create_user_derivation_path(vault_id: &[u8]) -> Vec<Vec<u8>> {
     vec![
         b"guardian_vault".to_vec(),
-        user.as_slice().to_vec(),
+        vault_id.to_vec(),
     ]
 }
 
@@ -267,25 +277,25 @@ fn create_transaction_hash(
     let mut hasher = Sha256::new();
     
     // Hash transaction data
-    hasher.update(&input_index.to_be_bytes());
+    hasher.update(input_index.to_be_bytes());
     
     for input in &transaction.inputs {
         hasher.update(&input.previous_output.txid);
-        hasher.update(&input.previous_output.vout.to_be_bytes());
-        hasher.update(&input.sequence.to_be_bytes());
+        hasher.update(input.previous_output.vout.to_be_bytes());
+        hasher.update(input.sequence.to_be_bytes());
     }
     
     for output in &transaction.outputs {
-        hasher.update(&output.value.to_be_bytes());
+        hasher.update(output.value.to_be_bytes());
         hasher.update(&output.script_pubkey);
     }
     
-    hasher.update(&transaction.fee.to_be_bytes());
+    hasher.update(transaction.fee.to_be_bytes());
     
     // Double SHA256 as per Bitcoin protocol
     let first_hash = hasher.finalize();
     let mut second_hasher = Sha256::new();
-    second_hasher.update(&first_hash);
+    second_hasher.update(first_hash);
     let final_hash = second_hasher.finalize();
     
     Ok(final_hash.to_vec())
@@ -306,13 +316,36 @@ mod tests {
     #[test]
     fn test_derivation_path_creation() {
         let user = Principal::from_text("rdmx6-jaaaa-aaaaa-aaadq-cai").unwrap();
-        let path = create_user_derivation_path(&user);
+        let path = create_user_derivation_path(user.as_slice());
         
         assert_eq!(path.len(), 2);
         assert_eq!(path[0], b"guardian_vault".to_vec());
         assert_eq!(path[1], user.as_slice().to_vec());
     }
     
+    #[test]
+    fn test_only_owner_signs_with_the_vault_key() {
+        let owner = Principal::from_text("rdmx6-jaaaa-aaaaa-aaadq-cai").unwrap();
+        let guardian = Principal::from_text("rrkah-fqaaa-aaaaa-aaaaq-cai").unwrap();
+        let mut state = VaultStateV2 {
+            guardian_state: Some(crate::types::GuardianState {
+                guardians: vec![guardian],
+                quorum: 1,
+                owner,
+                weights: Default::default(),
+                categories: Default::default(),
+                policy: None,
+            }),
+            ..Default::default()
+        };
+        assert!(owner_derivation_path(&state, &guardian).is_err());
+
+        // A recovered owner signs with the vault's original key
+        let vault_id = vec![9; 32];
+        state.vault_ids.insert(owner, vault_id.clone());
+        assert_eq!(owner_derivation_path(&state, &owner).unwrap(), create_user_derivation_path(&vault_id));
+    }
+    
     #[test]
     fn test_public_key_to_address() {
         // Test with compressed public key (33 bytes)
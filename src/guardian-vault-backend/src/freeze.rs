@@ -0,0 +1,209 @@
+use candid::{CandidType, Deserialize, Principal};
+use serde::Serialize;
+use crate::notifications::{notify_vault, NotificationKind};
+use crate::proposals::{open_proposal, ProposalAction};
+use crate::state::{with_state, with_state_mut, VaultStateV2};
+
+/// How long one freeze blocks outgoing funds unless it is lifted earlier.
+const FREEZE_DURATION_NANOS: u64 = 72 * 60 * 60 * 1_000_000_000;
+/// A guardian may freeze the vault at most once per this period, so a single
+/// guardian cannot keep the vault frozen indefinitely.
+const FREEZE_COOLDOWN_NANOS: u64 = 30 * 24 * 60 * 60 * 1_000_000_000;
+const MAX_FREEZE_REASON_LEN: usize = 256;
+
+// Any guardian who suspects the owner is being scammed can freeze the vault.
+// While frozen, every ledger transfer and minter withdrawal is refused where it
+// is executed, so `ckbtc_transfer`, `retrieve_btc*`, approved transfer
+// proposals, pocket moves and bond payouts all wait, as does tECDSA signing.
+// Bond payouts stay held and can be retried once the freeze ends. The freeze
+// lifts by itself after `FREEZE_DURATION_NANOS`; lifting it earlier needs the
+// owner's proposal and a guardian quorum, so a phished owner session alone
+// cannot undo it. The proposal names the freeze it lifts; once another guardian
+// extends the freeze, the guardians have to vote again.
+
+#[derive(Clone, Debug, CandidType, Deserialize, Serialize)]
+pub enum FreezeEvent {
+    Frozen { guardian: Principal, reason: String, frozen_until: u64 },
+    Unfrozen { proposal_id: u64 },
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize, Serialize)]
+pub struct FreezeLogEntry {
+    pub at: u64,
+    pub event: FreezeEvent,
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct FreezeStatus {
+    pub frozen_until: Option<u64>, // None when outgoing funds are not blocked
+    pub log: Vec<FreezeLogEntry>,
+}
+
+/// Blocks outgoing funds for `FREEZE_DURATION_NANOS`. Freezing an already
+/// frozen vault extends the freeze. Returns when the freeze ends.
+#[ic_cdk::update]
+pub fn freeze_vault(reason: String) -> Result<u64, String> {
+    let caller = ic_cdk::api::msg_caller();
+    with_state_mut(|state| freeze(state, caller, reason, ic_cdk::api::time()))
+}
+
+/// Proposes lifting the freeze. The owner proposes and guardians approve it
+/// like any other proposal. Returns the proposal id.
+#[ic_cdk::update]
+pub fn unfreeze_vault() -> Result<u64, String> {
+    let caller = ic_cdk::api::msg_caller();
+    with_state_mut(|state| {
+        let now = ic_cdk::api::time();
+        let g = state.guardian_state.as_ref().ok_or("guardian state not initialized")?;
+        if g.owner != caller {
+            return Err("only owner can propose lifting a freeze".to_string());
+        }
+        if !is_frozen(state, now) {
+            return Err("vault is not frozen".to_string());
+        }
+        let frozen_until = state.frozen_until;
+        open_proposal(state, caller, ProposalAction::UnfreezeVault { frozen_until }, now)
+    })
+}
+
+#[ic_cdk::query]
+pub fn get_freeze_status() -> Result<FreezeStatus, String> {
+    let caller = ic_cdk::api::msg_caller();
+    with_state(|state| {
+        let g = state.guardian_state.as_ref().ok_or("guardian state not initialized")?;
+        if caller != g.owner && !g.guardians.contains(&caller) {
+            return Err("only owner or guardian may view the freeze status".to_string());
+        }
+        let now = ic_cdk::api::time();
+        Ok(FreezeStatus {
+            frozen_until: is_frozen(state, now).then_some(state.frozen_until),
+            log: state.freeze_log.clone(),
+        })
+    })
+}
+
+// Enforcement
+pub(crate) fn ensure_not_frozen(state: &VaultStateV2, now: u64) -> Result<(), String> {
+    if is_frozen(state, now) {
+        Err("vault is frozen by a guardian".to_string())
+    } else {
+        Ok(())
+    }
+}
+
+/// Runs an approved `UnfreezeVault` proposal for the freeze ending at
+/// `frozen_until`. A freeze extended since the proposal opened stays in place.
+pub(crate) fn lift_freeze(state: &mut VaultStateV2, proposal_id: u64, frozen_until: u64, now: u64) -> Result<(), String> {
+    if state.frozen_until != frozen_until {
+        return Err("the freeze was extended after this proposal opened".to_string());
+    }
+    state.frozen_until = 0;
+    state.freeze_log.push(FreezeLogEntry { at: now, event: FreezeEvent::Unfrozen { proposal_id } });
+    Ok(())
+}
+
+// Helper functions
+fn is_frozen(state: &VaultStateV2, now: u64) -> bool {
+    now < state.frozen_until
+}
+
+fn freeze(state: &mut VaultStateV2, caller: Principal, reason: String, now: u64) -> Result<u64, String> {
+    let g = state.guardian_state.as_ref().ok_or("guardian state not initialized")?;
+    if !g.guardians.contains(&caller) {
+        return Err("only guardian may freeze the vault".to_string());
+    }
+    let reason = reason.trim().to_string();
+    if reason.is_empty() || reason.len() > MAX_FREEZE_REASON_LEN {
+        return Err(format!("freeze reason must be 1 to {} bytes", MAX_FREEZE_REASON_LEN));
+    }
+    let last_freeze = state.freeze_log.iter().rev().find_map(|e| match &e.event {
+        FreezeEvent::Frozen { guardian, .. } if *guardian == caller => Some(e.at),
+        _ => None,
+    });
+    if last_freeze.is_some_and(|at| now < at.saturating_add(FREEZE_COOLDOWN_NANOS)) {
+        return Err("guardian froze the vault too recently".to_string());
+    }
+
+    let frozen_until = state.frozen_until.max(now.saturating_add(FREEZE_DURATION_NANOS));
+    state.frozen_until = frozen_until;
+    state.freeze_log.push(FreezeLogEntry {
+        at: now,
+        event: FreezeEvent::Frozen { guardian: caller, reason, frozen_until },
+    });
+    notify_vault(state, NotificationKind::VaultFrozen { guardian: caller, frozen_until }, Some(caller), now);
+    Ok(frozen_until)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::GuardianState;
+    use std::collections::BTreeMap;
+
+    fn owner_principal() -> Principal {
+        Principal::from_text("rdmx6-jaaaa-aaaaa-aaadq-cai").unwrap()
+    }
+
+    fn guardian1_principal() -> Principal {
+        Principal::from_text("rrkah-fqaaa-aaaaa-aaaaq-cai").unwrap()
+    }
+
+    fn guardian2_principal() -> Principal {
+        Principal::from_text("ryjl3-tyaaa-aaaaa-aaaba-cai").unwrap()
+    }
+
+    fn setup_test_state_with_guardians() -> VaultStateV2 {
+        VaultStateV2 {
+            guardian_state: Some(GuardianState {
+                guardians: vec![guardian1_principal(), guardian2_principal()],
+                quorum: 2,
+                owner: owner_principal(),
+                weights: BTreeMap::new(),
+                categories: BTreeMap::new(),
+                policy: None,
+            }),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_freeze_is_bounded_and_rate_limited() {
+        let mut state = setup_test_state_with_guardians();
+        let (g1, g2) = (guardian1_principal(), guardian2_principal());
+        assert!(freeze(&mut state, owner_principal(), "scam".to_string(), 0).is_err());
+        assert!(freeze(&mut state, g1, " ".to_string(), 0).is_err());
+
+        assert_eq!(freeze(&mut state, g1, "owner is on a call with a scammer".to_string(), 0).unwrap(), FREEZE_DURATION_NANOS);
+        assert!(ensure_not_frozen(&state, FREEZE_DURATION_NANOS - 1).is_err());
+        assert!(ensure_not_frozen(&state, FREEZE_DURATION_NANOS).is_ok());
+
+        // The same guardian must wait out the cooldown; another guardian may extend
+        assert!(freeze(&mut state, g1, "again".to_string(), FREEZE_DURATION_NANOS).is_err());
+        assert_eq!(freeze(&mut state, g2, "still suspicious".to_string(), 10).unwrap(), 10 + FREEZE_DURATION_NANOS);
+        assert!(freeze(&mut state, g1, "again".to_string(), FREEZE_COOLDOWN_NANOS).is_ok());
+        assert_eq!(state.freeze_log.len(), 3);
+        assert_eq!(state.notifications[&owner_principal()].len(), 3);
+    }
+
+    #[test]
+    fn test_lifting_a_freeze_is_logged() {
+        let mut state = setup_test_state_with_guardians();
+        let frozen_until = freeze(&mut state, guardian1_principal(), "phishing".to_string(), 0).unwrap();
+        lift_freeze(&mut state, 7, frozen_until, 5).unwrap();
+
+        assert!(ensure_not_frozen(&state, 5).is_ok());
+        assert!(matches!(state.freeze_log.last().unwrap().event, FreezeEvent::Unfrozen { proposal_id: 7 }));
+    }
+
+    #[test]
+    fn test_unfreeze_does_not_lift_a_later_freeze() {
+        let mut state = setup_test_state_with_guardians();
+        let first = freeze(&mut state, guardian1_principal(), "phishing".to_string(), 0).unwrap();
+        let second = freeze(&mut state, guardian2_principal(), "still phishing".to_string(), 10).unwrap();
+
+        let result = lift_freeze(&mut state, 7, first, 20);
+        assert_eq!(result, Err("the freeze was extended after this proposal opened".to_string()));
+        assert_eq!(state.frozen_until, second);
+        assert!(ensure_not_frozen(&state, 20).is_err());
+    }
+}
@@ -1,51 +1,420 @@
-use candid::Principal;
-use crate::state::{with_state, update_state};
-use crate::types::GuardianState;
+use candid::{CandidType, Deserialize, Principal};
+use crate::canister_call::call;
+use crate::state::{with_state, with_state_mut, update_state, VaultStateV2};
+use crate::types::{
+    GuardianCategories, GuardianInvitation, GuardianPolicy, GuardianProfile, GuardianSelector, GuardianState,
+    GuardianSummary, GuardianWeight, InvitationCode, MembershipProof, RecoveryStatus, VaultHealth,
+};
+use crate::proposals::ProposalStatus;
+use crate::notifications::{notify_vault, NotificationKind};
+use crate::vetkd::{reshare_stale_secrets, revoke_guardian_shares};
+use serde::Serialize;
+use sha2::{Sha256, Digest};
+use std::collections::BTreeMap;
+use std::time::Duration;
 
+/// How long an invited guardian has to accept before the invitation lapses.
+const INVITATION_TTL_NANOS: u64 = 7 * 24 * 60 * 60 * 1_000_000_000;
+
+/// VetKD transport public keys are compressed BLS12-381 G1 points.
+const TRANSPORT_KEY_LEN: usize = 48;
+
+/// How long a queued guardian change waits, giving guardians time to veto it.
+const GUARDIAN_CHANGE_DELAY_NANOS: u64 = 72 * 60 * 60 * 1_000_000_000;
+/// A guardian removal that has not gathered its approvals by then lapses.
+const REMOVAL_TTL_NANOS: u64 = 14 * 24 * 60 * 60 * 1_000_000_000;
+
+const MAX_POLICY_DEPTH: usize = 4;
+const MAX_NICKNAME_LEN: usize = 64;
+const MAX_CALLBACK_METHOD_LEN: usize = 64;
+
+const MEMBERSHIP_SALT_DOMAIN: &[u8] = b"guardian_vault_membership_salt_v1";
+const COMMITMENT_DOMAIN: &[u8] = b"guardian_vault_commitment_v1";
+
+/// How often the attestation timer looks for guardians that are due to check in.
+const ATTESTATION_TIMER_PERIOD: Duration = Duration::from_secs(24 * 60 * 60);
+/// Guardians silent for this long are asked to send a heartbeat.
+const ATTESTATION_INTERVAL_NANOS: u64 = 30 * 24 * 60 * 60 * 1_000_000_000;
+/// Guardians silent for this long no longer count as live.
+const LIVENESS_WINDOW_NANOS: u64 = 90 * 24 * 60 * 60 * 1_000_000_000;
+const MAX_CATEGORIES_PER_GUARDIAN: usize = 8;
+const MAX_CATEGORY_LEN: usize = 32;
+
+// Guardian Change Types
+#[derive(Clone, Debug, CandidType, Deserialize, Serialize)]
+pub enum GuardianChangeKind {
+    SetGuardians {
+        guardians: Vec<Principal>,
+        quorum: u8,
+        weights: BTreeMap<Principal, u8>,
+        invitation_hashes: BTreeMap<Principal, Vec<u8>>, // codes were handed out when queued
+    },
+    SetPolicy {
+        policy: Option<GuardianPolicy>,
+        categories: Vec<GuardianCategories>,
+    },
+    /// Proposed by a guardian and approved by the others rather than delayed.
+    RemoveGuardian {
+        guardian: Principal,
+        replacement: Option<Principal>,
+        invitation_hash: Option<Vec<u8>>,
+    },
+}
+
+#[derive(Clone, Debug, PartialEq, CandidType, Deserialize, Serialize)]
+pub enum GuardianChangeStatus {
+    Pending,
+    Executed,
+    Vetoed,
+    Cancelled,
+    Expired,
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize, Serialize)]
+pub struct GuardianChange {
+    pub id: u64,
+    pub requested_by: Principal,
+    pub kind: GuardianChangeKind,
+    pub approvals: Vec<Principal>,
+    pub vetoes: Vec<Principal>,
+    pub status: GuardianChangeStatus,
+    pub requested_at: u64,
+    pub executes_at: u64,
+    pub executed_at: Option<u64>,
+    pub expires_at: Option<u64>, // only removals lapse; queued changes wait for execution
+}
+
+#[derive(Clone, Debug, PartialEq, CandidType, Deserialize, Serialize)]
+pub enum GuardianRole {
+    Invited,
+    Active,
+}
+
+/// A vault the caller guards and the items currently waiting on them.
+#[derive(Clone, Debug, CandidType, Deserialize, Serialize)]
+pub struct Guardianship {
+    pub vault: Principal,
+    pub owner: Principal,
+    pub role: GuardianRole,
+    pub pending_recoveries: Vec<u64>,
+    pub pending_proposals: Vec<u64>,
+    pub pending_guardian_changes: Vec<u64>,
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize, Serialize)]
+pub enum GuardianSetOutcome {
+    Applied { invitations: Vec<InvitationCode> },
+    Queued { change_id: u64, executes_at: u64, invitations: Vec<InvitationCode> },
+}
+
+/// Replaces the guardian set. Principals that are not yet guardians receive an
+/// invitation and only count toward quorum once they accept it. The returned
+/// one-time codes must be passed to each invitee out of band.
+///
+/// `quorum` is the approval weight required; guardians without an entry in
+/// `weights` weigh 1.
+///
+/// Once the vault has accepted guardians the change is queued instead, and
+/// only takes effect through `execute_guardian_change` after the delay unless
+/// the current guardians veto it. Invitation codes are issued up front but
+/// can only be redeemed after execution.
 #[ic_cdk::update]
-pub fn set_guardians(guardians: Vec<Principal>, quorum: u8) -> Result<(), String> {
-    let caller = ic_cdk::caller();
-    
-    update_state(|state| {
-        // Initialize guardian state if it doesn't exist
-        if state.guardian_state.is_none() {
-            state.guardian_state = Some(GuardianState {
-                guardians: Vec::new(),
-                quorum: 0,
-                owner: caller,
-            });
+pub async fn set_guardians(
+    guardians: Vec<Principal>,
+    quorum: u8,
+    weights: Option<Vec<GuardianWeight>>,
+) -> Result<GuardianSetOutcome, String> {
+    let caller = ic_cdk::api::msg_caller();
+
+    let weights = weight_map(&guardians, weights.unwrap_or_default())?;
+    with_state(|state| validate_guardian_set(state, caller, &guardians, quorum, &weights))?;
+
+    let (seed,): (Vec<u8>,) = call(Principal::management_canister(), "raw_rand", ())
+        .await
+        .map_err(|e| format!("raw_rand failed: {:?}", e))?;
+
+    let outcome = with_state_mut(|state| -> Result<GuardianSetOutcome, String> {
+        let now = ic_cdk::api::time();
+        if !has_accepted_guardians(state) {
+            let invitations = apply_guardian_set(state, caller, guardians, quorum, weights, &seed, now)?;
+            return Ok(GuardianSetOutcome::Applied { invitations });
         }
-        
-        let g = state.guardian_state.as_mut().unwrap();
-        if g.owner != caller { 
-            return;
+        validate_guardian_set(state, caller, &guardians, quorum, &weights)?;
+        let invitations = invitation_codes(state, &guardians, &seed);
+        let invitation_hashes = code_hashes(&invitations);
+        let kind = GuardianChangeKind::SetGuardians { guardians, quorum, weights, invitation_hashes };
+        let (change_id, executes_at) = queue_guardian_change(state, caller, kind, now)?;
+        Ok(GuardianSetOutcome::Queued { change_id, executes_at, invitations })
+    })?;
+
+    if matches!(outcome, GuardianSetOutcome::Applied { .. }) {
+        reshare_after_guardian_change().await;
+    }
+    Ok(outcome)
+}
+
+/// Tags guardians with categories and replaces the approval rule. With
+/// `policy` set to `None` the vault falls back to the plain `quorum`. Pending
+/// invitees may be tagged and named in the policy ahead of accepting.
+///
+/// Queued like `set_guardians` once the vault has accepted guardians; returns
+/// the change id when pending.
+#[ic_cdk::update]
+pub fn set_guardian_policy(
+    policy: Option<GuardianPolicy>,
+    categories: Vec<GuardianCategories>,
+) -> Result<Option<u64>, String> {
+    let caller = ic_cdk::api::msg_caller();
+    with_state_mut(|state| {
+        if !has_accepted_guardians(state) {
+            apply_guardian_policy(state, caller, policy, categories)?;
+            return Ok(None);
         }
-        if guardians.is_empty() || quorum == 0 || (quorum as usize) > guardians.len() {
-            return;
+        checked_guardian_policy(state, caller, policy.clone(), categories.clone())?;
+        let kind = GuardianChangeKind::SetPolicy { policy, categories };
+        queue_guardian_change(state, caller, kind, ic_cdk::api::time()).map(|(id, _)| Some(id))
+    })
+}
+
+/// Applies a queued change once its delay has passed. Callable by the owner
+/// or any guardian.
+#[ic_cdk::update]
+pub async fn execute_guardian_change(id: u64) -> Result<GuardianChangeStatus, String> {
+    let caller = ic_cdk::api::msg_caller();
+    let (status, reshare) = with_state_mut(|state| run_guardian_change(state, caller, id, ic_cdk::api::time()))?;
+    if reshare {
+        reshare_after_guardian_change().await;
+    }
+    Ok(status)
+}
+
+/// Lets a guardian propose removing another guardian, e.g. one whose identity
+/// is compromised or who has died, optionally inviting a replacement with the
+/// same weight and categories. The remaining guardians approve it under the
+/// rules the vault will have without that guardian; their approval alone
+/// applies it. The target's own approval never counts. A removal lapses if
+/// it is not approved within `REMOVAL_TTL_NANOS`, and never holds up the
+/// owner's queued changes.
+#[ic_cdk::update]
+pub async fn propose_guardian_removal(
+    guardian: Principal,
+    replacement: Option<Principal>,
+) -> Result<GuardianSetOutcome, String> {
+    let caller = ic_cdk::api::msg_caller();
+
+    let invitations = match replacement {
+        Some(replacement) => {
+            let (seed,): (Vec<u8>,) = call(Principal::management_canister(), "raw_rand", ())
+                .await
+                .map_err(|e| format!("raw_rand failed: {:?}", e))?;
+            vec![InvitationCode { guardian: replacement, code: invitation_code(&seed, &replacement) }]
         }
-        g.guardians = guardians;
-        g.quorum = quorum;
+        None => Vec::new(),
+    };
+    let invitation_hash = code_hashes(&invitations).into_values().next();
+
+    let (change_id, status) = with_state_mut(|state| {
+        propose_removal(state, caller, guardian, replacement, invitation_hash, ic_cdk::api::time())
+    })?;
+    if status == GuardianChangeStatus::Executed {
+        reshare_after_guardian_change().await;
+        return Ok(GuardianSetOutcome::Applied { invitations });
+    }
+    let executes_at = with_state(|state| {
+        state.guardian_changes.iter().find(|c| c.id == change_id).map(|c| c.executes_at).unwrap_or_default()
     });
-    
-    // Validate after state update
+    Ok(GuardianSetOutcome::Queued { change_id, executes_at, invitations })
+}
+
+#[ic_cdk::update]
+pub async fn approve_guardian_removal(id: u64) -> Result<GuardianChangeStatus, String> {
+    let caller = ic_cdk::api::msg_caller();
+    let status = with_state_mut(|state| record_removal_approval(state, caller, id, ic_cdk::api::time()))?;
+    if status == GuardianChangeStatus::Executed {
+        reshare_after_guardian_change().await;
+    }
+    Ok(status)
+}
+
+/// Votes to block a queued change; it is dropped once the vetoes reach quorum.
+#[ic_cdk::update]
+pub fn veto_guardian_change(id: u64) -> Result<GuardianChangeStatus, String> {
+    let caller = ic_cdk::api::msg_caller();
+    with_state_mut(|state| record_veto(state, caller, id))
+}
+
+#[ic_cdk::update]
+pub fn cancel_guardian_change(id: u64) -> Result<(), String> {
+    let caller = ic_cdk::api::msg_caller();
+    with_state_mut(|state| {
+        let owner = state.guardian_state.as_ref().ok_or("guardian state not initialized")?.owner;
+        let change = pending_change_mut(state, id)?;
+        if caller != owner && caller != change.requested_by {
+            return Err("only owner or proposer can cancel guardian changes".to_string());
+        }
+        change.status = GuardianChangeStatus::Cancelled;
+        Ok(())
+    })
+}
+
+#[ic_cdk::query]
+pub fn get_guardian_changes() -> Result<Vec<GuardianChange>, String> {
+    let caller = ic_cdk::api::msg_caller();
+    with_state(|state| {
+        let g = state.guardian_state.as_ref().ok_or("guardian state not initialized")?;
+        if caller != g.owner && !g.guardians.contains(&caller) {
+            return Err("only owner or guardian may view guardian changes".to_string());
+        }
+        Ok(state.guardian_changes.clone())
+    })
+}
+
+#[ic_cdk::update]
+pub async fn accept_guardianship(code: String, transport_public_key: Vec<u8>) -> Result<(), String> {
+    let caller = ic_cdk::api::msg_caller();
+    with_state_mut(|state| accept_invitation(state, caller, &code, transport_public_key, ic_cdk::api::time()))?;
+    reshare_after_guardian_change().await;
+    Ok(())
+}
+
+#[ic_cdk::update]
+pub fn decline_guardianship() -> Result<(), String> {
+    let caller = ic_cdk::api::msg_caller();
+    with_state_mut(|state| {
+        let before = state.guardian_invitations.len();
+        state.guardian_invitations.retain(|i| i.guardian != caller);
+        if state.guardian_invitations.len() == before {
+            return Err("no pending invitation".to_string());
+        }
+        sync_guardian_index(state);
+        Ok(())
+    })
+}
+
+/// Pending invitations: all of them for the owner, only their own for an invitee.
+#[ic_cdk::query]
+pub fn get_guardian_invitations() -> Vec<GuardianInvitation> {
+    let caller = ic_cdk::api::msg_caller();
     with_state(|state| {
+        let is_owner = state.guardian_state.as_ref().map(|g| g.owner == caller).unwrap_or(false);
+        state.guardian_invitations.iter()
+            .filter(|i| is_owner || i.guardian == caller)
+            .cloned()
+            .collect()
+    })
+}
+
+/// Lets a guardian prove they still control their identity, answering any
+/// outstanding attestation request.
+#[ic_cdk::update]
+pub fn guardian_heartbeat() -> Result<(), String> {
+    let caller = ic_cdk::api::msg_caller();
+    with_state_mut(|state| record_heartbeat(state, caller, ic_cdk::api::time()))
+}
+
+#[ic_cdk::update]
+pub fn set_guardian_nickname(guardian: Principal, nickname: Option<String>) -> Result<(), String> {
+    let caller = ic_cdk::api::msg_caller();
+    let nickname = nickname.map(|n| n.trim().to_string()).filter(|n| !n.is_empty());
+    if nickname.as_ref().is_some_and(|n| n.len() > MAX_NICKNAME_LEN) {
+        return Err(format!("nickname must be at most {} bytes", MAX_NICKNAME_LEN));
+    }
+    with_state_mut(|state| {
         let g = state.guardian_state.as_ref().ok_or("guardian state not initialized")?;
-        if g.owner != caller { return Err("only owner can set guardians".into()); }
-        if g.guardians.is_empty() || g.quorum == 0 || (g.quorum as usize) > g.guardians.len() {
-            return Err("invalid quorum/guardians".into());
+        if g.owner != caller {
+            return Err("only owner can name guardians".to_string());
         }
+        let profile = state.guardian_profiles.get_mut(&guardian).ok_or("guardian not found")?;
+        profile.nickname = nickname;
         Ok(())
     })
 }
 
+/// Lets a canister guardian (an SNS DAO, treasury or custodian canister)
+/// register a method the vault notifies with a `RecoveryVoteRequest` whenever
+/// a recovery is opened. Notifications are one-way and best effort.
+#[ic_cdk::update]
+pub fn set_guardian_callback(method: Option<String>) -> Result<(), String> {
+    let caller = ic_cdk::api::msg_caller();
+    with_state_mut(|state| register_callback(state, caller, method))
+}
+
+#[ic_cdk::query]
+/// All profiles for the owner; a guardian only sees their own.
+pub fn get_guardian_profiles() -> Result<Vec<GuardianProfile>, String> {
+    let caller = ic_cdk::api::msg_caller();
+    with_state(|state| {
+        let g = state.guardian_state.as_ref().ok_or("guardian state not initialized")?;
+        if caller != g.owner && !g.guardians.contains(&caller) {
+            return Err("only owner or guardian may view guardian profiles".to_string());
+        }
+        Ok(state.guardian_profiles.values()
+            .filter(|p| caller == g.owner || p.guardian == caller)
+            .cloned()
+            .collect())
+    })
+}
+
+/// Reports guardians that have not checked in recently and warns when the
+/// live ones could no longer approve a recovery.
+#[ic_cdk::query]
+pub fn get_vault_health() -> Result<VaultHealth, String> {
+    let caller = ic_cdk::api::msg_caller();
+    with_state(|state| {
+        let g = state.guardian_state.as_ref().ok_or("guardian state not initialized")?;
+        if caller != g.owner && !g.guardians.contains(&caller) {
+            return Err("only owner or guardian may view vault health".to_string());
+        }
+        let mut health = vault_health(state, g, ic_cdk::api::time());
+        if caller != g.owner {
+            health.stale_guardians.clear();
+        }
+        Ok(health)
+    })
+}
+
+/// Arms the periodic attestation check. Timers do not survive upgrades, so
+/// this runs from both `init` and `post_upgrade`.
+pub fn start_attestation_timer() {
+    ic_cdk_timers::set_timer_interval(ATTESTATION_TIMER_PERIOD, || {
+        let requested = with_state_mut(|state| request_attestations(state, ic_cdk::api::time()));
+        if requested > 0 {
+            ic_cdk::println!("Requested attestation from {} guardians", requested);
+        }
+    });
+}
+
+/// Vaults the caller guards or has been invited to guard, with the recovery
+/// requests, transfer proposals and guardian changes awaiting their vote.
+/// A canister holds a single vault, so this lists at most one.
 #[ic_cdk::query]
-pub fn get_guardians() -> Option<GuardianState> { 
-    with_state(|state| state.guardian_state.clone())
+pub fn my_guardianships() -> Vec<Guardianship> {
+    let caller = ic_cdk::api::msg_caller();
+    with_state(|state| guardianships_of(state, caller, ic_cdk::api::canister_self(), ic_cdk::api::time()))
+}
+
+/// The full guardian set, readable only by the owner. Everyone else gets
+/// `get_guardian_summary`.
+#[ic_cdk::query]
+pub fn get_guardians() -> Result<GuardianState, String> {
+    let caller = ic_cdk::api::msg_caller();
+    with_state(|state| {
+        let g = state.guardian_state.as_ref().ok_or("guardian state not initialized")?;
+        if g.owner != caller {
+            return Err("only owner can list guardians".to_string());
+        }
+        Ok(g.clone())
+    })
+}
+
+#[ic_cdk::query]
+pub fn get_guardian_summary() -> Option<GuardianSummary> {
+    with_state(guardian_summary)
 }
 
 #[ic_cdk::update]
 pub fn initialize_guardians(owner: Principal) -> Result<(), String> {
-    let _caller = ic_cdk::caller();
+    let _caller = ic_cdk::api::msg_caller();
     
     with_state(|state| {
         if state.guardian_state.is_some() {
@@ -59,18 +428,713 @@ pub fn initialize_guardians(owner: Principal) -> Result<(), String> {
             guardians: Vec::new(),
             quorum: 0,
             owner,
+            weights: BTreeMap::new(),
+            categories: BTreeMap::new(),
+            policy: None,
         });
     });
     
     Ok(())
 }
 
+// Helper functions
+/// The guardian change itself has already been committed; a failed re-share is
+/// logged and can be retried with `reshare_recovery_secrets`.
+async fn reshare_after_guardian_change() {
+    if let Err(e) = reshare_stale_secrets().await {
+        ic_cdk::println!("Re-sharing recovery secrets failed: {}", e);
+    }
+}
+
+fn validate_guardian_set(
+    state: &VaultStateV2,
+    caller: Principal,
+    guardians: &[Principal],
+    quorum: u8,
+    weights: &BTreeMap<Principal, u8>,
+) -> Result<(), String> {
+    // The first caller to configure guardians becomes the owner
+    let owner = state.guardian_state.as_ref().map(|g| g.owner).unwrap_or(caller);
+    if owner != caller {
+        return Err("only owner can set guardians".to_string());
+    }
+    let total_weight: u32 = guardians.iter().map(|p| weights[p] as u32).sum();
+    if guardians.is_empty() || quorum == 0 || (quorum as u32) > total_weight {
+        return Err("invalid quorum/guardians".to_string());
+    }
+    for (i, guardian) in guardians.iter().enumerate() {
+        if *guardian == Principal::anonymous() {
+            return Err("anonymous principal cannot be a guardian".to_string());
+        }
+        if *guardian == owner {
+            return Err("owner cannot be their own guardian".to_string());
+        }
+        if guardians[..i].contains(guardian) {
+            return Err(format!("duplicate guardian {}", guardian));
+        }
+    }
+
+    // A structured policy must stay satisfiable by the new set
+    if let Some(g) = state.guardian_state.as_ref().filter(|g| g.policy.is_some()) {
+        let prospective = GuardianState {
+            guardians: guardians.to_vec(),
+            weights: weights.clone(),
+            ..g.clone()
+        };
+        validate_policy(&prospective.effective_policy(), &prospective, 1)
+            .map_err(|e| format!("guardian policy no longer valid: {}", e))?;
+    }
+    Ok(())
+}
+
+fn apply_guardian_set(
+    state: &mut VaultStateV2,
+    caller: Principal,
+    guardians: Vec<Principal>,
+    quorum: u8,
+    weights: BTreeMap<Principal, u8>,
+    seed: &[u8],
+    now: u64,
+) -> Result<Vec<InvitationCode>, String> {
+    validate_guardian_set(state, caller, &guardians, quorum, &weights)?;
+    let codes = invitation_codes(state, &guardians, seed);
+    install_guardian_set(state, caller, guardians, quorum, weights, &code_hashes(&codes), now);
+    Ok(codes)
+}
+
+/// One-time codes for every principal in the set who has not accepted yet.
+fn invitation_codes(state: &VaultStateV2, guardians: &[Principal], seed: &[u8]) -> Vec<InvitationCode> {
+    let accepted = state.guardian_state.as_ref().map(|g| g.guardians.clone()).unwrap_or_default();
+    guardians.iter()
+        .filter(|p| !accepted.contains(p))
+        .map(|p| InvitationCode { guardian: *p, code: invitation_code(seed, p) })
+        .collect()
+}
+
+fn code_hashes(codes: &[InvitationCode]) -> BTreeMap<Principal, Vec<u8>> {
+    codes.iter().map(|c| (c.guardian, Sha256::digest(c.code.as_bytes()).to_vec())).collect()
+}
+
+fn install_guardian_set(
+    state: &mut VaultStateV2,
+    owner: Principal,
+    guardians: Vec<Principal>,
+    quorum: u8,
+    weights: BTreeMap<Principal, u8>,
+    code_hashes: &BTreeMap<Principal, Vec<u8>>,
+    now: u64,
+) {
+    let g = state.guardian_state.get_or_insert_with(|| GuardianState {
+        guardians: Vec::new(),
+        quorum: 0,
+        owner,
+        weights: BTreeMap::new(),
+        categories: BTreeMap::new(),
+        policy: None,
+    });
+    g.guardians.retain(|p| guardians.contains(p));
+    g.quorum = quorum;
+    g.weights = g.guardians.iter().map(|p| (*p, weights[p])).collect();
+    g.categories.retain(|p, _| guardians.contains(p));
+    let accepted = g.guardians.clone();
+
+    state.guardian_transport_keys.retain(|p, _| accepted.contains(p));
+    state.guardian_profiles.retain(|p, _| accepted.contains(p));
+    state.guardian_signing_keys.retain(|p, _| accepted.contains(p));
+    state.guardian_invitations.retain(|i| guardians.contains(&i.guardian));
+
+    for guardian in guardians.into_iter().filter(|p| !accepted.contains(p)) {
+        // A queued change only holds codes for principals pending when it was queued
+        let Some(code_hash) = code_hashes.get(&guardian) else { continue };
+        // Re-inviting a pending guardian replaces their previous code
+        state.guardian_invitations.retain(|i| i.guardian != guardian);
+        state.guardian_invitations.push(GuardianInvitation {
+            guardian,
+            weight: weights[&guardian],
+            code_hash: code_hash.clone(),
+            invited_at: now,
+            expires_at: now.saturating_add(INVITATION_TTL_NANOS),
+        });
+    }
+    sync_guardian_index(state);
+}
+
+fn accept_invitation(
+    state: &mut VaultStateV2,
+    caller: Principal,
+    code: &str,
+    transport_public_key: Vec<u8>,
+    now: u64,
+) -> Result<(), String> {
+    if transport_public_key.len() != TRANSPORT_KEY_LEN {
+        return Err(format!("transport public key must be {} bytes", TRANSPORT_KEY_LEN));
+    }
+    let invitation = state.guardian_invitations.iter()
+        .find(|i| i.guardian == caller)
+        .ok_or("no pending invitation")?;
+    if now >= invitation.expires_at {
+        return Err("invitation expired".to_string());
+    }
+    if invitation.code_hash != Sha256::digest(code.trim().as_bytes()).to_vec() {
+        return Err("invalid invitation code".to_string());
+    }
+
+    let weight = invitation.weight;
+    let g = state.guardian_state.as_mut().ok_or("guardian state not initialized")?;
+    if !g.guardians.contains(&caller) {
+        g.guardians.push(caller);
+    }
+    g.weights.insert(caller, weight);
+    state.guardian_invitations.retain(|i| i.guardian != caller);
+    state.guardian_transport_keys.insert(caller, transport_public_key);
+    state.guardian_profiles.insert(caller, GuardianProfile {
+        guardian: caller,
+        nickname: None,
+        added_at: now,
+        last_seen: now,
+        attestation_requested_at: None,
+        is_canister: is_canister_principal(&caller),
+        callback_method: None,
+        membership_salt: membership_salt(code.trim(), &caller),
+    });
+    sync_guardian_index(state);
+    Ok(())
+}
+
+/// Rebuilds the guardian -> role index. Called after every change to the
+/// guardian set or its invitations so lookups never see a stale entry.
+pub(crate) fn sync_guardian_index(state: &mut VaultStateV2) {
+    let mut index = BTreeMap::new();
+    for invitation in &state.guardian_invitations {
+        index.insert(invitation.guardian, GuardianRole::Invited);
+    }
+    if let Some(g) = &state.guardian_state {
+        for guardian in &g.guardians {
+            index.insert(*guardian, GuardianRole::Active);
+        }
+    }
+    state.guardian_index = index;
+}
+
+fn guardianships_of(state: &VaultStateV2, caller: Principal, vault: Principal, now: u64) -> Vec<Guardianship> {
+    let (Some(role), Some(g)) = (state.guardian_index.get(&caller), &state.guardian_state) else {
+        return Vec::new();
+    };
+    let mut guardianship = Guardianship {
+        vault,
+        owner: g.owner,
+        role: role.clone(),
+        pending_recoveries: Vec::new(),
+        pending_proposals: Vec::new(),
+        pending_guardian_changes: Vec::new(),
+    };
+    if *role == GuardianRole::Active {
+        guardianship.pending_recoveries = state.recovery_reqs.iter()
+            .filter(|r| r.status == RecoveryStatus::Open && !r.has_voted(&caller))
+            .map(|r| r.id)
+            .collect();
+        guardianship.pending_proposals = state.transfer_proposals.iter()
+            .filter(|p| matches!(p.status, ProposalStatus::Pending) && now < p.expires_at && !p.approvals.contains(&caller))
+            .map(|p| p.id)
+            .collect();
+        guardianship.pending_guardian_changes = state.guardian_changes.iter()
+            .filter(|c| c.status == GuardianChangeStatus::Pending && c.expires_at.is_none_or(|t| now < t))
+            .filter(|c| !c.vetoes.contains(&caller) && !c.approvals.contains(&caller))
+            .filter(|c| !matches!(c.kind, GuardianChangeKind::RemoveGuardian { guardian, .. } if guardian == caller))
+            .map(|c| c.id)
+            .collect();
+    }
+    vec![guardianship]
+}
+
+/// Derived from the one-time invitation code, so only the owner and the
+/// guardian can open the commitment.
+fn membership_salt(code: &str, guardian: &Principal) -> Vec<u8> {
+    let mut hasher = Sha256::new();
+    hasher.update(MEMBERSHIP_SALT_DOMAIN);
+    hasher.update(code.as_bytes());
+    hasher.update(guardian.as_slice());
+    hasher.finalize().to_vec()
+}
+
+pub(crate) fn guardian_commitment(salt: &[u8], guardian: &Principal) -> Vec<u8> {
+    let mut hasher = Sha256::new();
+    hasher.update(COMMITMENT_DOMAIN);
+    hasher.update(salt);
+    hasher.update(guardian.as_slice());
+    hasher.finalize().to_vec()
+}
+
+/// Proof for an accepted guardian, published once they approve a recovery.
+pub(crate) fn membership_proof(state: &VaultStateV2, guardian: &Principal) -> Option<MembershipProof> {
+    let profile = state.guardian_profiles.get(guardian)?;
+    Some(MembershipProof {
+        guardian: *guardian,
+        salt: profile.membership_salt.clone(),
+        commitment: guardian_commitment(&profile.membership_salt, guardian),
+    })
+}
+
+fn guardian_summary(state: &VaultStateV2) -> Option<GuardianSummary> {
+    let g = state.guardian_state.as_ref()?;
+    // Sorted so the order does not hint at who was added when
+    let mut commitments: Vec<Vec<u8>> = state.guardian_profiles.values()
+        .filter(|p| g.guardians.contains(&p.guardian))
+        .map(|p| guardian_commitment(&p.membership_salt, &p.guardian))
+        .collect();
+    commitments.sort();
+    Some(GuardianSummary {
+        guardians: g.guardians.len() as u32,
+        invited: state.guardian_invitations.len() as u32,
+        quorum: g.quorum,
+        total_weight: g.total_weight(),
+        has_policy: g.policy.is_some(),
+        commitments,
+    })
+}
+
+/// Canister ids are opaque principals, which end in the 0x01 tag byte.
+pub(crate) fn is_canister_principal(principal: &Principal) -> bool {
+    principal.as_slice().last() == Some(&0x01)
+}
+
+fn register_callback(state: &mut VaultStateV2, caller: Principal, method: Option<String>) -> Result<(), String> {
+    if let Some(method) = &method {
+        let valid = !method.is_empty() && method.len() <= MAX_CALLBACK_METHOD_LEN
+            && method.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+        if !valid {
+            return Err("callback method must be a plain method name".to_string());
+        }
+    }
+    let profile = state.guardian_profiles.get_mut(&caller).ok_or("only guardian may register a callback")?;
+    if !profile.is_canister {
+        return Err("only canister guardians may register a callback".to_string());
+    }
+    profile.callback_method = method;
+    Ok(())
+}
+
+fn record_heartbeat(state: &mut VaultStateV2, caller: Principal, now: u64) -> Result<(), String> {
+    let profile = state.guardian_profiles.get_mut(&caller).ok_or("only guardian may send heartbeats")?;
+    profile.last_seen = now;
+    profile.attestation_requested_at = None;
+    Ok(())
+}
+
+/// Flags guardians silent for longer than the attestation interval and
+/// returns how many new requests were made.
+fn request_attestations(state: &mut VaultStateV2, now: u64) -> usize {
+    let mut requested = 0;
+    for profile in state.guardian_profiles.values_mut() {
+        let due = now.saturating_sub(profile.last_seen) >= ATTESTATION_INTERVAL_NANOS;
+        if due && profile.attestation_requested_at.is_none() {
+            profile.attestation_requested_at = Some(now);
+            requested += 1;
+        }
+    }
+    requested
+}
+
+fn vault_health(state: &VaultStateV2, g: &GuardianState, now: u64) -> VaultHealth {
+    let is_live = |p: &Principal| {
+        state.guardian_profiles.get(p)
+            .is_some_and(|profile| now.saturating_sub(profile.last_seen) < LIVENESS_WINDOW_NANOS)
+    };
+    let (live, stale): (Vec<Principal>, Vec<Principal>) = g.guardians.iter().partition(|p| is_live(p));
+
+    let mut warnings = Vec::new();
+    if g.guardians.is_empty() {
+        warnings.push("vault has no guardians".to_string());
+    } else if !g.quorum_reached(&live) {
+        warnings.push(format!(
+            "live guardians cannot reach quorum: {}",
+            g.unmet_requirements(&live).join("; ")
+        ));
+    }
+    if !stale.is_empty() {
+        warnings.push(format!("{} guardians have not checked in recently", stale.len()));
+    }
+
+    VaultHealth {
+        guardians: g.guardians.len() as u32,
+        live_guardians: live.len() as u32,
+        stale_guardians: stale,
+        warnings,
+    }
+}
+
+fn has_accepted_guardians(state: &VaultStateV2) -> bool {
+    state.guardian_state.as_ref().is_some_and(|g| !g.guardians.is_empty())
+}
+
+fn queue_guardian_change(
+    state: &mut VaultStateV2,
+    caller: Principal,
+    kind: GuardianChangeKind,
+    now: u64,
+) -> Result<(u64, u64), String> {
+    // Guardian-proposed removals are tracked apart from the owner's queued change,
+    // so a guardian cannot hold the owner's slot
+    if !is_removal(&kind)
+        && state.guardian_changes.iter().any(|c| c.status == GuardianChangeStatus::Pending && !is_removal(&c.kind))
+    {
+        return Err("a guardian change is already pending".to_string());
+    }
+    let id = state.next_guardian_change_id;
+    state.next_guardian_change_id += 1;
+    let executes_at = now.saturating_add(GUARDIAN_CHANGE_DELAY_NANOS);
+    state.guardian_changes.push(GuardianChange {
+        id,
+        requested_by: caller,
+        kind,
+        approvals: Vec::new(),
+        vetoes: Vec::new(),
+        status: GuardianChangeStatus::Pending,
+        requested_at: now,
+        executes_at,
+        executed_at: None,
+        expires_at: None,
+    });
+    notify_vault(state, NotificationKind::GuardianChangeQueued { change_id: id }, Some(caller), now);
+    Ok((id, executes_at))
+}
+
+fn is_removal(kind: &GuardianChangeKind) -> bool {
+    matches!(kind, GuardianChangeKind::RemoveGuardian { .. })
+}
+
+fn expire_removals(state: &mut VaultStateV2, now: u64) {
+    for change in state.guardian_changes.iter_mut()
+        .filter(|c| c.status == GuardianChangeStatus::Pending && c.expires_at.is_some_and(|t| now >= t))
+    {
+        change.status = GuardianChangeStatus::Expired;
+    }
+}
+
+fn pending_change_mut(state: &mut VaultStateV2, id: u64) -> Result<&mut GuardianChange, String> {
+    state.guardian_changes.iter_mut()
+        .find(|c| c.id == id && c.status == GuardianChangeStatus::Pending)
+        .ok_or_else(|| "guardian change not found or not pending".to_string())
+}
+
+fn record_veto(state: &mut VaultStateV2, caller: Principal, id: u64) -> Result<GuardianChangeStatus, String> {
+    let g = state.guardian_state.clone().ok_or("guardian state not initialized")?;
+    if !g.guardians.contains(&caller) {
+        return Err("only guardian may veto".to_string());
+    }
+    let change = pending_change_mut(state, id)?;
+    if !change.vetoes.contains(&caller) {
+        change.vetoes.push(caller);
+    }
+    if g.quorum_reached(&change.vetoes) {
+        change.status = GuardianChangeStatus::Vetoed;
+    }
+    Ok(change.status.clone())
+}
+
+/// Applies a due change and reports whether recovery secrets need re-sharing.
+fn run_guardian_change(
+    state: &mut VaultStateV2,
+    caller: Principal,
+    id: u64,
+    now: u64,
+) -> Result<(GuardianChangeStatus, bool), String> {
+    let g = state.guardian_state.as_ref().ok_or("guardian state not initialized")?;
+    if caller != g.owner && !g.guardians.contains(&caller) {
+        return Err("only owner or guardian may execute guardian changes".to_string());
+    }
+    let owner = g.owner;
+    let change = pending_change_mut(state, id)?;
+    if matches!(change.kind, GuardianChangeKind::RemoveGuardian { .. }) {
+        return Err("guardian removals take effect once approved".to_string());
+    }
+    if now < change.executes_at {
+        return Err("guardian change is still in its delay period".to_string());
+    }
+    // A change queued by a previous owner must not outlive a recovery
+    if change.requested_by != owner {
+        change.status = GuardianChangeStatus::Cancelled;
+        return Ok((GuardianChangeStatus::Cancelled, false));
+    }
+
+    let kind = change.kind.clone();
+    let reshare = match kind {
+        GuardianChangeKind::SetGuardians { guardians, quorum, weights, invitation_hashes } => {
+            validate_guardian_set(state, owner, &guardians, quorum, &weights)?;
+            install_guardian_set(state, owner, guardians, quorum, weights, &invitation_hashes, now);
+            true
+        }
+        GuardianChangeKind::SetPolicy { policy, categories } => {
+            apply_guardian_policy(state, owner, policy, categories)?;
+            false
+        }
+        GuardianChangeKind::RemoveGuardian { .. } => {
+            return Err("guardian removals take effect once approved".to_string());
+        }
+    };
+    mark_executed(state, id, now)?;
+    Ok((GuardianChangeStatus::Executed, reshare))
+}
+
+fn mark_executed(state: &mut VaultStateV2, id: u64, now: u64) -> Result<(), String> {
+    let change = pending_change_mut(state, id)?;
+    change.status = GuardianChangeStatus::Executed;
+    change.executed_at = Some(now);
+    Ok(())
+}
+
+fn propose_removal(
+    state: &mut VaultStateV2,
+    caller: Principal,
+    target: Principal,
+    replacement: Option<Principal>,
+    invitation_hash: Option<Vec<u8>>,
+    now: u64,
+) -> Result<(u64, GuardianChangeStatus), String> {
+    let g = state.guardian_state.as_ref().ok_or("guardian state not initialized")?;
+    if !g.guardians.contains(&caller) || caller == target {
+        return Err("only another guardian may propose a removal".to_string());
+    }
+    if !g.guardians.contains(&target) {
+        return Err("target is not a guardian".to_string());
+    }
+    if let Some(replacement) = replacement {
+        let pending = state.guardian_invitations.iter().any(|i| i.guardian == replacement);
+        if replacement == Principal::anonymous() || replacement == g.owner
+            || g.guardians.contains(&replacement) || pending
+        {
+            return Err("replacement must be a new principal other than the owner".to_string());
+        }
+    }
+
+    without_guardian(g, &target)?;
+
+    // One live removal per proposer and per target
+    expire_removals(state, now);
+    let duplicate = state.guardian_changes.iter()
+        .filter(|c| c.status == GuardianChangeStatus::Pending)
+        .any(|c| matches!(c.kind, GuardianChangeKind::RemoveGuardian { guardian, .. }
+            if guardian == target || c.requested_by == caller));
+    if duplicate {
+        return Err("a removal of this guardian, or by this guardian, is already pending".to_string());
+    }
+
+    let kind = GuardianChangeKind::RemoveGuardian { guardian: target, replacement, invitation_hash };
+    let (id, _) = queue_guardian_change(state, caller, kind, now)?;
+    // Removals are not delayed; they wait on approvals instead, for a limited time
+    let change = pending_change_mut(state, id)?;
+    change.executes_at = now;
+    change.expires_at = Some(now.saturating_add(REMOVAL_TTL_NANOS));
+    let status = record_removal_approval(state, caller, id, now)?;
+    Ok((id, status))
+}
+
+fn record_removal_approval(
+    state: &mut VaultStateV2,
+    caller: Principal,
+    id: u64,
+    now: u64,
+) -> Result<GuardianChangeStatus, String> {
+    let g = state.guardian_state.clone().ok_or("guardian state not initialized")?;
+    expire_removals(state, now);
+    let change = pending_change_mut(state, id)?;
+    let GuardianChangeKind::RemoveGuardian { guardian: target, replacement, invitation_hash } = change.kind.clone() else {
+        return Err("not a guardian removal".to_string());
+    };
+    if !g.guardians.contains(&caller) || caller == target {
+        return Err("only the other guardians may approve a removal".to_string());
+    }
+    if !g.guardians.contains(&target) {
+        change.status = GuardianChangeStatus::Cancelled;
+        return Ok(GuardianChangeStatus::Cancelled);
+    }
+    if !change.approvals.contains(&caller) {
+        change.approvals.push(caller);
+    }
+
+    // Approvals are weighed under the current set, where the target cannot vote
+    if !g.quorum_reached(&change.approvals) {
+        return Ok(GuardianChangeStatus::Pending);
+    }
+    let remaining = without_guardian(&g, &target)?;
+
+    let (weight, categories) = (g.weights.get(&target).copied().unwrap_or(1), g.categories.get(&target).cloned());
+    state.guardian_state = Some(remaining);
+    state.guardian_transport_keys.remove(&target);
+    state.guardian_profiles.remove(&target);
+    state.guardian_signing_keys.remove(&target);
+    // The callers re-share the recovery secrets once this returns `Executed`
+    revoke_guardian_shares(state, &target);
+    if let (Some(replacement), Some(code_hash)) = (replacement, invitation_hash) {
+        state.guardian_invitations.push(GuardianInvitation {
+            guardian: replacement,
+            weight,
+            code_hash,
+            invited_at: now,
+            expires_at: now.saturating_add(INVITATION_TTL_NANOS),
+        });
+        if let (Some(g), Some(categories)) = (state.guardian_state.as_mut(), categories) {
+            g.categories.insert(replacement, categories);
+        }
+    }
+    sync_guardian_index(state);
+    mark_executed(state, id, now)?;
+    Ok(GuardianChangeStatus::Executed)
+}
+
+/// The guardian state left after dropping `target`. Quorum and policy are kept
+/// as they are, so a removal the others could not outvote afterwards is refused.
+fn without_guardian(g: &GuardianState, target: &Principal) -> Result<GuardianState, String> {
+    let mut remaining = g.clone();
+    remaining.guardians.retain(|p| p != target);
+    remaining.weights.remove(target);
+    remaining.categories.remove(target);
+    let unmet = remaining.unmet_requirements(&remaining.guardians);
+    if !unmet.is_empty() {
+        return Err(format!("removal would leave the quorum or policy unreachable: {}", unmet.join("; ")));
+    }
+    Ok(remaining)
+}
+
+fn apply_guardian_policy(
+    state: &mut VaultStateV2,
+    caller: Principal,
+    policy: Option<GuardianPolicy>,
+    categories: Vec<GuardianCategories>,
+) -> Result<(), String> {
+    let checked = checked_guardian_policy(state, caller, policy, categories)?;
+    let g = state.guardian_state.as_mut().ok_or("guardian state not initialized")?;
+    g.categories = checked.categories;
+    g.policy = checked.policy;
+    Ok(())
+}
+
+/// Validates a policy change, returning the guardian state it would produce
+/// with pending invitees counted as accepted.
+fn checked_guardian_policy(
+    state: &VaultStateV2,
+    caller: Principal,
+    policy: Option<GuardianPolicy>,
+    categories: Vec<GuardianCategories>,
+) -> Result<GuardianState, String> {
+    let g = state.guardian_state.as_ref().ok_or("guardian state not initialized")?;
+    if g.owner != caller {
+        return Err("only owner can set the guardian policy".to_string());
+    }
+
+    // Validate against everyone who is or may become a guardian
+    let mut prospective = g.clone();
+    for invitation in &state.guardian_invitations {
+        prospective.guardians.push(invitation.guardian);
+        prospective.weights.insert(invitation.guardian, invitation.weight);
+    }
+    prospective.categories = category_map(&prospective.guardians, categories)?;
+    prospective.policy = policy;
+    if let Some(policy) = &prospective.policy {
+        validate_policy(policy, &prospective, 1)?;
+    }
+    Ok(prospective)
+}
+
+/// Checks a policy is well formed and that the full guardian set can satisfy it.
+fn validate_policy(policy: &GuardianPolicy, g: &GuardianState, depth: usize) -> Result<(), String> {
+    if depth > MAX_POLICY_DEPTH {
+        return Err(format!("policy nesting deeper than {}", MAX_POLICY_DEPTH));
+    }
+    match policy {
+        GuardianPolicy::Threshold { from, weight } => {
+            if *weight == 0 {
+                return Err("policy threshold must be at least 1".to_string());
+            }
+            match from {
+                GuardianSelector::Guardian(p) if !g.guardians.contains(p) => {
+                    return Err(format!("policy names non-guardian {}", p));
+                }
+                GuardianSelector::Category(c) if !g.categories.values().any(|cs| cs.contains(c)) => {
+                    return Err(format!("no guardian in category \"{}\"", c));
+                }
+                _ => {}
+            }
+        }
+        GuardianPolicy::All(policies) | GuardianPolicy::Any(policies) => {
+            if policies.is_empty() {
+                return Err("policy group must not be empty".to_string());
+            }
+            for p in policies {
+                validate_policy(p, g, depth + 1)?;
+            }
+        }
+    }
+    if depth == 1 && !g.unmet_requirements(&g.guardians).is_empty() {
+        return Err("policy cannot be met even if every guardian approves".to_string());
+    }
+    Ok(())
+}
+
+fn category_map(
+    guardians: &[Principal],
+    categories: Vec<GuardianCategories>,
+) -> Result<BTreeMap<Principal, Vec<String>>, String> {
+    let mut map = BTreeMap::new();
+    for GuardianCategories { guardian, categories } in categories {
+        if !guardians.contains(&guardian) {
+            return Err(format!("categories given for non-guardian {}", guardian));
+        }
+        if categories.len() > MAX_CATEGORIES_PER_GUARDIAN {
+            return Err(format!("at most {} categories per guardian", MAX_CATEGORIES_PER_GUARDIAN));
+        }
+        let mut tags: Vec<String> = Vec::new();
+        for category in categories {
+            let category = category.trim().to_lowercase();
+            if category.is_empty() || category.len() > MAX_CATEGORY_LEN {
+                return Err(format!("category must be 1-{} bytes", MAX_CATEGORY_LEN));
+            }
+            if !tags.contains(&category) {
+                tags.push(category);
+            }
+        }
+        if map.insert(guardian, tags).is_some() {
+            return Err(format!("duplicate categories for guardian {}", guardian));
+        }
+    }
+    Ok(map)
+}
+
+/// Resolves the weight of every guardian in the set, defaulting to 1.
+fn weight_map(guardians: &[Principal], weights: Vec<GuardianWeight>) -> Result<BTreeMap<Principal, u8>, String> {
+    let mut map: BTreeMap<Principal, u8> = guardians.iter().map(|p| (*p, 1)).collect();
+    let mut seen = Vec::new();
+    for GuardianWeight { guardian, weight } in weights {
+        if !guardians.contains(&guardian) {
+            return Err(format!("weight given for non-guardian {}", guardian));
+        }
+        if seen.contains(&guardian) {
+            return Err(format!("duplicate weight for guardian {}", guardian));
+        }
+        if weight == 0 {
+            return Err("guardian weight must be at least 1".to_string());
+        }
+        seen.push(guardian);
+        map.insert(guardian, weight);
+    }
+    Ok(map)
+}
+
+fn invitation_code(seed: &[u8], guardian: &Principal) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(b"guardian_vault_invitation_");
+    hasher.update(seed);
+    hasher.update(guardian.as_slice());
+    hasher.finalize()[..8].iter().map(|b| format!("{:02x}", b)).collect()
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
     use candid::Principal;
-    use crate::state::VaultStateV1;
+    use crate::state::VaultStateV2;
     use crate::types::GuardianState;
+    use crate::vetkd::{install_reshared_secret, stale_secrets, GuardianShare, RecoverySecret, ShareEpoch};
     
     fn owner_principal() -> Principal {
         Principal::from_text("rdmx6-jaaaa-aaaaa-aaadq-cai").unwrap()
@@ -87,6 +1151,10 @@ mod tests {
     fn guardian3_principal() -> Principal {
         Principal::from_text("rno2w-sqaaa-aaaaa-aaacq-cai").unwrap()
     }
+
+    fn new_guardian_principal() -> Principal {
+        Principal::from_text("renrk-eyaaa-aaaaa-aaada-cai").unwrap()
+    }
     
     #[test]
     fn test_guardian_quorum_validation() {
@@ -100,12 +1168,12 @@ mod tests {
         // Invalid quorum scenarios
         assert!(validate_quorum(&guardians, 0).is_err()); // 0 guardians required
         assert!(validate_quorum(&guardians, 4).is_err()); // More than available guardians
-        assert!(validate_quorum(&vec![], 1).is_err()); // No guardians but quorum > 0
+        assert!(validate_quorum(&[], 1).is_err()); // No guardians but quorum > 0
     }
     
     #[test]
     fn test_guardian_initialization() {
-        let mut state = VaultStateV1::default();
+        let mut state = VaultStateV2::default();
         let owner = owner_principal();
         
         // Test initialization
@@ -113,6 +1181,9 @@ mod tests {
             guardians: vec![],
             quorum: 0,
             owner,
+            weights: BTreeMap::new(),
+            categories: BTreeMap::new(),
+            policy: None,
         });
         
         let guardians = vec![guardian1_principal(), guardian2_principal()];
@@ -127,7 +1198,7 @@ mod tests {
     
     #[test]
     fn test_unauthorized_guardian_operations() {
-        let mut state = VaultStateV1::default();
+        let mut state = VaultStateV2::default();
         let owner = owner_principal();
         let unauthorized = Principal::anonymous();
         
@@ -135,6 +1206,9 @@ mod tests {
             guardians: vec![],
             quorum: 0,
             owner,
+            weights: BTreeMap::new(),
+            categories: BTreeMap::new(),
+            policy: None,
         });
         
         let guardians = vec![guardian1_principal()];
@@ -143,6 +1217,490 @@ mod tests {
         assert!(result.unwrap_err().contains("only owner"));
     }
     
+    fn unit_weights(guardians: &[Principal]) -> BTreeMap<Principal, u8> {
+        weight_map(guardians, vec![]).unwrap()
+    }
+
+    #[test]
+    fn test_guardian_set_rejects_invalid_principals() {
+        let state = VaultStateV2::default();
+        let owner = owner_principal();
+        let check = |guardians: &[Principal], quorum: u8| {
+            validate_guardian_set(&state, owner, guardians, quorum, &unit_weights(guardians))
+        };
+
+        assert!(check(&[guardian1_principal(), Principal::anonymous()], 1).is_err());
+        assert!(check(&[guardian1_principal(), owner], 1).is_err());
+        assert!(check(&[guardian1_principal(), guardian1_principal()], 1).is_err());
+        assert!(check(&[guardian1_principal(), guardian2_principal()], 3).is_err());
+        assert!(check(&[guardian1_principal(), guardian2_principal()], 2).is_ok());
+    }
+
+    #[test]
+    fn test_weighted_quorum_validation() {
+        let state = VaultStateV2::default();
+        let owner = owner_principal();
+        let guardians = vec![guardian1_principal(), guardian2_principal(), guardian3_principal()];
+        let spouse = vec![GuardianWeight { guardian: guardian1_principal(), weight: 3 }];
+        let weights = weight_map(&guardians, spouse).unwrap();
+
+        // Total weight is 3 + 1 + 1
+        assert!(validate_guardian_set(&state, owner, &guardians, 5, &weights).is_ok());
+        assert!(validate_guardian_set(&state, owner, &guardians, 6, &weights).is_err());
+
+        assert!(weight_map(&guardians, vec![GuardianWeight { guardian: guardian1_principal(), weight: 0 }]).is_err());
+        assert!(weight_map(&guardians, vec![GuardianWeight { guardian: owner, weight: 2 }]).is_err());
+    }
+
+    #[test]
+    fn test_weighted_approvals() {
+        let mut weights = BTreeMap::new();
+        weights.insert(guardian1_principal(), 3);
+        let g = GuardianState {
+            guardians: vec![guardian1_principal(), guardian2_principal(), guardian3_principal()],
+            quorum: 4,
+            owner: owner_principal(),
+            weights,
+            categories: BTreeMap::new(),
+            policy: None,
+        };
+
+        assert_eq!(g.total_weight(), 5);
+        assert!(!g.quorum_reached(&[guardian2_principal(), guardian3_principal()]));
+        assert!(!g.quorum_reached(&[guardian1_principal(), guardian1_principal()]));
+        assert!(!g.quorum_reached(&[guardian1_principal(), owner_principal()]));
+        assert!(g.quorum_reached(&[guardian1_principal(), guardian2_principal()]));
+    }
+
+    #[test]
+    fn test_invited_guardians_count_only_after_acceptance() {
+        let mut state = VaultStateV2::default();
+        let owner = owner_principal();
+        let guardians = vec![guardian1_principal(), guardian2_principal()];
+
+        let weights = unit_weights(&guardians);
+        let codes = apply_guardian_set(&mut state, owner, guardians, 2, weights, b"seed", 0).unwrap();
+        assert_eq!(codes.len(), 2);
+        assert!(state.guardian_state.as_ref().unwrap().guardians.is_empty());
+        assert_eq!(state.guardian_invitations.len(), 2);
+
+        let code1 = codes.iter().find(|c| c.guardian == guardian1_principal()).unwrap().code.clone();
+        let code2 = codes.iter().find(|c| c.guardian == guardian2_principal()).unwrap().code.clone();
+
+        // Codes are bound to their invitee and transport keys must be well formed
+        assert!(accept_invitation(&mut state, guardian1_principal(), &code2, vec![1; 48], 1).is_err());
+        assert!(accept_invitation(&mut state, guardian1_principal(), &code1, vec![1; 32], 1).is_err());
+        assert!(accept_invitation(&mut state, guardian1_principal(), &code1, vec![1; 48], 1).is_ok());
+
+        // Codes are single use
+        assert!(accept_invitation(&mut state, guardian1_principal(), &code1, vec![1; 48], 2).is_err());
+        assert_eq!(state.guardian_state.as_ref().unwrap().guardians, vec![guardian1_principal()]);
+        assert_eq!(state.guardian_transport_keys.get(&guardian1_principal()), Some(&vec![1; 48]));
+
+        assert!(accept_invitation(&mut state, guardian2_principal(), &code2, vec![2; 48], INVITATION_TTL_NANOS).is_err());
+    }
+
+    #[test]
+    fn test_guardian_set_update_keeps_accepted_guardians() {
+        let mut state = VaultStateV2::default();
+        let owner = owner_principal();
+
+        let first = vec![guardian1_principal()];
+        let codes = apply_guardian_set(&mut state, owner, first.clone(), 1, unit_weights(&first), b"seed", 0).unwrap();
+        accept_invitation(&mut state, guardian1_principal(), &codes[0].code, vec![1; 48], 1).unwrap();
+
+        let second = vec![guardian1_principal(), guardian3_principal()];
+        let codes = apply_guardian_set(&mut state, owner, second.clone(), 2, unit_weights(&second), b"seed2", 2).unwrap();
+        assert_eq!(codes.len(), 1);
+        assert_eq!(codes[0].guardian, guardian3_principal());
+        assert_eq!(state.guardian_state.as_ref().unwrap().guardians, vec![guardian1_principal()]);
+
+        // Dropping a guardian removes them and their transport key
+        let third = vec![guardian3_principal()];
+        apply_guardian_set(&mut state, owner, third.clone(), 1, unit_weights(&third), b"seed3", 3).unwrap();
+        assert!(state.guardian_state.as_ref().unwrap().guardians.is_empty());
+        assert!(state.guardian_transport_keys.is_empty());
+    }
+
+    fn tagged(guardian: Principal, category: &str) -> GuardianCategories {
+        GuardianCategories { guardian, categories: vec![category.to_string()] }
+    }
+
+    fn from_category(category: &str, weight: u32) -> GuardianPolicy {
+        GuardianPolicy::Threshold { from: GuardianSelector::Category(category.to_string()), weight }
+    }
+
+    fn setup_state_with_accepted_guardians() -> VaultStateV2 {
+        VaultStateV2 {
+            guardian_state: Some(GuardianState {
+                guardians: vec![guardian1_principal(), guardian2_principal(), guardian3_principal()],
+                quorum: 2,
+                owner: owner_principal(),
+                weights: BTreeMap::new(),
+                categories: BTreeMap::new(),
+                policy: None,
+            }),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_structured_policy_evaluation() {
+        let mut state = setup_state_with_accepted_guardians();
+        let categories = vec![
+            tagged(guardian1_principal(), "Institution"),
+            tagged(guardian2_principal(), "friend"),
+            tagged(guardian3_principal(), "friend"),
+        ];
+        // One institution plus two friends
+        let policy = GuardianPolicy::All(vec![from_category("institution", 1), from_category("friend", 2)]);
+        apply_guardian_policy(&mut state, owner_principal(), Some(policy), categories).unwrap();
+
+        let g = state.guardian_state.as_ref().unwrap();
+        assert!(!g.quorum_reached(&[guardian2_principal(), guardian3_principal()]));
+        assert_eq!(
+            g.unmet_requirements(&[guardian2_principal(), guardian3_principal()]),
+            vec!["1 more approval weight from guardians in category \"institution\"".to_string()]
+        );
+        assert_eq!(g.unmet_requirements(&[guardian1_principal()]).len(), 1);
+        assert!(g.quorum_reached(&[guardian1_principal(), guardian2_principal(), guardian3_principal()]));
+
+        // Mandatory guardian, or else every friend
+        let mandatory = GuardianPolicy::Any(vec![
+            GuardianPolicy::Threshold { from: GuardianSelector::Guardian(guardian1_principal()), weight: 1 },
+            from_category("friend", 2),
+        ]);
+        let categories = vec![tagged(guardian2_principal(), "friend"), tagged(guardian3_principal(), "friend")];
+        apply_guardian_policy(&mut state, owner_principal(), Some(mandatory), categories).unwrap();
+        let g = state.guardian_state.as_ref().unwrap();
+        assert!(g.quorum_reached(&[guardian1_principal()]));
+        assert!(g.quorum_reached(&[guardian2_principal(), guardian3_principal()]));
+        assert!(!g.quorum_reached(&[guardian2_principal()]));
+        assert!(g.unmet_requirements(&[guardian2_principal()])[0].starts_with("one of: "));
+
+        // Clearing the policy falls back to the plain quorum
+        apply_guardian_policy(&mut state, owner_principal(), None, vec![]).unwrap();
+        assert!(state.guardian_state.as_ref().unwrap().quorum_reached(&[guardian2_principal(), guardian3_principal()]));
+    }
+
+    #[test]
+    fn test_guardian_policy_validation() {
+        let mut state = setup_state_with_accepted_guardians();
+        let owner = owner_principal();
+        let friends = || vec![tagged(guardian2_principal(), "friend"), tagged(guardian3_principal(), "friend")];
+
+        assert!(apply_guardian_policy(&mut state, guardian1_principal(), None, vec![]).is_err());
+        assert!(apply_guardian_policy(&mut state, owner, Some(from_category("institution", 1)), friends()).is_err());
+        assert!(apply_guardian_policy(&mut state, owner, Some(from_category("friend", 3)), friends()).is_err());
+        assert!(apply_guardian_policy(&mut state, owner, Some(from_category("friend", 0)), friends()).is_err());
+        assert!(apply_guardian_policy(&mut state, owner, Some(GuardianPolicy::Any(vec![])), friends()).is_err());
+        assert!(apply_guardian_policy(&mut state, owner, None, vec![tagged(owner, "friend")]).is_err());
+
+        let mandatory = GuardianPolicy::Threshold { from: GuardianSelector::Guardian(guardian3_principal()), weight: 1 };
+        apply_guardian_policy(&mut state, owner, Some(mandatory), friends()).unwrap();
+
+        // The guardian set cannot drop a guardian the policy depends on
+        let without = vec![guardian1_principal(), guardian2_principal()];
+        assert!(validate_guardian_set(&state, owner, &without, 1, &unit_weights(&without)).is_err());
+        let with = vec![guardian2_principal(), guardian3_principal()];
+        apply_guardian_set(&mut state, owner, with.clone(), 1, unit_weights(&with), b"seed", 0).unwrap();
+        assert!(!state.guardian_state.as_ref().unwrap().categories.contains_key(&guardian1_principal()));
+    }
+
+    #[test]
+    fn test_attestation_and_vault_health() {
+        let mut state = VaultStateV2::default();
+        let owner = owner_principal();
+        let guardians = vec![guardian1_principal(), guardian2_principal()];
+        let codes = apply_guardian_set(&mut state, owner, guardians.clone(), 2, unit_weights(&guardians), b"seed", 0).unwrap();
+        for code in &codes {
+            accept_invitation(&mut state, code.guardian, &code.code, vec![1; 48], 10).unwrap();
+        }
+        assert_eq!(state.guardian_profiles[&guardian1_principal()].added_at, 10);
+
+        let g = state.guardian_state.clone().unwrap();
+        assert!(vault_health(&state, &g, 20).warnings.is_empty());
+
+        // Silent guardians are asked to attest once, and a heartbeat answers it
+        let due = 10 + ATTESTATION_INTERVAL_NANOS;
+        assert_eq!(request_attestations(&mut state, due), 2);
+        assert_eq!(request_attestations(&mut state, due + 1), 0);
+        record_heartbeat(&mut state, guardian1_principal(), due + 2).unwrap();
+        assert_eq!(state.guardian_profiles[&guardian1_principal()].attestation_requested_at, None);
+        assert_eq!(state.guardian_profiles[&guardian2_principal()].attestation_requested_at, Some(due));
+        assert!(record_heartbeat(&mut state, owner, due).is_err());
+
+        // Guardian 2 lapses, leaving too few live guardians for quorum
+        let health = vault_health(&state, &g, 10 + LIVENESS_WINDOW_NANOS);
+        assert_eq!(health.live_guardians, 1);
+        assert_eq!(health.stale_guardians, vec![guardian2_principal()]);
+        assert!(health.warnings[0].starts_with("live guardians cannot reach quorum"));
+    }
+
+    fn queue_set(state: &mut VaultStateV2, guardians: Vec<Principal>, quorum: u8, now: u64) -> u64 {
+        let weights = unit_weights(&guardians);
+        let invitation_hashes = code_hashes(&invitation_codes(state, &guardians, b"seed"));
+        let kind = GuardianChangeKind::SetGuardians { guardians, quorum, weights, invitation_hashes };
+        queue_guardian_change(state, owner_principal(), kind, now).unwrap().0
+    }
+
+    #[test]
+    fn test_queued_guardian_change_applies_after_delay() {
+        let mut state = setup_state_with_accepted_guardians();
+        let id = queue_set(&mut state, vec![guardian1_principal(), new_guardian_principal()], 1, 0);
+
+        // Only one change may be pending at a time
+        let kind = GuardianChangeKind::SetPolicy { policy: None, categories: vec![] };
+        assert!(queue_guardian_change(&mut state, owner_principal(), kind, 0).is_err());
+
+        let early = run_guardian_change(&mut state, owner_principal(), id, GUARDIAN_CHANGE_DELAY_NANOS - 1);
+        assert!(early.is_err());
+        assert!(run_guardian_change(&mut state, new_guardian_principal(), id, GUARDIAN_CHANGE_DELAY_NANOS).is_err());
+
+        let result = run_guardian_change(&mut state, guardian2_principal(), id, GUARDIAN_CHANGE_DELAY_NANOS).unwrap();
+        assert_eq!(result, (GuardianChangeStatus::Executed, true));
+        let g = state.guardian_state.as_ref().unwrap();
+        assert_eq!(g.guardians, vec![guardian1_principal()]);
+        assert_eq!(g.quorum, 1);
+        assert_eq!(state.guardian_invitations.len(), 1);
+        assert_eq!(state.guardian_invitations[0].guardian, new_guardian_principal());
+    }
+
+    #[test]
+    fn test_guardians_veto_queued_change() {
+        let mut state = setup_state_with_accepted_guardians();
+        let id = queue_set(&mut state, vec![new_guardian_principal()], 1, 0);
+
+        assert!(record_veto(&mut state, owner_principal(), id).is_err());
+        assert_eq!(record_veto(&mut state, guardian1_principal(), id).unwrap(), GuardianChangeStatus::Pending);
+        assert_eq!(record_veto(&mut state, guardian1_principal(), id).unwrap(), GuardianChangeStatus::Pending);
+        assert_eq!(record_veto(&mut state, guardian3_principal(), id).unwrap(), GuardianChangeStatus::Vetoed);
+
+        assert!(run_guardian_change(&mut state, owner_principal(), id, GUARDIAN_CHANGE_DELAY_NANOS).is_err());
+        assert_eq!(state.guardian_state.as_ref().unwrap().guardians.len(), 3);
+    }
+
+    #[test]
+    fn test_recovery_cancels_changes_queued_by_previous_owner() {
+        let mut state = setup_state_with_accepted_guardians();
+        let id = queue_set(&mut state, vec![new_guardian_principal()], 1, 0);
+        state.guardian_state.as_mut().unwrap().owner = new_guardian_principal();
+
+        let result = run_guardian_change(&mut state, guardian1_principal(), id, GUARDIAN_CHANGE_DELAY_NANOS).unwrap();
+        assert_eq!(result, (GuardianChangeStatus::Cancelled, false));
+        assert_eq!(state.guardian_state.as_ref().unwrap().guardians.len(), 3);
+    }
+
+    #[test]
+    fn test_guardians_remove_and_replace_a_guardian() {
+        let mut state = setup_state_with_accepted_guardians();
+        state.guardian_state.as_mut().unwrap().categories
+            .insert(guardian3_principal(), vec!["family".to_string()]);
+        let replacement = Some(new_guardian_principal());
+        let hash = Some(vec![7; 32]);
+
+        assert!(propose_removal(&mut state, owner_principal(), guardian3_principal(), None, None, 0).is_err());
+        assert!(propose_removal(&mut state, guardian3_principal(), guardian3_principal(), None, None, 0).is_err());
+        assert!(propose_removal(&mut state, guardian1_principal(), guardian2_principal(), Some(guardian3_principal()), None, 0).is_err());
+
+        let (id, status) = propose_removal(&mut state, guardian1_principal(), guardian3_principal(), replacement, hash, 0).unwrap();
+        assert_eq!(status, GuardianChangeStatus::Pending);
+
+        // The target cannot vote on their own removal, and it cannot be run as a delayed change
+        assert!(record_removal_approval(&mut state, guardian3_principal(), id, 1).is_err());
+        assert!(run_guardian_change(&mut state, owner_principal(), id, GUARDIAN_CHANGE_DELAY_NANOS).is_err());
+
+        assert_eq!(record_removal_approval(&mut state, guardian2_principal(), id, 2).unwrap(), GuardianChangeStatus::Executed);
+        let g = state.guardian_state.as_ref().unwrap();
+        assert_eq!(g.guardians, vec![guardian1_principal(), guardian2_principal()]);
+        assert_eq!(g.categories.get(&new_guardian_principal()), Some(&vec!["family".to_string()]));
+        assert_eq!(state.guardian_invitations[0].guardian, new_guardian_principal());
+        assert_eq!(state.guardian_invitations[0].code_hash, vec![7; 32]);
+
+        // The change log keeps the audit trail
+        let change = &state.guardian_changes[0];
+        assert_eq!(change.requested_by, guardian1_principal());
+        assert_eq!(change.approvals, vec![guardian1_principal(), guardian2_principal()]);
+        assert_eq!(change.executed_at, Some(2));
+    }
+
+    #[test]
+    fn test_removal_retires_the_removed_guardians_share() {
+        let mut state = setup_state_with_accepted_guardians();
+        let guardians = state.guardian_state.as_ref().unwrap().guardians.clone();
+        let share = |guardian: Principal, epoch: u32| GuardianShare {
+            guardian,
+            commitment: vec![],
+            encrypted_share: vec![epoch as u8],
+            share_index: 0,
+            derivation_path: vec![],
+            epoch,
+            weight: 1,
+        };
+        let secret_id = vec![7; 32];
+        state.recovery_secrets.insert(secret_id.clone(), RecoverySecret {
+            secret_id: secret_id.clone(),
+            guardian_shares: guardians.iter().map(|g| (*g, share(*g, 0))).collect(),
+            threshold: 2,
+            created_at: 0,
+            epoch: 0,
+            epoch_history: vec![ShareEpoch {
+                epoch: 0,
+                guardians: guardians.clone(),
+                weights: BTreeMap::new(),
+                threshold: 2,
+                created_at: 0,
+                retired_at: None,
+            }],
+        });
+
+        let (id, _) = propose_removal(&mut state, guardian1_principal(), guardian3_principal(), None, None, 0).unwrap();
+        assert_eq!(record_removal_approval(&mut state, guardian2_principal(), id, 1).unwrap(), GuardianChangeStatus::Executed);
+
+        // The share is withdrawn at once and the secret is due for re-sharing
+        assert!(!state.recovery_secrets[&secret_id].guardian_shares.contains_key(&guardian3_principal()));
+        assert_eq!(stale_secrets(&state), vec![(secret_id.clone(), 0)]);
+
+        let g = state.guardian_state.clone().unwrap();
+        let shares = g.guardians.iter().map(|p| (*p, share(*p, 1))).collect();
+        assert!(install_reshared_secret(&mut state, &secret_id, 0, &g, shares, 2));
+        let secret = &state.recovery_secrets[&secret_id];
+        assert_eq!(secret.epoch, 1);
+        assert_eq!(secret.epoch_history[0].retired_at, Some(2));
+        assert!(!secret.epoch_history[1].guardians.contains(&guardian3_principal()));
+        assert!(stale_secrets(&state).is_empty());
+    }
+
+    #[test]
+    fn test_removal_must_leave_a_reachable_quorum() {
+        let mut state = setup_state_with_accepted_guardians();
+        state.guardian_state.as_mut().unwrap().quorum = 3;
+        assert!(propose_removal(&mut state, guardian1_principal(), guardian3_principal(), None, None, 0).is_err());
+
+        // Nor may it strand a policy that needs the target
+        let g = state.guardian_state.as_mut().unwrap();
+        g.quorum = 2;
+        g.policy = Some(GuardianPolicy::Threshold { from: GuardianSelector::Guardian(guardian3_principal()), weight: 1 });
+        assert!(propose_removal(&mut state, guardian1_principal(), guardian3_principal(), None, None, 0).is_err());
+        assert!(state.guardian_changes.is_empty());
+    }
+
+    #[test]
+    fn test_removal_approvals_are_weighed_under_the_current_set() {
+        let mut state = setup_state_with_accepted_guardians();
+        let g = state.guardian_state.as_mut().unwrap();
+        g.guardians.push(new_guardian_principal());
+        g.quorum = 3;
+
+        // The target cannot vote, so every other guardian's approval is needed
+        let (id, _) = propose_removal(&mut state, guardian1_principal(), guardian3_principal(), None, None, 0).unwrap();
+        assert_eq!(record_removal_approval(&mut state, guardian2_principal(), id, 1).unwrap(), GuardianChangeStatus::Pending);
+        assert_eq!(record_removal_approval(&mut state, new_guardian_principal(), id, 2).unwrap(), GuardianChangeStatus::Executed);
+        assert_eq!(state.guardian_state.as_ref().unwrap().quorum, 3);
+    }
+
+    #[test]
+    fn test_pending_removal_does_not_block_the_owner() {
+        let mut state = setup_state_with_accepted_guardians();
+        let (g1, g2, g3) = (guardian1_principal(), guardian2_principal(), guardian3_principal());
+        let (id, status) = propose_removal(&mut state, g1, g3, None, None, 0).unwrap();
+        assert_eq!(status, GuardianChangeStatus::Pending);
+
+        // The owner's queue is separate, and a guardian holds one live removal at a time
+        queue_set(&mut state, vec![g1, g2, g3], 2, 0);
+        assert!(propose_removal(&mut state, g1, g2, None, None, 1).is_err());
+        assert!(propose_removal(&mut state, g2, g3, None, None, 1).is_err());
+
+        // An unapproved removal lapses
+        assert!(record_removal_approval(&mut state, g2, id, REMOVAL_TTL_NANOS).is_err());
+        assert_eq!(state.guardian_changes[0].status, GuardianChangeStatus::Expired);
+        assert!(propose_removal(&mut state, g1, g2, None, None, REMOVAL_TTL_NANOS).is_ok());
+        assert_eq!(state.guardian_state.as_ref().unwrap().guardians.len(), 3);
+    }
+
+    #[test]
+    fn test_only_canister_guardians_register_callbacks() {
+        let mut state = VaultStateV2::default();
+        let user = Principal::self_authenticating(b"user key");
+        let guardians = vec![guardian1_principal(), user];
+        let codes = apply_guardian_set(&mut state, owner_principal(), guardians.clone(), 2, unit_weights(&guardians), b"seed", 0).unwrap();
+        for code in &codes {
+            accept_invitation(&mut state, code.guardian, &code.code, vec![1; 48], 1).unwrap();
+        }
+        assert!(state.guardian_profiles[&guardian1_principal()].is_canister);
+        assert!(!state.guardian_profiles[&user].is_canister);
+
+        assert!(register_callback(&mut state, user, Some("on_recovery".to_string())).is_err());
+        assert!(register_callback(&mut state, guardian1_principal(), Some("bad method()".to_string())).is_err());
+        assert!(register_callback(&mut state, guardian2_principal(), Some("on_recovery".to_string())).is_err());
+        register_callback(&mut state, guardian1_principal(), Some("on_recovery".to_string())).unwrap();
+        assert_eq!(state.guardian_profiles[&guardian1_principal()].callback_method.as_deref(), Some("on_recovery"));
+    }
+
+    #[test]
+    fn test_my_guardianships_follows_guardian_changes() {
+        let mut state = VaultStateV2::default();
+        let vault = Principal::management_canister();
+        let guardians = vec![guardian1_principal(), guardian2_principal()];
+        let codes = apply_guardian_set(&mut state, owner_principal(), guardians.clone(), 1, unit_weights(&guardians), b"seed", 0).unwrap();
+
+        let invited = guardianships_of(&state, guardian1_principal(), vault, 0);
+        assert_eq!(invited.len(), 1);
+        assert_eq!(invited[0].role, GuardianRole::Invited);
+        assert_eq!(invited[0].owner, owner_principal());
+        assert!(guardianships_of(&state, guardian3_principal(), vault, 0).is_empty());
+
+        let code1 = codes.iter().find(|c| c.guardian == guardian1_principal()).unwrap();
+        accept_invitation(&mut state, guardian1_principal(), &code1.code, vec![1; 48], 1).unwrap();
+        state.recovery_reqs.push(crate::types::RecoveryRequest {
+            id: 7,
+            new_owner: new_guardian_principal(),
+            linked_devices: vec![],
+            confirmations: vec![],
+            requested_by: guardian1_principal(),
+            approvals: vec![],
+            rejections: vec![],
+            bond: None,
+            commit_reveal: None,
+            status: RecoveryStatus::Open,
+            created_at: 1,
+            expires_at: u64::MAX,
+            unlocks_at: None,
+        });
+
+        let active = guardianships_of(&state, guardian1_principal(), vault, 2);
+        assert_eq!(active[0].role, GuardianRole::Active);
+        assert_eq!(active[0].pending_recoveries, vec![7]);
+        state.recovery_reqs[0].approvals.push(guardian1_principal());
+        assert!(guardianships_of(&state, guardian1_principal(), vault, 2)[0].pending_recoveries.is_empty());
+
+        // Dropping a guardian removes them from the index
+        let remaining = vec![guardian2_principal()];
+        apply_guardian_set(&mut state, owner_principal(), remaining.clone(), 1, unit_weights(&remaining), b"seed2", 3).unwrap();
+        assert!(guardianships_of(&state, guardian1_principal(), vault, 3).is_empty());
+        assert_eq!(guardianships_of(&state, guardian2_principal(), vault, 3)[0].role, GuardianRole::Invited);
+    }
+
+    #[test]
+    fn test_summary_publishes_commitments_not_principals() {
+        let mut state = VaultStateV2::default();
+        let guardians = vec![guardian1_principal(), guardian2_principal()];
+        let codes = apply_guardian_set(&mut state, owner_principal(), guardians.clone(), 1, unit_weights(&guardians), b"seed", 0).unwrap();
+        let code1 = codes.iter().find(|c| c.guardian == guardian1_principal()).unwrap();
+        accept_invitation(&mut state, guardian1_principal(), &code1.code, vec![1; 48], 1).unwrap();
+
+        let summary = guardian_summary(&state).unwrap();
+        assert_eq!((summary.guardians, summary.invited, summary.total_weight), (1, 1, 1));
+        assert_eq!(summary.commitments.len(), 1);
+        assert_ne!(summary.commitments[0], guardian1_principal().as_slice().to_vec());
+
+        // A revealed proof opens exactly one published commitment
+        let proof = membership_proof(&state, &guardian1_principal()).unwrap();
+        assert_eq!(guardian_commitment(&proof.salt, &proof.guardian), summary.commitments[0]);
+        assert_ne!(guardian_commitment(&proof.salt, &guardian2_principal()), summary.commitments[0]);
+        assert!(membership_proof(&state, &guardian2_principal()).is_none());
+    }
+
     // Helper functions
     fn validate_quorum(guardians: &[Principal], quorum: u8) -> Result<(), String> {
         if guardians.is_empty() || quorum == 0 || (quorum as usize) > guardians.len() {
@@ -152,7 +1710,7 @@ mod tests {
     }
     
     fn simulate_set_guardians(
-        state: &mut VaultStateV1,
+        state: &mut VaultStateV2,
         caller: Principal,
         guardians: Vec<Principal>,
         quorum: u8,
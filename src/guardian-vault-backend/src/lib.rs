@@ -4,23 +4,44 @@ pub mod config;
 pub mod guardians;
 pub mod recovery;
 pub mod ckbtc;
+pub mod proposals;
+pub mod whitelist;
 pub mod ecdsa;
 pub mod vetkd;
+pub mod vault_id;
+pub mod notifications;
+pub mod signed_approvals;
+pub mod drills;
+pub mod freeze;
+pub mod migration;
+mod canister_call;
 
 pub use config::*;
 pub use guardians::*;
 pub use recovery::*;
 pub use ckbtc::*;
+pub use proposals::*;
+pub use whitelist::*;
 pub use ecdsa::*;
 pub use vetkd::*;
+pub use vault_id::*;
+pub use notifications::*;
+pub use signed_approvals::*;
+pub use drills::*;
+pub use freeze::*;
 
 use candid::Principal;
-use crate::state::migrate_state;
-use crate::types::{Config, GuardianState, RecoveryRequest, UtxoStatus, PendingUtxo};
+use crate::state::{migrate_state, NamedSubaccount, TransactionRecord};
+use crate::types::{
+    Config, GuardianCategories, GuardianInvitation, GuardianPolicy, GuardianProfile, GuardianState, GuardianSummary,
+    GuardianWeight, Icrc1Account, MembershipProof, RecoveryBond, RecoveryProgress, RecoveryRequest, RecoveryStatus, RecoveryTally, RecoveryVoting, UtxoStatus,
+    PendingUtxo, VaultHealth,
+};
 
 
 #[ic_cdk::init]
 fn init() {
+    start_attestation_timer();
     ic_cdk::println!("Guardian Vault canister initialized");
 }
 
@@ -33,8 +54,9 @@ fn pre_upgrade() {
 fn post_upgrade() {
     ic_cdk::println!("Canister upgraded successfully");
     if let Err(e) = migrate_state() {
-        ic_cdk::trap(&format!("State migration failed: {}", e));
+        ic_cdk::trap(format!("State migration failed: {}", e));
     }
+    start_attestation_timer();
     ic_cdk::println!("State migration completed");
 }
 
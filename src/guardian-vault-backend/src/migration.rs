@@ -0,0 +1,282 @@
+use candid::{CandidType, Deserialize, Principal};
+use std::collections::{BTreeMap, HashMap};
+use crate::guardians::sync_guardian_index;
+use crate::recovery::RECOVERY_TTL_NANOS;
+use crate::state::{TransactionKind, TransactionRecord, TransactionStatus, VaultStateV2};
+use crate::types::{Config, GuardianState, Icrc1Account, RecoveryRequest, RecoveryStatus};
+use crate::vetkd::{share_commitment, GuardianShare, RecoverySecret, ShareEpoch};
+
+// Stable state layouts written by earlier versions of the canister. Each one is
+// decoded as it was stored and converted to the current layout; fields that
+// did not exist yet get the same values a fresh canister starts with.
+
+/// Version 1 stable state, as stored before guardian management, proposals,
+/// named subaccounts and the recovery state machine.
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct VaultStateV1 {
+    pub version: u32,
+    pub config: Option<Config>,
+    pub guardian_state: Option<GuardianStateV1>,
+    pub next_recovery_id: u64,
+    pub recovery_reqs: Vec<RecoveryRequestV1>,
+    pub subaccounts: BTreeMap<Principal, Vec<u8>>, // user -> subaccount
+    pub recovery_secrets: HashMap<Vec<u8>, RecoverySecretV1>,
+    pub submitted_recovery_shares: HashMap<u64, HashMap<Principal, Vec<u8>>>,
+    pub btc_addresses: HashMap<Principal, String>,
+    pub transaction_history: Vec<TransactionRecordV1>,
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct GuardianStateV1 {
+    pub guardians: Vec<Principal>,
+    pub quorum: u8,
+    pub owner: Principal,
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct RecoveryRequestV1 {
+    pub id: u64,
+    pub new_owner: Principal,
+    pub approvals: Vec<Principal>,
+    pub open: bool,
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct GuardianShareV1 {
+    pub guardian: Principal,
+    pub encrypted_share: Vec<u8>,
+    pub share_index: u8,
+    pub derivation_path: Vec<Vec<u8>>,
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct RecoverySecretV1 {
+    pub secret_id: Vec<u8>,
+    pub guardian_shares: HashMap<Principal, GuardianShareV1>,
+    pub threshold: u8,
+    pub created_at: u64,
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct TransactionRecordV1 {
+    pub id: u64,
+    pub from: Principal,
+    pub to: Principal,
+    pub amount: u64,
+    pub fee: u64,
+    pub memo: Option<Vec<u8>>,
+    pub timestamp: u64,
+    pub status: TransactionStatus,
+}
+
+/// Converts version 1 state. Version 1 minted deposits to the user's own
+/// ledger account under its subaccount, not to the canister, so each one is
+/// kept as that account rather than turned into a vault pocket that would
+/// show an empty balance.
+pub(crate) fn upgrade_v1(old: VaultStateV1, now: u64) -> VaultStateV2 {
+    let guardian_state = old.guardian_state.map(|g| GuardianState {
+        guardians: g.guardians,
+        quorum: g.quorum,
+        owner: g.owner,
+        weights: BTreeMap::new(),
+        categories: BTreeMap::new(),
+        policy: None,
+    });
+    let legacy_accounts = old.subaccounts.into_iter()
+        .map(|(owner, subaccount)| (owner, Icrc1Account { owner, subaccount: Some(subaccount) }))
+        .collect();
+    let recovery_reqs = old.recovery_reqs.into_iter()
+        .map(|req| upgrade_recovery_request(req, now))
+        .collect();
+    let recovery_secrets = old.recovery_secrets.into_iter()
+        .map(|(id, secret)| (id, upgrade_recovery_secret(secret)))
+        .collect();
+    let transaction_history = old.transaction_history.into_iter()
+        .map(|t| TransactionRecord {
+            id: t.id,
+            from: t.from,
+            to: t.to,
+            amount: t.amount,
+            fee: t.fee,
+            memo: t.memo,
+            timestamp: t.timestamp,
+            status: t.status,
+            kind: TransactionKind::Transfer,
+        })
+        .collect();
+
+    let mut state = VaultStateV2 {
+        config: old.config,
+        guardian_state,
+        next_recovery_id: old.next_recovery_id,
+        recovery_reqs,
+        legacy_accounts,
+        recovery_secrets,
+        submitted_recovery_shares: old.submitted_recovery_shares,
+        btc_addresses: old.btc_addresses,
+        transaction_history,
+        ..Default::default()
+    };
+    sync_guardian_index(&mut state);
+    state
+}
+
+/// Version 1 closed a request only when it executed. Open requests keep their
+/// approvals and get a fresh lifetime from the upgrade.
+fn upgrade_recovery_request(req: RecoveryRequestV1, now: u64) -> RecoveryRequest {
+    RecoveryRequest {
+        id: req.id,
+        new_owner: req.new_owner,
+        linked_devices: vec![],
+        confirmations: vec![],
+        requested_by: req.new_owner,
+        approvals: req.approvals,
+        rejections: vec![],
+        bond: None,
+        commit_reveal: None,
+        status: if req.open { RecoveryStatus::Open } else { RecoveryStatus::Executed },
+        created_at: now,
+        expires_at: now.saturating_add(RECOVERY_TTL_NANOS),
+        unlocks_at: None,
+    }
+}
+
+/// Version 1 shares become epoch 0 with unit weights, which is what they were
+/// issued under, and get the commitment they would have been issued with.
+fn upgrade_recovery_secret(secret: RecoverySecretV1) -> RecoverySecret {
+    let guardian_shares: HashMap<Principal, GuardianShare> = secret.guardian_shares.into_iter()
+        .map(|(guardian, share)| {
+            let share = GuardianShare {
+                guardian: share.guardian,
+                commitment: share_commitment(&share.encrypted_share),
+                encrypted_share: share.encrypted_share,
+                share_index: share.share_index,
+                derivation_path: share.derivation_path,
+                epoch: 0,
+                weight: 1,
+            };
+            (guardian, share)
+        })
+        .collect();
+    let mut guardians: Vec<Principal> = guardian_shares.keys().copied().collect();
+    guardians.sort();
+    RecoverySecret {
+        secret_id: secret.secret_id,
+        guardian_shares,
+        threshold: secret.threshold,
+        created_at: secret.created_at,
+        epoch: 0,
+        epoch_history: vec![ShareEpoch {
+            epoch: 0,
+            guardians,
+            weights: BTreeMap::new(),
+            threshold: secret.threshold,
+            created_at: secret.created_at,
+            retired_at: None,
+        }],
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state::decode_state;
+
+    fn owner_principal() -> Principal {
+        Principal::from_text("rdmx6-jaaaa-aaaaa-aaadq-cai").unwrap()
+    }
+
+    fn guardian1_principal() -> Principal {
+        Principal::from_text("rrkah-fqaaa-aaaaa-aaaaq-cai").unwrap()
+    }
+
+    fn new_owner_principal() -> Principal {
+        Principal::from_text("renrk-eyaaa-aaaaa-aaada-cai").unwrap()
+    }
+
+    fn v1_state() -> VaultStateV1 {
+        VaultStateV1 {
+            version: 1,
+            config: None,
+            guardian_state: Some(GuardianStateV1 {
+                guardians: vec![guardian1_principal()],
+                quorum: 1,
+                owner: owner_principal(),
+            }),
+            next_recovery_id: 2,
+            recovery_reqs: vec![RecoveryRequestV1 {
+                id: 1,
+                new_owner: new_owner_principal(),
+                approvals: vec![],
+                open: true,
+            }],
+            subaccounts: BTreeMap::from([(owner_principal(), vec![7; 32])]),
+            recovery_secrets: HashMap::new(),
+            submitted_recovery_shares: HashMap::new(),
+            btc_addresses: HashMap::new(),
+            transaction_history: vec![],
+        }
+    }
+
+    #[test]
+    fn test_v1_state_decodes_with_its_subaccounts() {
+        let bytes = candid::encode_one(v1_state()).unwrap();
+        let state = decode_state(&bytes, 100).unwrap();
+
+        assert_eq!(state.version, 2);
+        // The funds sit in the user's own account, so no vault pocket claims them
+        let legacy = &state.legacy_accounts[&owner_principal()];
+        assert_eq!((legacy.owner, legacy.subaccount.clone()), (owner_principal(), Some(vec![7; 32])));
+        assert!(state.subaccounts.is_empty());
+        assert_eq!(state.next_recovery_id, 2);
+        assert_eq!(state.guardian_state.unwrap().owner, owner_principal());
+    }
+
+    #[test]
+    fn test_v1_records_get_current_defaults() {
+        let mut old = v1_state();
+        old.recovery_reqs.push(RecoveryRequestV1 { id: 0, new_owner: owner_principal(), approvals: vec![], open: false });
+        let share = GuardianShareV1 {
+            guardian: guardian1_principal(),
+            encrypted_share: vec![1; 16],
+            share_index: 0,
+            derivation_path: vec![],
+        };
+        old.recovery_secrets.insert(b"secret".to_vec(), RecoverySecretV1 {
+            secret_id: b"secret".to_vec(),
+            guardian_shares: HashMap::from([(guardian1_principal(), share)]),
+            threshold: 1,
+            created_at: 5,
+        });
+        old.transaction_history.push(TransactionRecordV1 {
+            id: 1,
+            from: owner_principal(),
+            to: guardian1_principal(),
+            amount: 10,
+            fee: 1,
+            memo: None,
+            timestamp: 5,
+            status: TransactionStatus::Confirmed,
+        });
+        let state = decode_state(&candid::encode_one(old).unwrap(), 100).unwrap();
+
+        let open = &state.recovery_reqs[0];
+        assert_eq!((open.status.clone(), open.expires_at), (RecoveryStatus::Open, 100 + RECOVERY_TTL_NANOS));
+        assert_eq!(state.recovery_reqs[1].status, RecoveryStatus::Executed);
+        let secret = &state.recovery_secrets[&b"secret".to_vec()];
+        assert_eq!((secret.epoch, secret.epoch_history.len()), (0, 1));
+        assert_eq!(secret.guardian_shares[&guardian1_principal()].weight, 1);
+        assert_eq!(secret.guardian_shares[&guardian1_principal()].commitment, share_commitment(&[1; 16]));
+        assert!(matches!(state.transaction_history[0].kind, TransactionKind::Transfer));
+        assert!(state.guardian_index.contains_key(&guardian1_principal()));
+
+        // Fields added since version 1 start as on a fresh canister
+        assert_eq!(state.next_proposal_id, 1);
+        assert_eq!(state.next_notification_id, 1);
+        assert_eq!(state.frozen_until, 0);
+
+        // And the converted state round-trips as the current version
+        let reloaded = decode_state(&candid::encode_one(&state).unwrap(), 200).unwrap();
+        assert_eq!(reloaded.recovery_reqs[0].expires_at, 100 + RECOVERY_TTL_NANOS);
+    }
+}
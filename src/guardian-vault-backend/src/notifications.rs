@@ -0,0 +1,210 @@
+use candid::{CandidType, Deserialize, Principal};
+use serde::Serialize;
+use crate::state::{with_state, with_state_mut, VaultStateV2};
+
+/// Oldest notifications are dropped once an inbox holds this many.
+const MAX_NOTIFICATIONS_PER_INBOX: usize = 200;
+/// Notifications older than this are dropped, read or not.
+const NOTIFICATION_RETENTION_NANOS: u64 = 90 * 24 * 60 * 60 * 1_000_000_000;
+const MAX_PAGE_SIZE: u32 = 50;
+
+#[derive(Clone, Debug, CandidType, Deserialize, Serialize)]
+pub enum NotificationKind {
+    RecoveryRequested { recovery_id: u64, new_owner: Principal },
+    RecoveryApproved { recovery_id: u64, guardian: Principal },
+    /// Sent to the new owner, who must call `accept_recovered_ownership`.
+    RecoveryAwaitingAcceptance { recovery_id: u64 },
+    /// Quorum and the new owner's acceptance are in; the owner can still reject until `unlocks_at`.
+    RecoveryTimeLocked { recovery_id: u64, unlocks_at: u64 },
+    GuardianChangeQueued { change_id: u64 },
+    ProposalOpened { proposal_id: u64 },
+    /// A rehearsal; responding never changes the vault's owner.
+    RecoveryDrillStarted { drill_id: u64 },
+    /// Outgoing funds are blocked until `frozen_until`.
+    VaultFrozen { guardian: Principal, frozen_until: u64 },
+    /// Guardians may cancel the entry until `active_at`.
+    WhitelistEntryAdded { id: u64, active_at: u64 },
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize, Serialize)]
+pub struct Notification {
+    pub id: u64,
+    pub kind: NotificationKind,
+    pub created_at: u64,
+    pub read: bool,
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct NotificationPage {
+    pub notifications: Vec<Notification>, // newest first
+    pub unread: u32,
+    pub next_cursor: Option<u64>, // pass as `before` to fetch older notifications
+}
+
+/// The caller's notifications, newest first. `before` is the cursor from the
+/// previous page.
+#[ic_cdk::query]
+pub fn get_notifications(before: Option<u64>, limit: u32, unread_only: bool) -> NotificationPage {
+    let caller = ic_cdk::api::msg_caller();
+    with_state(|state| notification_page(state, &caller, before, limit, unread_only))
+}
+
+/// Marks the given notifications, or all of them when `ids` is `None`, as
+/// read. Returns how many changed.
+#[ic_cdk::update]
+pub fn mark_notifications_read(ids: Option<Vec<u64>>) -> u32 {
+    let caller = ic_cdk::api::msg_caller();
+    with_state_mut(|state| mark_read(state, &caller, ids.as_deref()))
+}
+
+/// Delivers a notification to the vault owner and every guardian except
+/// `actor`, who caused the event.
+pub(crate) fn notify_vault(state: &mut VaultStateV2, kind: NotificationKind, actor: Option<Principal>, now: u64) {
+    let Some(g) = state.guardian_state.as_ref() else {
+        return;
+    };
+    let mut recipients: Vec<Principal> = g.guardians.clone();
+    recipients.push(g.owner);
+    recipients.retain(|p| Some(*p) != actor);
+    recipients.sort();
+    recipients.dedup();
+    deliver(state, &recipients, kind, now);
+}
+
+pub(crate) fn deliver(state: &mut VaultStateV2, recipients: &[Principal], kind: NotificationKind, now: u64) {
+    for recipient in recipients {
+        let id = state.next_notification_id;
+        state.next_notification_id += 1;
+        let inbox = state.notifications.entry(*recipient).or_default();
+        inbox.retain(|n| now.saturating_sub(n.created_at) < NOTIFICATION_RETENTION_NANOS);
+        inbox.push(Notification { id, kind: kind.clone(), created_at: now, read: false });
+        if inbox.len() > MAX_NOTIFICATIONS_PER_INBOX {
+            let excess = inbox.len() - MAX_NOTIFICATIONS_PER_INBOX;
+            inbox.drain(..excess);
+        }
+    }
+}
+
+// Helper functions
+fn notification_page(
+    state: &VaultStateV2,
+    caller: &Principal,
+    before: Option<u64>,
+    limit: u32,
+    unread_only: bool,
+) -> NotificationPage {
+    let inbox = state.notifications.get(caller).map(Vec::as_slice).unwrap_or_default();
+    let limit = limit.clamp(1, MAX_PAGE_SIZE) as usize;
+    let mut matching = inbox.iter()
+        .rev()
+        .filter(|n| before.is_none_or(|b| n.id < b) && (!unread_only || !n.read));
+    let notifications: Vec<Notification> = matching.by_ref().take(limit).cloned().collect();
+    let next_cursor = match matching.next() {
+        Some(_) => notifications.last().map(|n| n.id),
+        None => None,
+    };
+    NotificationPage {
+        notifications,
+        unread: inbox.iter().filter(|n| !n.read).count() as u32,
+        next_cursor,
+    }
+}
+
+fn mark_read(state: &mut VaultStateV2, caller: &Principal, ids: Option<&[u64]>) -> u32 {
+    let Some(inbox) = state.notifications.get_mut(caller) else {
+        return 0;
+    };
+    let mut changed = 0;
+    for n in inbox.iter_mut().filter(|n| !n.read && ids.is_none_or(|ids| ids.contains(&n.id))) {
+        n.read = true;
+        changed += 1;
+    }
+    changed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::GuardianState;
+    use std::collections::BTreeMap;
+
+    fn owner_principal() -> Principal {
+        Principal::from_text("rdmx6-jaaaa-aaaaa-aaadq-cai").unwrap()
+    }
+
+    fn guardian1_principal() -> Principal {
+        Principal::from_text("rrkah-fqaaa-aaaaa-aaaaq-cai").unwrap()
+    }
+
+    fn guardian2_principal() -> Principal {
+        Principal::from_text("ryjl3-tyaaa-aaaaa-aaaba-cai").unwrap()
+    }
+
+    fn setup_test_state_with_guardians() -> VaultStateV2 {
+        VaultStateV2 {
+            guardian_state: Some(GuardianState {
+                guardians: vec![guardian1_principal(), guardian2_principal()],
+                quorum: 2,
+                owner: owner_principal(),
+                weights: BTreeMap::new(),
+                categories: BTreeMap::new(),
+                policy: None,
+            }),
+            ..Default::default()
+        }
+    }
+
+    fn proposal(id: u64) -> NotificationKind {
+        NotificationKind::ProposalOpened { proposal_id: id }
+    }
+
+    #[test]
+    fn test_vault_events_skip_the_actor() {
+        let mut state = setup_test_state_with_guardians();
+        notify_vault(&mut state, proposal(1), Some(owner_principal()), 0);
+
+        assert!(!state.notifications.contains_key(&owner_principal()));
+        assert_eq!(notification_page(&state, &guardian1_principal(), None, 10, false).unread, 1);
+        assert_eq!(notification_page(&state, &guardian2_principal(), None, 10, false).unread, 1);
+    }
+
+    #[test]
+    fn test_pagination_and_read_state() {
+        let mut state = setup_test_state_with_guardians();
+        let owner = owner_principal();
+        for i in 0..5 {
+            deliver(&mut state, &[owner], proposal(i), i);
+        }
+
+        let first = notification_page(&state, &owner, None, 2, false);
+        assert_eq!(first.notifications.iter().map(|n| n.created_at).collect::<Vec<_>>(), vec![4, 3]);
+        let second = notification_page(&state, &owner, first.next_cursor, 2, false);
+        assert_eq!(second.notifications.iter().map(|n| n.created_at).collect::<Vec<_>>(), vec![2, 1]);
+        let last = notification_page(&state, &owner, second.next_cursor, 2, false);
+        assert_eq!(last.notifications.len(), 1);
+        assert_eq!(last.next_cursor, None);
+
+        let newest = first.notifications[0].id;
+        assert_eq!(mark_read(&mut state, &owner, Some(&[newest])), 1);
+        assert_eq!(mark_read(&mut state, &owner, Some(&[newest])), 0);
+        let unread = notification_page(&state, &owner, None, 10, true);
+        assert_eq!((unread.notifications.len(), unread.unread), (4, 4));
+        assert_eq!(mark_read(&mut state, &owner, None), 4);
+        assert_eq!(mark_read(&mut state, &guardian1_principal(), None), 0);
+    }
+
+    #[test]
+    fn test_retention_limits() {
+        let mut state = setup_test_state_with_guardians();
+        let owner = owner_principal();
+        for i in 0..(MAX_NOTIFICATIONS_PER_INBOX as u64 + 5) {
+            deliver(&mut state, &[owner], proposal(i), 1);
+        }
+        assert_eq!(state.notifications[&owner].len(), MAX_NOTIFICATIONS_PER_INBOX);
+        assert!(matches!(state.notifications[&owner][0].kind, NotificationKind::ProposalOpened { proposal_id: 5 }));
+
+        // Expired notifications are dropped on the next delivery
+        deliver(&mut state, &[owner], proposal(0), 1 + NOTIFICATION_RETENTION_NANOS);
+        assert_eq!(state.notifications[&owner].len(), 1);
+    }
+}
@@ -0,0 +1,500 @@
+use candid::{CandidType, Deserialize, Principal};
+use serde::Serialize;
+use crate::state::{with_state, with_state_mut, update_state, VaultStateV2};
+use crate::types::Icrc1Account;
+use crate::ckbtc::{execute_ckbtc_transfer, execute_retrieve_btc, execute_retrieve_btc_with_approval};
+use crate::whitelist::{ensure_whitelisted, WhitelistDestination, WhitelistPolicy};
+use crate::notifications::{notify_vault, NotificationKind};
+use crate::freeze::{ensure_not_frozen, lift_freeze};
+
+/// How long a proposal waits for guardian approval before it expires.
+const PROPOSAL_TTL_NANOS: u64 = 7 * 24 * 60 * 60 * 1_000_000_000;
+/// A proposal still `Executing` after this long was interrupted by a trap and
+/// never recorded its outcome.
+const EXECUTION_TIMEOUT_NANOS: u64 = 60 * 60 * 1_000_000_000;
+
+// Transfer Proposal Types
+#[derive(Clone, Debug, CandidType, Deserialize, Serialize)]
+pub enum ProposalAction {
+    CkbtcTransfer {
+        to: Icrc1Account,
+        amount: candid::Nat,
+        fee: Option<candid::Nat>,
+        from_subaccount: Option<Vec<u8>>,
+        memo: Option<Vec<u8>>,
+        idempotency_key: Option<String>, // the requester's key, carried to execution
+    },
+    RetrieveBtc { address: String, amount: u64 },
+    RetrieveBtcWithApproval { address: String, amount: u64, from_subaccount: Option<Vec<u8>> },
+    SetTransferThreshold { threshold: Option<u64> },
+    SetWhitelistPolicy { policy: WhitelistPolicy },
+    SetRecoveryBond { amount: u64 },
+    UnfreezeVault { frozen_until: u64 }, // the freeze guardians are voting to lift
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize, Serialize)]
+pub enum ProposalStatus {
+    Pending,
+    Executing,
+    Executed { block_index: Option<u128> },
+    Failed { reason: String },
+    Cancelled,
+    Expired,
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize, Serialize)]
+pub struct TransferProposal {
+    pub id: u64,
+    pub proposer: Principal,
+    pub action: ProposalAction,
+    pub approvals: Vec<Principal>,
+    pub status: ProposalStatus,
+    pub created_at: u64,
+    pub expires_at: u64,
+    pub executing_since: Option<u64>,
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub enum TransferOutcome {
+    Completed { block_index: u128 },
+    PendingApproval { proposal_id: u64 },
+}
+
+// Threshold Management
+/// Sets the amount above which transfers need guardian approval. Tightening the
+/// limit applies immediately; raising or removing it is itself a proposal, so a
+/// stolen owner session cannot simply switch the protection off. Returns the
+/// proposal id when the change is waiting for guardians.
+#[ic_cdk::update]
+pub fn set_transfer_approval_threshold(threshold: Option<u64>) -> Result<Option<u64>, String> {
+    let caller = ic_cdk::api::msg_caller();
+
+    let current = with_state(|state| {
+        let g = state.guardian_state.as_ref().ok_or("guardian state not initialized")?;
+        if g.owner != caller {
+            return Err("only owner can set the transfer approval threshold".to_string());
+        }
+        if threshold.is_some() && (g.guardians.is_empty() || g.quorum == 0) {
+            return Err("guardians must be configured before setting a threshold".to_string());
+        }
+        Ok(state.transfer_approval_threshold)
+    })?;
+
+    if is_tightening(current, threshold) {
+        update_state(|state| state.transfer_approval_threshold = threshold);
+        return Ok(None);
+    }
+
+    let action = ProposalAction::SetTransferThreshold { threshold };
+    let proposal_id = with_state_mut(|state| open_proposal(state, caller, action, ic_cdk::api::time()))?;
+    Ok(Some(proposal_id))
+}
+
+#[ic_cdk::query]
+pub fn get_transfer_approval_threshold() -> Option<u64> {
+    with_state(|state| state.transfer_approval_threshold)
+}
+
+// Proposal Lifecycle
+#[ic_cdk::update]
+pub async fn approve_transfer_proposal(id: u64) -> Result<ProposalStatus, String> {
+    let caller = ic_cdk::api::msg_caller();
+
+    let quorum_reached = with_state_mut(|state| {
+        record_proposal_approval(state, id, caller, ic_cdk::api::time())
+    })?;
+    if !quorum_reached {
+        return Ok(ProposalStatus::Pending);
+    }
+
+    execute_proposal(id).await
+}
+
+#[ic_cdk::update]
+pub fn cancel_transfer_proposal(id: u64) -> Result<(), String> {
+    let caller = ic_cdk::api::msg_caller();
+    with_state_mut(|state| cancel_proposal(state, id, caller))
+}
+
+#[ic_cdk::query]
+pub fn get_transfer_proposals() -> Vec<TransferProposal> {
+    let caller = ic_cdk::api::msg_caller();
+    let now = ic_cdk::api::time();
+
+    with_state(|state| {
+        let is_member = state.guardian_state.as_ref()
+            .map(|g| caller == g.owner || g.guardians.contains(&caller))
+            .unwrap_or(false);
+
+        state.transfer_proposals.iter()
+            .filter(|p| is_member || p.proposer == caller)
+            .cloned()
+            .map(|mut p| {
+                if matches!(p.status, ProposalStatus::Pending) && now >= p.expires_at {
+                    p.status = ProposalStatus::Expired;
+                }
+                if is_stalled(&p, now) {
+                    p.status = stalled_status();
+                }
+                p
+            })
+            .collect()
+    })
+}
+
+async fn execute_proposal(id: u64) -> Result<ProposalStatus, String> {
+    let proposal = with_state(|state| state.transfer_proposals.iter().find(|p| p.id == id).cloned())
+        .ok_or("proposal not found")?;
+
+    let destination_check = with_state(|state| check_payout(state, &proposal.action, ic_cdk::api::time()));
+    let result = match destination_check {
+        Ok(()) => run_proposal_action(id, proposal.proposer, proposal.action).await,
+        Err(reason) => Err(reason),
+    };
+
+    let status = match result {
+        Ok(block_index) => ProposalStatus::Executed { block_index },
+        Err(reason) => ProposalStatus::Failed { reason },
+    };
+    update_state(|state| {
+        if let Some(p) = state.transfer_proposals.iter_mut().find(|p| p.id == id) {
+            p.status = status.clone();
+        }
+    });
+
+    Ok(status)
+}
+
+async fn run_proposal_action(id: u64, proposer: Principal, action: ProposalAction) -> Result<Option<u128>, String> {
+    match action {
+        ProposalAction::CkbtcTransfer { to, amount, fee, from_subaccount, memo, idempotency_key } => {
+            // Keyed by the requester's key, or else by proposal id, so a retried
+            // execution is deduplicated by the ledger
+            let idempotency_key = idempotency_key.or_else(|| Some(format!("transfer-proposal-{}", id)));
+            execute_ckbtc_transfer(proposer, to, amount, fee, from_subaccount, memo, idempotency_key)
+                .await
+                .map(Some)
+        }
+        ProposalAction::RetrieveBtc { address, amount } => {
+            execute_retrieve_btc(address, amount).await.map(|b| Some(b.into()))
+        }
+        ProposalAction::RetrieveBtcWithApproval { address, amount, from_subaccount } => {
+            execute_retrieve_btc_with_approval(address, amount, from_subaccount)
+                .await
+                .map(|b| Some(b.into()))
+        }
+        ProposalAction::SetTransferThreshold { threshold } => {
+            update_state(|state| state.transfer_approval_threshold = threshold);
+            Ok(None)
+        }
+        ProposalAction::SetWhitelistPolicy { policy } => {
+            update_state(|state| state.whitelist_policy = policy);
+            Ok(None)
+        }
+        ProposalAction::SetRecoveryBond { amount } => {
+            update_state(|state| state.recovery_bond = amount);
+            Ok(None)
+        }
+        ProposalAction::UnfreezeVault { frozen_until } => {
+            with_state_mut(|state| lift_freeze(state, id, frozen_until, ic_cdk::api::time()))?;
+            Ok(None)
+        }
+    }
+}
+
+// Helper functions
+/// The destination may have been removed from the whitelist, or the vault
+/// frozen, while guardians voted.
+fn check_payout(state: &VaultStateV2, action: &ProposalAction, now: u64) -> Result<(), String> {
+    let Some(destination) = proposal_destination(action) else {
+        return Ok(());
+    };
+    ensure_not_frozen(state, now)?;
+    ensure_whitelisted(state, &destination, now)
+}
+
+fn proposal_destination(action: &ProposalAction) -> Option<WhitelistDestination> {
+    match action {
+        ProposalAction::CkbtcTransfer { to, .. } => Some(WhitelistDestination::Icrc { account: to.clone() }),
+        ProposalAction::RetrieveBtc { address, .. }
+        | ProposalAction::RetrieveBtcWithApproval { address, .. } => {
+            Some(WhitelistDestination::Bitcoin { address: address.clone() })
+        }
+        ProposalAction::SetTransferThreshold { .. }
+        | ProposalAction::SetWhitelistPolicy { .. }
+        | ProposalAction::SetRecoveryBond { .. }
+        | ProposalAction::UnfreezeVault { .. } => None,
+    }
+}
+
+pub(crate) fn requires_guardian_approval(state: &VaultStateV2, amount: &candid::Nat) -> bool {
+    use num_traits::cast::ToPrimitive;
+    state.transfer_approval_threshold
+        // Amounts too large for u64 are always above the threshold
+        .map(|threshold| amount.0.to_u64().is_none_or(|a| a > threshold))
+        .unwrap_or(false)
+}
+
+/// Opens a proposal for guardians to approve. Only the vault owner proposes.
+pub(crate) fn open_proposal(
+    state: &mut VaultStateV2,
+    proposer: Principal,
+    action: ProposalAction,
+    now: u64,
+) -> Result<u64, String> {
+    let g = state.guardian_state.as_ref().ok_or("guardian state not initialized")?;
+    if g.owner != proposer {
+        return Err("only owner can open proposals".to_string());
+    }
+    let id = state.next_proposal_id;
+    state.next_proposal_id += 1;
+    state.transfer_proposals.push(TransferProposal {
+        id,
+        proposer,
+        action,
+        approvals: vec![],
+        status: ProposalStatus::Pending,
+        created_at: now,
+        expires_at: now.saturating_add(PROPOSAL_TTL_NANOS),
+        executing_since: None,
+    });
+    notify_vault(state, NotificationKind::ProposalOpened { proposal_id: id }, Some(proposer), now);
+    Ok(id)
+}
+
+/// Marks proposals whose execution trapped before recording an outcome as
+/// failed, so they stop holding their idempotency key. A late reply still
+/// overwrites this with the real outcome.
+pub(crate) fn fail_stalled_proposals(state: &mut VaultStateV2, now: u64) {
+    for p in state.transfer_proposals.iter_mut().filter(|p| is_stalled(p, now)) {
+        p.status = stalled_status();
+    }
+}
+
+fn is_stalled(p: &TransferProposal, now: u64) -> bool {
+    matches!(p.status, ProposalStatus::Executing)
+        && p.executing_since.is_some_and(|t| now.saturating_sub(t) >= EXECUTION_TIMEOUT_NANOS)
+}
+
+fn stalled_status() -> ProposalStatus {
+    ProposalStatus::Failed { reason: "execution was interrupted; check the ledger before proposing again".to_string() }
+}
+
+/// Cancels the proposals still waiting for approval. Called when a recovery
+/// hands the vault to a new owner.
+pub(crate) fn cancel_open_proposals(state: &mut VaultStateV2) {
+    for p in state.transfer_proposals.iter_mut().filter(|p| matches!(p.status, ProposalStatus::Pending)) {
+        p.status = ProposalStatus::Cancelled;
+    }
+}
+
+/// Records a guardian approval and returns whether quorum was reached. A
+/// proposal reaching quorum moves to `Executing` so it cannot run twice.
+fn record_proposal_approval(
+    state: &mut VaultStateV2,
+    id: u64,
+    caller: Principal,
+    now: u64,
+) -> Result<bool, String> {
+    let g = state.guardian_state.clone().ok_or("guardian state not initialized")?;
+    if !g.guardians.contains(&caller) {
+        return Err("only guardian may approve proposals".to_string());
+    }
+    fail_stalled_proposals(state, now);
+
+    let proposal = state.transfer_proposals.iter_mut()
+        .find(|p| p.id == id)
+        .ok_or("proposal not found")?;
+    if !matches!(proposal.status, ProposalStatus::Pending) {
+        return Err("proposal is not pending".to_string());
+    }
+    if now >= proposal.expires_at {
+        proposal.status = ProposalStatus::Expired;
+        return Err("proposal expired".to_string());
+    }
+
+    if !proposal.approvals.contains(&caller) {
+        proposal.approvals.push(caller);
+    }
+    if g.quorum_reached(&proposal.approvals) {
+        // A proposal opened by a previous owner must not outlive a recovery
+        if proposal.proposer != g.owner {
+            proposal.status = ProposalStatus::Cancelled;
+            return Err("proposal was opened by a previous owner".to_string());
+        }
+        proposal.status = ProposalStatus::Executing;
+        proposal.executing_since = Some(now);
+        return Ok(true);
+    }
+    Ok(false)
+}
+
+fn cancel_proposal(state: &mut VaultStateV2, id: u64, caller: Principal) -> Result<(), String> {
+    let owner = state.guardian_state.as_ref().map(|g| g.owner);
+    let proposal = state.transfer_proposals.iter_mut()
+        .find(|p| p.id == id)
+        .ok_or("proposal not found")?;
+    if Some(caller) != owner && caller != proposal.proposer {
+        return Err("only owner or proposer may cancel".to_string());
+    }
+    if !matches!(proposal.status, ProposalStatus::Pending) {
+        return Err("proposal is not pending".to_string());
+    }
+    proposal.status = ProposalStatus::Cancelled;
+    Ok(())
+}
+
+fn is_tightening(current: Option<u64>, new: Option<u64>) -> bool {
+    match (current, new) {
+        (_, None) => current.is_none(),
+        (None, Some(_)) => true,
+        (Some(current), Some(new)) => new <= current,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::GuardianState;
+    use std::collections::BTreeMap;
+
+    fn owner_principal() -> Principal {
+        Principal::from_text("rdmx6-jaaaa-aaaaa-aaadq-cai").unwrap()
+    }
+
+    fn guardian1_principal() -> Principal {
+        Principal::from_text("rrkah-fqaaa-aaaaa-aaaaq-cai").unwrap()
+    }
+
+    fn guardian2_principal() -> Principal {
+        Principal::from_text("ryjl3-tyaaa-aaaaa-aaaba-cai").unwrap()
+    }
+
+    fn setup_test_state_with_threshold() -> VaultStateV2 {
+        VaultStateV2 {
+            guardian_state: Some(GuardianState {
+                guardians: vec![guardian1_principal(), guardian2_principal()],
+                quorum: 2,
+                owner: owner_principal(),
+                weights: BTreeMap::new(),
+                categories: BTreeMap::new(),
+                policy: None,
+            }),
+            transfer_approval_threshold: Some(100_000),
+            ..Default::default()
+        }
+    }
+
+    fn retrieve_action(amount: u64) -> ProposalAction {
+        ProposalAction::RetrieveBtc { address: "bc1qtest".to_string(), amount }
+    }
+
+    #[test]
+    fn test_threshold_detection() {
+        let mut state = setup_test_state_with_threshold();
+        assert!(!requires_guardian_approval(&state, &candid::Nat::from(100_000u64)));
+        assert!(requires_guardian_approval(&state, &candid::Nat::from(100_001u64)));
+
+        state.transfer_approval_threshold = None;
+        assert!(!requires_guardian_approval(&state, &candid::Nat::from(u64::MAX)));
+    }
+
+    #[test]
+    fn test_proposal_reaches_quorum() {
+        let mut state = setup_test_state_with_threshold();
+        let id = open_proposal(&mut state, owner_principal(), retrieve_action(500_000), 0).unwrap();
+
+        assert_eq!(record_proposal_approval(&mut state, id, guardian1_principal(), 1), Ok(false));
+        // Duplicate approvals are not counted twice
+        assert_eq!(record_proposal_approval(&mut state, id, guardian1_principal(), 2), Ok(false));
+        assert_eq!(record_proposal_approval(&mut state, id, guardian2_principal(), 3), Ok(true));
+        assert!(matches!(state.transfer_proposals[0].status, ProposalStatus::Executing));
+
+        // Executing proposals cannot be approved again
+        assert!(record_proposal_approval(&mut state, id, guardian1_principal(), 4).is_err());
+    }
+
+    #[test]
+    fn test_proposal_approval_authorization_and_expiry() {
+        let mut state = setup_test_state_with_threshold();
+        let id = open_proposal(&mut state, owner_principal(), retrieve_action(500_000), 0).unwrap();
+
+        assert!(record_proposal_approval(&mut state, id, owner_principal(), 1).is_err());
+
+        let result = record_proposal_approval(&mut state, id, guardian1_principal(), PROPOSAL_TTL_NANOS);
+        assert_eq!(result, Err("proposal expired".to_string()));
+        assert!(matches!(state.transfer_proposals[0].status, ProposalStatus::Expired));
+    }
+
+    #[test]
+    fn test_interrupted_execution_is_marked_failed() {
+        let mut state = setup_test_state_with_threshold();
+        let id = open_proposal(&mut state, owner_principal(), retrieve_action(500_000), 0).unwrap();
+        record_proposal_approval(&mut state, id, guardian1_principal(), 1).unwrap();
+        record_proposal_approval(&mut state, id, guardian2_principal(), 2).unwrap();
+
+        // The execution trapped and never wrote its outcome back
+        fail_stalled_proposals(&mut state, 2 + EXECUTION_TIMEOUT_NANOS - 1);
+        assert!(matches!(state.transfer_proposals[0].status, ProposalStatus::Executing));
+        fail_stalled_proposals(&mut state, 2 + EXECUTION_TIMEOUT_NANOS);
+        assert!(matches!(state.transfer_proposals[0].status, ProposalStatus::Failed { .. }));
+    }
+
+    #[test]
+    fn test_previous_owners_proposal_does_not_execute() {
+        let mut state = setup_test_state_with_threshold();
+        let id = open_proposal(&mut state, owner_principal(), retrieve_action(500_000), 0).unwrap();
+        record_proposal_approval(&mut state, id, guardian1_principal(), 1).unwrap();
+        state.guardian_state.as_mut().unwrap().owner = Principal::from_text("renrk-eyaaa-aaaaa-aaada-cai").unwrap();
+
+        let result = record_proposal_approval(&mut state, id, guardian2_principal(), 2);
+        assert_eq!(result, Err("proposal was opened by a previous owner".to_string()));
+        assert!(matches!(state.transfer_proposals[0].status, ProposalStatus::Cancelled));
+    }
+
+    #[test]
+    fn test_proposal_reaching_quorum_during_a_freeze_does_not_pay_out() {
+        let mut state = setup_test_state_with_threshold();
+        let action = retrieve_action(500_000);
+        let id = open_proposal(&mut state, owner_principal(), action.clone(), 0).unwrap();
+        state.frozen_until = 100;
+
+        record_proposal_approval(&mut state, id, guardian1_principal(), 1).unwrap();
+        assert_eq!(record_proposal_approval(&mut state, id, guardian2_principal(), 2), Ok(true));
+        assert_eq!(check_payout(&state, &action, 2), Err("vault is frozen by a guardian".to_string()));
+
+        // Settings changes still go through, and payouts resume once the freeze ends
+        assert!(check_payout(&state, &ProposalAction::SetTransferThreshold { threshold: None }, 2).is_ok());
+        assert!(check_payout(&state, &action, 100).is_ok());
+    }
+
+    #[test]
+    fn test_only_owner_opens_proposals() {
+        let mut state = setup_test_state_with_threshold();
+        let result = open_proposal(&mut state, guardian1_principal(), retrieve_action(500_000), 0);
+
+        assert_eq!(result, Err("only owner can open proposals".to_string()));
+        assert!(state.transfer_proposals.is_empty());
+        assert_eq!(state.next_proposal_id, 1);
+    }
+
+    #[test]
+    fn test_proposal_cancellation() {
+        let mut state = setup_test_state_with_threshold();
+        let id = open_proposal(&mut state, owner_principal(), retrieve_action(500_000), 0).unwrap();
+
+        assert!(cancel_proposal(&mut state, id, guardian1_principal()).is_err());
+        assert!(cancel_proposal(&mut state, id, owner_principal()).is_ok());
+        assert!(matches!(state.transfer_proposals[0].status, ProposalStatus::Cancelled));
+        assert!(record_proposal_approval(&mut state, id, guardian1_principal(), 1).is_err());
+    }
+
+    #[test]
+    fn test_threshold_tightening() {
+        assert!(is_tightening(None, Some(10)));
+        assert!(is_tightening(Some(10), Some(5)));
+        assert!(is_tightening(None, None));
+        assert!(!is_tightening(Some(10), Some(20)));
+        assert!(!is_tightening(Some(10), None));
+    }
+}
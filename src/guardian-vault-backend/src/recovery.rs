@@ -1,88 +1,304 @@
 use candid::Principal;
-use crate::state::{with_state, update_state};
-use crate::types::RecoveryRequest;
+use crate::canister_call::notify;
+use crate::ckbtc::{collect_with_allowance, derive_subaccount_from_seed, release_from_subaccount, vault_account};
+use crate::guardians::{is_canister_principal, membership_proof};
+use crate::notifications::{deliver, notify_vault, NotificationKind};
+use crate::proposals::{cancel_open_proposals, open_proposal, ProposalAction};
+use crate::state::{with_state, with_state_mut, update_state, VaultStateV2};
+use crate::vault_id::{check_rebind_target, rebind_vault};
+use crate::types::{
+    BondStatus, CommitRevealRound, Icrc1Account, MembershipProof, RecoveryBond, RecoveryProgress, RecoveryRejection,
+    RecoveryRequest, RecoveryStatus, RecoveryTally, RecoveryVoteRequest, RecoveryVoting, VoteCommitment,
+};
+use sha2::{Sha256, Digest};
 
+/// How long a request may gather approvals before it expires.
+pub(crate) const RECOVERY_TTL_NANOS: u64 = 14 * 24 * 60 * 60 * 1_000_000_000;
+/// Delay between reaching quorum and the ownership change, during which the
+/// current owner can still reject the recovery.
+const RECOVERY_TIME_LOCK_NANOS: u64 = 48 * 60 * 60 * 1_000_000_000;
+/// A draft whose bond never arrived stops blocking new requests after this long.
+const RECOVERY_DRAFT_TTL_NANOS: u64 = 10 * 60 * 1_000_000_000;
+const MAX_LINKED_DEVICES: usize = 4;
+/// Seed of the vault subaccount that holds recovery bonds.
+const RECOVERY_BOND_SEED: &str = "recovery_bonds";
+const MAX_REJECTION_REASON_LEN: usize = 256;
+const MIN_VOTING_WINDOW_NANOS: u64 = 60 * 60 * 1_000_000_000;
+/// Domain separator for commit-reveal vote commitments.
+const VOTE_COMMITMENT_DOMAIN: &[u8] = b"guardian_vault_recovery_vote_v1";
+const REVEALED_REJECTION_REASON: &str = "rejected in a commit-reveal vote";
+
+// Recovery lifecycle:
+//   Draft -> Open -> QuorumReached -> TimeLocked -> Executed
+// with Cancelled (by the requester), Rejected (by the owner, or once guardian
+// rejections leave the threshold out of reach) and Expired (past `expires_at`
+// before the time lock) as the other terminal states.
+// A request stays in Draft while the requester's bond is collected.
+// Guardian approvals and VetKD share submissions both count toward the same
+// quorum. A request that reached quorum waits there until the new owner, and
+// any linked devices, accept the takeover; only then does the time lock start.
+//
+// Under commit-reveal voting, guardians commit vote hashes while the commit
+// window is open and reveal them afterwards. Only revealed votes count, and a
+// request that has not reached quorum when the reveal window ends expires;
+// commitments that were never revealed are reported in the tally.
+
+/// Opens a recovery to `new_owner`. `linked_devices` are further principals
+/// the new owner controls, such as other device identities, which must also
+/// confirm before the takeover can go ahead. Only one request may be active
+/// at a time.
+///
+/// When the vault sets a recovery bond, the caller must first approve this
+/// canister (ICRC-2) for the bond plus the ledger fee. The bond is forfeited
+/// to the vault if guardian rejections close the request, and refunded
+/// however else it ends, including an owner veto.
 #[ic_cdk::update]
-pub fn request_recovery(new_owner: Principal) -> Result<u64, String> {
-    let caller = ic_cdk::caller();
-    
-    // Validate caller permissions and get next ID
-    let id = with_state(|state| {
+pub async fn request_recovery(new_owner: Principal, linked_devices: Option<Vec<Principal>>) -> Result<u64, String> {
+    let caller = ic_cdk::api::msg_caller();
+    let devices = linked_devices.unwrap_or_default();
+    let id = with_state_mut(|state| create_recovery(state, caller, new_owner, devices, ic_cdk::api::time()))?;
+    if let Some(amount) = with_state(|state| pending_bond(state, id)) {
+        let memo = Some(bond_key(id).into_bytes());
+        let collected = collect_with_allowance(caller, derive_subaccount_from_seed(RECOVERY_BOND_SEED), amount, memo).await;
+        with_state_mut(|state| finish_bond_collection(state, id, collected, ic_cdk::api::time()))?;
+    }
+    notify_guardian_canisters(id, new_owner);
+    Ok(id)
+}
+
+/// Sets the ckBTC bond locked per recovery request. Lowering it applies
+/// immediately; raising it could price guardians out of recovering the vault,
+/// so it needs guardian approval when the vault has guardians. Returns the
+/// proposal id when the change is pending.
+#[ic_cdk::update]
+pub fn set_recovery_bond(amount: u64) -> Result<Option<u64>, String> {
+    let caller = ic_cdk::api::msg_caller();
+
+    let (current, has_guardians) = with_state(|state| {
         let g = state.guardian_state.as_ref().ok_or("guardian state not initialized")?;
-        if caller != g.owner && !g.guardians.contains(&caller) {
-            return Err("only owner or guardian may open recovery".to_string());
+        if g.owner != caller {
+            return Err("only owner can set the recovery bond".to_string());
         }
-        Ok(state.next_recovery_id)
+        Ok((state.recovery_bond, !g.guardians.is_empty() && g.quorum > 0))
     })?;
-    
-    // Update state with new recovery request
-    update_state(|state| {
-        let recovery_id = state.next_recovery_id;
-        state.next_recovery_id += 1;
-        state.recovery_reqs.push(RecoveryRequest { 
-            id: recovery_id, 
-            new_owner, 
-            approvals: vec![], 
-            open: true 
-        });
-    });
-    
-    Ok(id)
+
+    if !has_guardians || amount <= current {
+        update_state(|state| state.recovery_bond = amount);
+        return Ok(None);
+    }
+
+    let action = ProposalAction::SetRecoveryBond { amount };
+    let proposal_id = with_state_mut(|state| open_proposal(state, caller, action, ic_cdk::api::time()))?;
+    Ok(Some(proposal_id))
 }
 
+#[ic_cdk::query]
+pub fn get_recovery_bond() -> u64 {
+    with_state(|state| state.recovery_bond)
+}
+
+/// Pays out the bond of a closed request. Bonds settle on their own when a
+/// request executes, is cancelled or is rejected; this retries a failed payout
+/// and settles requests that closed by expiring.
 #[ic_cdk::update]
-pub fn approve_recovery(id: u64) -> Result<bool, String> {
-    let caller = ic_cdk::caller();
-    
-    // Validate caller is a guardian and get quorum requirement
-    let (quorum, owner) = with_state(|state| {
+pub async fn settle_recovery_bond(id: u64) -> Result<Option<RecoveryBond>, String> {
+    settle_bond(id).await
+}
+
+/// Confirms a pending takeover. Must be called by the new owner and by each
+/// linked device; until all have confirmed the recovery stays pending.
+#[ic_cdk::update]
+pub fn accept_recovered_ownership(id: u64) -> Result<RecoveryStatus, String> {
+    let caller = ic_cdk::api::msg_caller();
+    with_state_mut(|state| confirm_takeover(state, caller, id, ic_cdk::api::time()))
+}
+
+#[ic_cdk::update]
+pub fn approve_recovery(id: u64) -> Result<RecoveryStatus, String> {
+    let caller = ic_cdk::api::msg_caller();
+    with_state_mut(|state| record_recovery_approval(state, caller, id, ic_cdk::api::time()))
+}
+
+/// Inter-canister variant of `approve_recovery` for canister guardians such
+/// as DAOs. The vote names the new owner it was cast for, so a decision taken
+/// for one request cannot be applied to another.
+#[ic_cdk::update]
+pub fn approve_recovery_from_canister(id: u64, new_owner: Principal) -> Result<RecoveryStatus, String> {
+    let caller = ic_cdk::api::msg_caller();
+    with_state_mut(|state| {
+        check_canister_vote(state, caller, id, new_owner)?;
+        record_recovery_approval(state, caller, id, ic_cdk::api::time())
+    })
+}
+
+/// Votes against an open recovery. Once the guardians who have not rejected
+/// can no longer reach the threshold, the request is closed as `Rejected`.
+#[ic_cdk::update]
+pub async fn reject_recovery(id: u64, reason: String) -> Result<RecoveryStatus, String> {
+    let caller = ic_cdk::api::msg_caller();
+    let status = with_state_mut(|state| record_recovery_rejection(state, caller, id, reason, ic_cdk::api::time()))?;
+    if status == RecoveryStatus::Rejected {
+        settle_closed_bond(id).await;
+    }
+    Ok(status)
+}
+
+/// Sets how guardians vote on recovery requests opened from now on.
+#[ic_cdk::update]
+pub fn set_recovery_voting(mode: RecoveryVoting) -> Result<(), String> {
+    let caller = ic_cdk::api::msg_caller();
+    with_state_mut(|state| {
         let g = state.guardian_state.as_ref().ok_or("guardian state not initialized")?;
-        if !g.guardians.contains(&caller) { 
-            return Err("only guardian may approve".to_string()); 
+        if g.owner != caller {
+            return Err("only owner can set the recovery voting mode".to_string());
         }
-        Ok((g.quorum, g.owner))
+        validate_voting(&mode)?;
+        state.recovery_voting = mode;
+        Ok(())
+    })
+}
+
+#[ic_cdk::query]
+pub fn get_recovery_voting() -> RecoveryVoting {
+    with_state(|state| state.recovery_voting.clone())
+}
+
+/// Commits a hidden vote on a commit-reveal recovery. The commitment is
+/// `sha256(domain || recovery_id (8 bytes, big-endian) || guardian principal
+/// length (1 byte) || guardian principal || 1 for approve or 0 for reject ||
+/// salt)` with domain `guardian_vault_recovery_vote_v1`. Committing again
+/// before the window closes replaces the earlier commitment.
+#[ic_cdk::update]
+pub fn commit_recovery_vote(id: u64, commitment: Vec<u8>) -> Result<(), String> {
+    let caller = ic_cdk::api::msg_caller();
+    with_state_mut(|state| commit_vote(state, caller, id, commitment, ic_cdk::api::time()))
+}
+
+/// Reveals a committed vote once the commit window has closed. `reason` is
+/// recorded for rejections.
+#[ic_cdk::update]
+pub async fn reveal_recovery_vote(
+    id: u64,
+    approve: bool,
+    salt: Vec<u8>,
+    reason: Option<String>,
+) -> Result<RecoveryStatus, String> {
+    let caller = ic_cdk::api::msg_caller();
+    let status = with_state_mut(|state| reveal_vote(state, caller, id, approve, &salt, reason, ic_cdk::api::time()))?;
+    if status == RecoveryStatus::Rejected {
+        settle_closed_bond(id).await;
+    }
+    Ok(status)
+}
+
+/// Inter-canister variant of `reject_recovery`, bound to the new owner the
+/// same way as `approve_recovery_from_canister`.
+#[ic_cdk::update]
+pub async fn reject_recovery_from_canister(id: u64, new_owner: Principal, reason: String) -> Result<RecoveryStatus, String> {
+    let caller = ic_cdk::api::msg_caller();
+    let status = with_state_mut(|state| {
+        check_canister_vote(state, caller, id, new_owner)?;
+        record_recovery_rejection(state, caller, id, reason, ic_cdk::api::time())
     })?;
-    
-    // Process approval and check if quorum met
-    let mut recovery_completed = false;
-    let mut error_msg: Option<String> = None;
-    
-    update_state(|state| {
-        if let Some(req) = state.recovery_reqs.iter_mut().find(|r| r.id == id && r.open) {
-            if !req.approvals.contains(&caller) { 
-                req.approvals.push(caller); 
-            }
-            
-            if (req.approvals.len() as u8) >= quorum {
-                // Transfer ownership
-                if let Some(g) = state.guardian_state.as_mut() {
-                    if g.owner == owner { 
-                        g.owner = req.new_owner; 
-                    }
-                }
-                req.open = false;
-                recovery_completed = true;
-            }
-        } else {
-            error_msg = Some("recovery request not found or closed".to_string());
-        }
-    });
-    
-    if let Some(err) = error_msg {
-        return Err(err);
+    if status == RecoveryStatus::Rejected {
+        settle_closed_bond(id).await;
     }
-    
-    Ok(recovery_completed)
+    Ok(status)
 }
 
+/// Hands the vault to the new owner once the time lock has passed. Callable
+/// by the new owner or any guardian.
+#[ic_cdk::update]
+pub async fn execute_recovery(id: u64) -> Result<(), String> {
+    let caller = ic_cdk::api::msg_caller();
+    with_state_mut(|state| execute_recovery_request(state, caller, id, ic_cdk::api::time()))?;
+    settle_closed_bond(id).await;
+    Ok(())
+}
+
+/// Withdraws a request. Only the principal who opened it may cancel.
+#[ic_cdk::update]
+pub async fn cancel_recovery(id: u64) -> Result<(), String> {
+    let caller = ic_cdk::api::msg_caller();
+    with_state_mut(|state| cancel_recovery_request(state, caller, id, ic_cdk::api::time()))?;
+    settle_closed_bond(id).await;
+    Ok(())
+}
+
+/// Lets an owner who still has access reject a recovery at any point before
+/// it executes.
+#[ic_cdk::update]
+pub async fn reject_recovery_as_owner(id: u64) -> Result<(), String> {
+    let caller = ic_cdk::api::msg_caller();
+    with_state_mut(|state| owner_reject_recovery(state, caller, id, ic_cdk::api::time()))?;
+    settle_closed_bond(id).await;
+    Ok(())
+}
+
+/// Visible to the owner and guardians. The prospective owner, who need not be
+/// a member, follows the request through `get_recovery_progress` instead.
 #[ic_cdk::query]
 pub fn recovery_status(id: u64) -> Option<RecoveryRequest> { 
-    with_state(|state| state.recovery_reqs.iter().find(|r| r.id == id).cloned())
+    let caller = ic_cdk::api::msg_caller();
+    with_state(|state| member_request_view(state, caller, id, ic_cdk::api::time()))
+}
+
+/// Status, vote weights and unlock time of a request, without who voted.
+/// Visible to the owner, guardians, the new owner and its linked devices.
+#[ic_cdk::query]
+pub fn get_recovery_progress(id: u64) -> Option<RecoveryProgress> {
+    let caller = ic_cdk::api::msg_caller();
+    with_state(|state| recovery_progress(state, caller, id, ic_cdk::api::time()))
+}
+
+/// Membership proofs of the guardians who approved a request. Anyone can
+/// check them against the commitments in `get_guardian_summary`.
+#[ic_cdk::query]
+pub fn get_membership_proofs(id: u64) -> Result<Vec<MembershipProof>, String> {
+    with_state(|state| {
+        let req = state.recovery_reqs.iter()
+            .find(|r| r.id == id)
+            .ok_or("recovery request not found")?;
+        Ok(req.approvals.iter().filter_map(|p| membership_proof(state, p)).collect())
+    })
 }
 
+/// Explains which guardian policy requirements a recovery request still
+/// lacks. Empty once the request's approvals satisfy the policy.
 #[ic_cdk::query]
-pub fn get_recovery_requests(caller_principal: Option<Principal>) -> Vec<RecoveryRequest> {
-    let caller = caller_principal.unwrap_or_else(|| ic_cdk::caller());
+pub fn get_unmet_recovery_requirements(id: u64) -> Result<Vec<String>, String> {
+    let caller = ic_cdk::api::msg_caller();
+    with_state(|state| {
+        let g = state.guardian_state.as_ref().ok_or("guardian state not initialized")?;
+        if caller != g.owner && !g.guardians.contains(&caller) {
+            return Err("only owner or guardian may view recovery requirements".to_string());
+        }
+        let req = state.recovery_reqs.iter()
+            .find(|r| r.id == id)
+            .ok_or("recovery request not found")?;
+        Ok(g.unmet_requirements(&req.approvals))
+    })
+}
+
+/// Approvals, rejections and undecided guardians of a request, with weights.
+#[ic_cdk::query]
+pub fn get_recovery_tally(id: u64) -> Result<RecoveryTally, String> {
+    let caller = ic_cdk::api::msg_caller();
+    with_state(|state| {
+        let g = state.guardian_state.as_ref().ok_or("guardian state not initialized")?;
+        if caller != g.owner && !g.guardians.contains(&caller) {
+            return Err("only owner or guardian may view the recovery tally".to_string());
+        }
+        let req = state.recovery_reqs.iter()
+            .find(|r| r.id == id)
+            .ok_or("recovery request not found")?;
+        Ok(recovery_tally(state, &with_lazy_expiry(req, ic_cdk::api::time())))
+    })
+}
+
+#[ic_cdk::query]
+pub fn get_recovery_requests() -> Vec<RecoveryRequest> {
+    let caller = ic_cdk::api::msg_caller();
     with_state(|state| {
         let g = match &state.guardian_state {
             Some(g) => g,
@@ -91,19 +307,718 @@ pub fn get_recovery_requests(caller_principal: Option<Principal>) -> Vec<Recover
         
         // Only return requests if caller is owner or guardian
         if caller == g.owner || g.guardians.contains(&caller) {
-            state.recovery_reqs.clone()
+            let now = ic_cdk::api::time();
+            state.recovery_reqs.iter().map(|r| with_lazy_expiry(r, now)).collect()
         } else {
             Vec::new()
         }
     })
 }
 
+// Helper functions
+pub(crate) fn create_recovery(
+    state: &mut VaultStateV2,
+    caller: Principal,
+    new_owner: Principal,
+    linked_devices: Vec<Principal>,
+    now: u64,
+) -> Result<u64, String> {
+    let g = state.guardian_state.as_ref().ok_or("guardian state not initialized")?;
+    if caller != g.owner && !g.guardians.contains(&caller) {
+        return Err("only owner or guardian may open recovery".to_string());
+    }
+    if new_owner == Principal::anonymous() || new_owner == g.owner {
+        return Err("new owner must be a principal other than the current owner".to_string());
+    }
+    if linked_devices.len() > MAX_LINKED_DEVICES {
+        return Err(format!("at most {} linked devices", MAX_LINKED_DEVICES));
+    }
+    for (i, device) in linked_devices.iter().enumerate() {
+        if *device == Principal::anonymous() || *device == new_owner || linked_devices[..i].contains(device) {
+            return Err("linked devices must be distinct principals other than the new owner".to_string());
+        }
+    }
+    check_takeover_outside_guardian_set(state, &new_owner, &linked_devices, now)?;
+    check_rebind_target(state, &new_owner)?;
+
+    // One active request per vault; stale ones make room first
+    for req in state.recovery_reqs.iter_mut().filter(|r| is_stale(r, now)) {
+        req.transition(RecoveryStatus::Expired)?;
+    }
+    if state.recovery_reqs.iter().any(|r| r.is_active()) {
+        return Err("a recovery request is already active".to_string());
+    }
+    let commit_reveal = match state.recovery_voting {
+        RecoveryVoting::Open => None,
+        RecoveryVoting::CommitReveal { commit_window_nanos, reveal_window_nanos } => Some(CommitRevealRound {
+            commit_ends_at: now.saturating_add(commit_window_nanos),
+            reveal_ends_at: now.saturating_add(commit_window_nanos).saturating_add(reveal_window_nanos),
+            commitments: vec![],
+        }),
+    };
+    let bond = (state.recovery_bond > 0).then_some(RecoveryBond {
+        payer: caller,
+        amount: state.recovery_bond,
+        collected_block: None,
+        status: BondStatus::Pending,
+        forfeit: false,
+    });
+
+    let id = state.next_recovery_id;
+    state.next_recovery_id += 1;
+    let mut req = RecoveryRequest {
+        id,
+        new_owner,
+        linked_devices,
+        confirmations: vec![],
+        requested_by: caller,
+        approvals: vec![],
+        rejections: vec![],
+        bond,
+        commit_reveal,
+        status: RecoveryStatus::Draft,
+        created_at: now,
+        expires_at: now.saturating_add(RECOVERY_TTL_NANOS),
+        unlocks_at: None,
+    };
+    let opened = req.bond.is_none();
+    if opened {
+        req.transition(RecoveryStatus::Open)?;
+    }
+    state.recovery_reqs.push(req);
+    if opened {
+        announce_request(state, id, now);
+    }
+    Ok(id)
+}
+
+fn announce_request(state: &mut VaultStateV2, id: u64, now: u64) {
+    let Some(req) = state.recovery_reqs.iter().find(|r| r.id == id) else {
+        return;
+    };
+    let kind = NotificationKind::RecoveryRequested { recovery_id: id, new_owner: req.new_owner };
+    let requester = req.requested_by;
+    notify_vault(state, kind, Some(requester), now);
+}
+
+/// Tells the new owner a request awaits their acceptance, or everyone that
+/// its time lock, during which the owner can still reject, has started.
+fn announce_progress(state: &mut VaultStateV2, id: u64, now: u64) {
+    let Some(req) = state.recovery_reqs.iter().find(|r| r.id == id) else {
+        return;
+    };
+    let new_owner = req.new_owner;
+    match (&req.status, req.unlocks_at) {
+        (RecoveryStatus::QuorumReached, _) => {
+            deliver(state, &[new_owner], NotificationKind::RecoveryAwaitingAcceptance { recovery_id: id }, now);
+        }
+        (RecoveryStatus::TimeLocked, Some(unlocks_at)) => {
+            let kind = NotificationKind::RecoveryTimeLocked { recovery_id: id, unlocks_at };
+            notify_vault(state, kind.clone(), None, now);
+            deliver(state, &[new_owner], kind, now);
+        }
+        _ => {}
+    }
+}
+
+fn pending_bond(state: &VaultStateV2, id: u64) -> Option<u64> {
+    state.recovery_reqs.iter()
+        .find(|r| r.id == id)?
+        .bond.as_ref()
+        .filter(|b| b.status == BondStatus::Pending)
+        .map(|b| b.amount)
+}
+
+/// Opens a draft once its bond is in, or cancels it if the bond could not be
+/// collected.
+fn finish_bond_collection(
+    state: &mut VaultStateV2,
+    id: u64,
+    collected: Result<u128, String>,
+    now: u64,
+) -> Result<(), String> {
+    let req = state.recovery_reqs.iter_mut()
+        .find(|r| r.id == id)
+        .ok_or("recovery request not found")?;
+    match collected {
+        Ok(block_index) => {
+            if let Some(bond) = req.bond.as_mut() {
+                bond.collected_block = Some(block_index);
+                bond.status = BondStatus::Held;
+            }
+            if req.status != RecoveryStatus::Draft {
+                // Closed while the ledger call was in flight; the bond is refunded on settlement
+                return Err(format!("recovery request is {:?}", req.status));
+            }
+            req.transition(RecoveryStatus::Open)?;
+            announce_request(state, id, now);
+            Ok(())
+        }
+        Err(e) => {
+            if req.status == RecoveryStatus::Draft {
+                req.transition(RecoveryStatus::Cancelled)?;
+            }
+            Err(format!("could not collect recovery bond: {}", e))
+        }
+    }
+}
+
+struct BondPayout {
+    to: Icrc1Account,
+    amount: u64,
+    refund: bool,
+}
+
+async fn settle_bond(id: u64) -> Result<Option<RecoveryBond>, String> {
+    let vault = ic_cdk::api::canister_self();
+    let payout = with_state_mut(|state| begin_bond_settlement(state, id, vault, ic_cdk::api::time()))?;
+    if let Some(payout) = payout {
+        let subaccount = derive_subaccount_from_seed(RECOVERY_BOND_SEED);
+        let released = release_from_subaccount(bond_key(id), subaccount, payout.to, payout.amount).await;
+        with_state_mut(|state| finish_bond_settlement(state, id, payout.refund, released))?;
+    }
+    Ok(with_state(|state| state.recovery_reqs.iter().find(|r| r.id == id).and_then(|r| r.bond.clone())))
+}
+
+/// Settles right after a request closed. A failed payout stays `Held` for
+/// `settle_recovery_bond` to retry.
+pub(crate) async fn settle_closed_bond(id: u64) {
+    if let Err(e) = settle_bond(id).await {
+        ic_cdk::println!("Failed to settle recovery bond {}: {}", id, e);
+    }
+}
+
+/// Claims a held bond of a closed request for payout: refunded to the payer
+/// unless guardian rejections closed the request, in which case it goes to the vault.
+fn begin_bond_settlement(
+    state: &mut VaultStateV2,
+    id: u64,
+    vault: Principal,
+    now: u64,
+) -> Result<Option<BondPayout>, String> {
+    let owner = state.guardian_state.as_ref().ok_or("guardian state not initialized")?.owner;
+    let forfeit_to = vault_account(state, &owner, vault, None);
+    let req = state.recovery_reqs.iter_mut()
+        .find(|r| r.id == id)
+        .ok_or("recovery request not found")?;
+    if is_stale(req, now) {
+        req.transition(RecoveryStatus::Expired)?;
+    }
+    if req.is_active() {
+        return Err("recovery request is still active".to_string());
+    }
+    let Some(bond) = req.bond.as_mut().filter(|b| b.status == BondStatus::Held) else {
+        return Ok(None);
+    };
+    let refund = !bond.forfeit;
+    bond.status = BondStatus::Settling;
+    let to = if refund { Icrc1Account { owner: bond.payer, subaccount: None } } else { forfeit_to };
+    Ok(Some(BondPayout { to, amount: bond.amount, refund }))
+}
+
+fn finish_bond_settlement(
+    state: &mut VaultStateV2,
+    id: u64,
+    refund: bool,
+    released: Result<Option<u128>, String>,
+) -> Result<(), String> {
+    let bond = state.recovery_reqs.iter_mut()
+        .find(|r| r.id == id)
+        .and_then(|r| r.bond.as_mut())
+        .ok_or("recovery bond not found")?;
+    match released {
+        Ok(block_index) if refund => bond.status = BondStatus::Refunded { block_index },
+        Ok(block_index) => bond.status = BondStatus::Forfeited { block_index },
+        Err(e) => {
+            bond.status = BondStatus::Held;
+            return Err(format!("could not settle recovery bond: {}", e));
+        }
+    }
+    Ok(())
+}
+
+fn bond_key(id: u64) -> String {
+    format!("recovery-bond-{}", id)
+}
+
+/// Looks up a request, first moving it to `Expired` if its window has lapsed.
+fn active_request_mut(state: &mut VaultStateV2, id: u64, now: u64) -> Result<&mut RecoveryRequest, String> {
+    let req = state.recovery_reqs.iter_mut()
+        .find(|r| r.id == id)
+        .ok_or("recovery request not found")?;
+    if is_stale(req, now) {
+        req.transition(RecoveryStatus::Expired)?;
+    }
+    if !req.is_active() {
+        return Err(format!("recovery request is {:?}", req.status));
+    }
+    Ok(req)
+}
+
+fn is_stale(req: &RecoveryRequest, now: u64) -> bool {
+    match req.status {
+        RecoveryStatus::Draft => now >= req.created_at.saturating_add(RECOVERY_DRAFT_TTL_NANOS),
+        RecoveryStatus::Open => {
+            now >= req.expires_at || req.commit_reveal.as_ref().is_some_and(|r| now >= r.reveal_ends_at)
+        }
+        RecoveryStatus::QuorumReached => now >= req.expires_at,
+        _ => false,
+    }
+}
+
+/// Queries cannot persist the expiry, so they report it on a copy.
+fn with_lazy_expiry(req: &RecoveryRequest, now: u64) -> RecoveryRequest {
+    let mut req = req.clone();
+    if is_stale(&req, now) {
+        req.status = RecoveryStatus::Expired;
+    }
+    req
+}
+
+pub(crate) fn record_recovery_approval(
+    state: &mut VaultStateV2,
+    caller: Principal,
+    id: u64,
+    now: u64,
+) -> Result<RecoveryStatus, String> {
+    if uses_commit_reveal(state, id) {
+        return Err("this recovery votes by commit-reveal".to_string());
+    }
+    count_approval(state, caller, id, now)
+}
+
+/// Records a guardian approval and advances the request when quorum is met.
+fn count_approval(
+    state: &mut VaultStateV2,
+    caller: Principal,
+    id: u64,
+    now: u64,
+) -> Result<RecoveryStatus, String> {
+    let g = state.guardian_state.clone().ok_or("guardian state not initialized")?;
+    if !g.guardians.contains(&caller) {
+        return Err("only guardian may approve".to_string());
+    }
+    let req = active_request_mut(state, id, now)?;
+    if req.status != RecoveryStatus::Open {
+        return Err("recovery request is no longer collecting approvals".to_string());
+    }
+    if req.rejections.iter().any(|r| r.guardian == caller) {
+        return Err("guardian already rejected this recovery".to_string());
+    }
+    if req.approvals.contains(&caller) {
+        return Ok(req.status.clone());
+    }
+    req.approvals.push(caller);
+    let quorum = g.quorum_reached(&req.approvals);
+    if quorum {
+        req.transition(RecoveryStatus::QuorumReached)?;
+        if req.is_accepted() {
+            start_time_lock(req, now)?;
+        }
+    }
+    let status = req.status.clone();
+
+    notify_vault(state, NotificationKind::RecoveryApproved { recovery_id: id, guardian: caller }, Some(caller), now);
+    if quorum {
+        announce_progress(state, id, now);
+    }
+    Ok(status)
+}
+
+fn record_recovery_rejection(
+    state: &mut VaultStateV2,
+    caller: Principal,
+    id: u64,
+    reason: String,
+    now: u64,
+) -> Result<RecoveryStatus, String> {
+    let reason = checked_reason(reason)?;
+    if uses_commit_reveal(state, id) {
+        return Err("this recovery votes by commit-reveal".to_string());
+    }
+    count_rejection(state, caller, id, reason, now)
+}
+
+fn checked_reason(reason: String) -> Result<String, String> {
+    let reason = reason.trim().to_string();
+    if reason.is_empty() || reason.len() > MAX_REJECTION_REASON_LEN {
+        return Err(format!("rejection reason must be 1 to {} bytes", MAX_REJECTION_REASON_LEN));
+    }
+    Ok(reason)
+}
+
+/// Records a guardian rejection and closes the request once the threshold is
+/// out of reach for everyone who has not rejected. Under commit-reveal, only
+/// guardians who committed a vote can still help reach it.
+fn count_rejection(
+    state: &mut VaultStateV2,
+    caller: Principal,
+    id: u64,
+    reason: String,
+    now: u64,
+) -> Result<RecoveryStatus, String> {
+    let g = state.guardian_state.clone().ok_or("guardian state not initialized")?;
+    if !g.guardians.contains(&caller) {
+        return Err("only guardian may reject".to_string());
+    }
+    let req = active_request_mut(state, id, now)?;
+    if req.status != RecoveryStatus::Open {
+        return Err("recovery request is no longer collecting votes".to_string());
+    }
+    if req.has_voted(&caller) {
+        return Err("guardian already voted on this recovery".to_string());
+    }
+    req.rejections.push(RecoveryRejection { guardian: caller, reason, rejected_at: now });
+
+    let committed: Option<Vec<Principal>> = req.commit_reveal.as_ref()
+        .filter(|r| now >= r.commit_ends_at)
+        .map(|r| r.commitments.iter().map(|c| c.guardian).collect());
+    let still_possible = g.guardians.iter().filter(|p| {
+        !req.rejections.iter().any(|r| r.guardian == **p) && committed.as_ref().is_none_or(|c| c.contains(p))
+    });
+    if !g.quorum_reached(still_possible) {
+        req.transition(RecoveryStatus::Rejected)?;
+        if let Some(bond) = req.bond.as_mut() {
+            bond.forfeit = true;
+        }
+        forget_submitted_shares(state, id);
+        return Ok(RecoveryStatus::Rejected);
+    }
+    Ok(req.status.clone())
+}
+
+fn uses_commit_reveal(state: &VaultStateV2, id: u64) -> bool {
+    state.recovery_reqs.iter().any(|r| r.id == id && r.commit_reveal.is_some())
+}
+
+fn validate_voting(mode: &RecoveryVoting) -> Result<(), String> {
+    if let RecoveryVoting::CommitReveal { commit_window_nanos, reveal_window_nanos } = mode {
+        if *commit_window_nanos < MIN_VOTING_WINDOW_NANOS || *reveal_window_nanos < MIN_VOTING_WINDOW_NANOS {
+            return Err("commit and reveal windows must each be at least an hour".to_string());
+        }
+        if commit_window_nanos.saturating_add(*reveal_window_nanos) > RECOVERY_TTL_NANOS {
+            return Err("commit and reveal windows must fit within the recovery lifetime".to_string());
+        }
+    }
+    Ok(())
+}
+
+pub(crate) fn vote_commitment(id: u64, guardian: &Principal, approve: bool, salt: &[u8]) -> Vec<u8> {
+    let guardian_bytes = guardian.as_slice();
+    let mut hasher = Sha256::new();
+    hasher.update(VOTE_COMMITMENT_DOMAIN);
+    hasher.update(id.to_be_bytes());
+    hasher.update([guardian_bytes.len() as u8]);
+    hasher.update(guardian_bytes);
+    hasher.update([approve as u8]);
+    hasher.update(salt);
+    hasher.finalize().to_vec()
+}
+
+fn commit_vote(state: &mut VaultStateV2, caller: Principal, id: u64, commitment: Vec<u8>, now: u64) -> Result<(), String> {
+    let g = state.guardian_state.as_ref().ok_or("guardian state not initialized")?;
+    if !g.guardians.contains(&caller) {
+        return Err("only guardian may vote".to_string());
+    }
+    if commitment.len() != 32 {
+        return Err("commitment must be a 32-byte hash".to_string());
+    }
+    let req = active_request_mut(state, id, now)?;
+    if req.status != RecoveryStatus::Open {
+        return Err("recovery request is not open for votes".to_string());
+    }
+    let round = req.commit_reveal.as_mut().ok_or("this recovery does not vote by commit-reveal")?;
+    if now >= round.commit_ends_at {
+        return Err("the commit window has closed".to_string());
+    }
+    match round.commitments.iter_mut().find(|c| c.guardian == caller) {
+        Some(existing) => existing.commitment = commitment,
+        None => round.commitments.push(VoteCommitment { guardian: caller, commitment, revealed: false }),
+    }
+    Ok(())
+}
+
+fn reveal_vote(
+    state: &mut VaultStateV2,
+    caller: Principal,
+    id: u64,
+    approve: bool,
+    salt: &[u8],
+    reason: Option<String>,
+    now: u64,
+) -> Result<RecoveryStatus, String> {
+    let reason = match reason {
+        Some(reason) => checked_reason(reason)?,
+        None => REVEALED_REJECTION_REASON.to_string(),
+    };
+    let req = active_request_mut(state, id, now)?;
+    let round = req.commit_reveal.as_mut().ok_or("this recovery does not vote by commit-reveal")?;
+    if now < round.commit_ends_at {
+        return Err("votes can be revealed once the commit window closes".to_string());
+    }
+    let entry = round.commitments.iter_mut()
+        .find(|c| c.guardian == caller)
+        .ok_or("no committed vote to reveal")?;
+    if entry.revealed {
+        return Err("vote already revealed".to_string());
+    }
+    if entry.commitment != vote_commitment(id, &caller, approve, salt) {
+        return Err("vote does not match the commitment".to_string());
+    }
+    entry.revealed = true;
+    if approve {
+        count_approval(state, caller, id, now)
+    } else {
+        count_rejection(state, caller, id, reason, now)
+    }
+}
+
+fn recovery_tally(state: &VaultStateV2, req: &RecoveryRequest) -> RecoveryTally {
+    let g = state.guardian_state.as_ref();
+    let weight = |ps: Vec<&Principal>| g.map(|g| g.approval_weight(ps)).unwrap_or(0);
+    let commitments = req.commit_reveal.as_ref().map(|r| r.commitments.as_slice()).unwrap_or_default();
+    RecoveryTally {
+        recovery_id: req.id,
+        status: req.status.clone(),
+        approvals: req.approvals.clone(),
+        approval_weight: weight(req.approvals.iter().collect()),
+        rejections: req.rejections.clone(),
+        rejection_weight: weight(req.rejections.iter().map(|r| &r.guardian).collect()),
+        undecided: g.map(|g| g.guardians.iter().filter(|p| !req.has_voted(p)).cloned().collect())
+            .unwrap_or_default(),
+        total_weight: g.map(|g| g.total_weight()).unwrap_or(0),
+        committed: commitments.iter().map(|c| c.guardian).collect(),
+        unrevealed: commitments.iter().filter(|c| !c.revealed).map(|c| c.guardian).collect(),
+    }
+}
+
+fn member_request_view(state: &VaultStateV2, caller: Principal, id: u64, now: u64) -> Option<RecoveryRequest> {
+    let g = state.guardian_state.as_ref()?;
+    if caller != g.owner && !g.guardians.contains(&caller) {
+        return None;
+    }
+    state.recovery_reqs.iter().find(|r| r.id == id).map(|r| with_lazy_expiry(r, now))
+}
+
+fn recovery_progress(state: &VaultStateV2, caller: Principal, id: u64, now: u64) -> Option<RecoveryProgress> {
+    let g = state.guardian_state.as_ref()?;
+    let req = state.recovery_reqs.iter().find(|r| r.id == id)?;
+    let is_member = caller == g.owner || g.guardians.contains(&caller);
+    if !is_member && caller != req.new_owner && !req.linked_devices.contains(&caller) {
+        return None;
+    }
+    let tally = recovery_tally(state, &with_lazy_expiry(req, now));
+    Some(RecoveryProgress {
+        recovery_id: id,
+        status: tally.status,
+        approval_weight: tally.approval_weight,
+        rejection_weight: tally.rejection_weight,
+        total_weight: tally.total_weight,
+        unlocks_at: req.unlocks_at,
+    })
+}
+
+fn confirm_takeover(state: &mut VaultStateV2, caller: Principal, id: u64, now: u64) -> Result<RecoveryStatus, String> {
+    let req = active_request_mut(state, id, now)?;
+    if caller != req.new_owner && !req.linked_devices.contains(&caller) {
+        return Err("only the new owner or a linked device may accept".to_string());
+    }
+    if req.status == RecoveryStatus::TimeLocked {
+        return Err("recovery already accepted".to_string());
+    }
+    if !req.confirmations.contains(&caller) {
+        req.confirmations.push(caller);
+    }
+    if req.status == RecoveryStatus::QuorumReached && req.is_accepted() {
+        start_time_lock(req, now)?;
+        announce_progress(state, id, now);
+        return Ok(RecoveryStatus::TimeLocked);
+    }
+    Ok(req.status.clone())
+}
+
+/// Stores a guardian's decrypted VetKD share; submitting one also counts as
+/// that guardian's approval.
+pub(crate) fn record_share_submission(
+    state: &mut VaultStateV2,
+    caller: Principal,
+    id: u64,
+    secret_id: &[u8],
+    share: Vec<u8>,
+    now: u64,
+) -> Result<RecoveryStatus, String> {
+    let g = state.guardian_state.as_ref().ok_or("guardian state not initialized")?;
+    if !g.guardians.contains(&caller) {
+        return Err("only guardians can submit recovery shares".to_string());
+    }
+    let req = active_request_mut(state, id, now)?;
+    if req.rejections.iter().any(|r| r.guardian == caller) {
+        return Err("guardian already rejected this recovery".to_string());
+    }
+    let status = req.status.clone();
+    let hidden_votes = req.commit_reveal.is_some();
+    let bound_secret = state.recovery_share_secrets.entry(id).or_insert_with(|| secret_id.to_vec());
+    if bound_secret.as_slice() != secret_id {
+        return Err("shares for this recovery belong to another recovery secret".to_string());
+    }
+    state.submitted_recovery_shares.entry(id).or_default().insert(caller, share);
+    // Under commit-reveal a share must not give away the guardian's vote
+    if status == RecoveryStatus::Open && !hidden_votes {
+        return record_recovery_approval(state, caller, id, now);
+    }
+    Ok(status)
+}
+
+/// Drops the shares submitted under `secret_id`'s retired epoch so guardians
+/// submit fresh ones. Shares not tied to a secret, as carried over from
+/// version 1 state, cannot be told apart and are dropped with any re-share.
+pub(crate) fn forget_shares_for_secret(state: &mut VaultStateV2, secret_id: &[u8]) {
+    let stale: Vec<u64> = state.submitted_recovery_shares.keys()
+        .filter(|id| state.recovery_share_secrets.get(*id).is_none_or(|s| s.as_slice() == secret_id))
+        .copied()
+        .collect();
+    for id in stale {
+        forget_submitted_shares(state, id);
+    }
+}
+
+fn forget_submitted_shares(state: &mut VaultStateV2, id: u64) {
+    state.submitted_recovery_shares.remove(&id);
+    state.recovery_share_secrets.remove(&id);
+}
+
+/// The owner may not be their own guardian, so neither the new owner nor a
+/// linked device may be a guardian or hold a pending invitation.
+fn check_takeover_outside_guardian_set(
+    state: &VaultStateV2,
+    new_owner: &Principal,
+    linked_devices: &[Principal],
+    now: u64,
+) -> Result<(), String> {
+    let g = state.guardian_state.as_ref().ok_or("guardian state not initialized")?;
+    let in_guardian_set = |p: &Principal| {
+        g.guardians.contains(p)
+            || state.guardian_invitations.iter().any(|i| i.guardian == *p && now < i.expires_at)
+    };
+    if in_guardian_set(new_owner) || linked_devices.iter().any(in_guardian_set) {
+        return Err("new owner and linked devices must not be guardians or invited guardians".to_string());
+    }
+    Ok(())
+}
+
+fn start_time_lock(req: &mut RecoveryRequest, now: u64) -> Result<(), String> {
+    req.transition(RecoveryStatus::TimeLocked)?;
+    req.unlocks_at = Some(now.saturating_add(RECOVERY_TIME_LOCK_NANOS));
+    Ok(())
+}
+
+pub(crate) fn execute_recovery_request(
+    state: &mut VaultStateV2,
+    caller: Principal,
+    id: u64,
+    now: u64,
+) -> Result<(), String> {
+    let g = state.guardian_state.as_ref().ok_or("guardian state not initialized")?;
+    let is_guardian = g.guardians.contains(&caller);
+    let req = active_request_mut(state, id, now)?;
+    if caller != req.new_owner && !is_guardian {
+        return Err("only the new owner or a guardian may execute recovery".to_string());
+    }
+    if req.status != RecoveryStatus::TimeLocked {
+        return Err("recovery has not reached quorum".to_string());
+    }
+    if req.unlocks_at.is_some_and(|t| now < t) {
+        return Err("recovery is still time-locked".to_string());
+    }
+    let (new_owner, linked_devices) = (req.new_owner, req.linked_devices.clone());
+    // The guardian set may have changed, or the new owner set up a vault of its
+    // own, since the request opened
+    check_takeover_outside_guardian_set(state, &new_owner, &linked_devices, now)?;
+    check_rebind_target(state, &new_owner)?;
+    active_request_mut(state, id, now)?.transition(RecoveryStatus::Executed)?;
+
+    let previous_owner = state.guardian_state.as_ref().map(|g| g.owner);
+    if let Some(g) = state.guardian_state.as_mut() {
+        g.owner = new_owner;
+    }
+    // The new owner takes over the vault's keys and subaccounts, not fresh ones
+    if let Some(previous_owner) = previous_owner {
+        rebind_vault(state, previous_owner, new_owner, id);
+    }
+    forget_submitted_shares(state, id);
+    cancel_open_proposals(state);
+    // Requests opened against the previous owner no longer apply
+    for other in state.recovery_reqs.iter_mut().filter(|r| r.id != id && r.is_active()) {
+        other.transition(RecoveryStatus::Cancelled)?;
+        state.submitted_recovery_shares.remove(&other.id);
+        state.recovery_share_secrets.remove(&other.id);
+    }
+    Ok(())
+}
+
+fn cancel_recovery_request(state: &mut VaultStateV2, caller: Principal, id: u64, now: u64) -> Result<(), String> {
+    let req = active_request_mut(state, id, now)?;
+    if req.requested_by != caller {
+        return Err("only the requester may cancel a recovery".to_string());
+    }
+    req.transition(RecoveryStatus::Cancelled)?;
+    forget_submitted_shares(state, id);
+    Ok(())
+}
+
+fn owner_reject_recovery(state: &mut VaultStateV2, caller: Principal, id: u64, now: u64) -> Result<(), String> {
+    let g = state.guardian_state.as_ref().ok_or("guardian state not initialized")?;
+    if g.owner != caller {
+        return Err("only owner can reject a recovery".to_string());
+    }
+    active_request_mut(state, id, now)?.transition(RecoveryStatus::Rejected)?;
+    forget_submitted_shares(state, id);
+    Ok(())
+}
+
+fn check_canister_vote(state: &VaultStateV2, caller: Principal, id: u64, new_owner: Principal) -> Result<(), String> {
+    if !is_canister_principal(&caller) {
+        return Err("only canister guardians may vote through the inter-canister endpoints".to_string());
+    }
+    let req = state.recovery_reqs.iter()
+        .find(|r| r.id == id && r.is_active())
+        .ok_or("recovery request not found or closed")?;
+    if req.new_owner != new_owner {
+        return Err("vote was cast for a different new owner".to_string());
+    }
+    Ok(())
+}
+
+/// Best-effort one-way calls to guardian canisters that registered a callback.
+fn notify_guardian_canisters(recovery_id: u64, new_owner: Principal) {
+    let callbacks: Vec<(Principal, String)> = with_state(|state| {
+        state.guardian_profiles.values()
+            .filter_map(|p| p.callback_method.clone().map(|m| (p.guardian, m)))
+            .collect()
+    });
+    let request = RecoveryVoteRequest {
+        vault: ic_cdk::api::canister_self(),
+        recovery_id,
+        new_owner,
+        requested_at: ic_cdk::api::time(),
+    };
+    for (guardian, method) in callbacks {
+        if let Err(code) = notify(guardian, &method, (request.clone(),)) {
+            ic_cdk::println!("Failed to notify guardian canister {}: {:?}", guardian, code);
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
     use candid::Principal;
-    use crate::state::VaultStateV1;
-    use crate::types::{GuardianState, RecoveryRequest};
+    use crate::state::VaultStateV2;
+    use crate::types::{GuardianInvitation, GuardianState};
+    use std::collections::{BTreeMap, HashMap};
+
+    const SECRET_A: &[u8] = b"secret-a";
+    const SECRET_B: &[u8] = b"secret-b";
     
     fn owner_principal() -> Principal {
         Principal::from_text("rdmx6-jaaaa-aaaaa-aaadq-cai").unwrap()
@@ -125,158 +1040,450 @@ mod tests {
         Principal::from_text("renrk-eyaaa-aaaaa-aaada-cai").unwrap()
     }
     
-    fn setup_test_state_with_guardians() -> VaultStateV1 {
-        let mut state = VaultStateV1::default();
-        state.guardian_state = Some(GuardianState {
-            guardians: vec![guardian1_principal(), guardian2_principal(), guardian3_principal()],
-            quorum: 2,
-            owner: owner_principal(),
-        });
-        state
+    fn setup_test_state_with_guardians() -> VaultStateV2 {
+        VaultStateV2 {
+            guardian_state: Some(GuardianState {
+                guardians: vec![guardian1_principal(), guardian2_principal(), guardian3_principal()],
+                quorum: 2,
+                owner: owner_principal(),
+                weights: BTreeMap::new(),
+                categories: BTreeMap::new(),
+                policy: None,
+            }),
+            ..Default::default()
+        }
+    }
+
+    fn status_of(state: &VaultStateV2, id: u64) -> RecoveryStatus {
+        state.recovery_reqs.iter().find(|r| r.id == id).unwrap().status.clone()
+    }
+
+    fn open_request(state: &mut VaultStateV2) -> u64 {
+        create_recovery(state, guardian1_principal(), new_owner_principal(), vec![], 0).unwrap()
+    }
+
+    /// Opens a request, accepts it and approves it up to the time lock.
+    fn time_locked_request(state: &mut VaultStateV2) -> u64 {
+        let id = open_request(state);
+        confirm_takeover(state, new_owner_principal(), id, 0).unwrap();
+        record_recovery_approval(state, guardian1_principal(), id, 1).unwrap();
+        record_recovery_approval(state, guardian2_principal(), id, 2).unwrap();
+        id
     }
     
     #[test]
     fn test_recovery_request_creation() {
-        let state = setup_test_state_with_guardians();
+        let mut state = setup_test_state_with_guardians();
         let new_owner = new_owner_principal();
         
-        // Test that owner can create recovery request
-        let result = simulate_create_recovery_request(&state, owner_principal(), new_owner);
-        assert!(result.is_ok());
-        
-        // Test that guardian can create recovery request
-        let result = simulate_create_recovery_request(&state, guardian1_principal(), new_owner);
-        assert!(result.is_ok());
-        
-        // Test that unauthorized principal cannot create recovery request
-        let unauthorized = Principal::anonymous(); // Use anonymous principal for testing
-        let result = simulate_create_recovery_request(&state, unauthorized, new_owner);
-        assert!(result.is_err());
+        assert!(create_recovery(&mut state, Principal::anonymous(), new_owner, vec![], 0).is_err());
+        assert!(create_recovery(&mut state, guardian1_principal(), owner_principal(), vec![], 0).is_err());
+        assert!(create_recovery(&mut state, guardian1_principal(), new_owner, vec![new_owner], 0).is_err());
+        assert!(create_recovery(&mut state, owner_principal(), new_owner, vec![], 0).is_ok());
+
+        let req = &state.recovery_reqs[0];
+        assert_eq!(req.status, RecoveryStatus::Open);
+        assert_eq!(req.requested_by, owner_principal());
+        assert_eq!(req.expires_at, RECOVERY_TTL_NANOS);
     }
     
     #[test]
     fn test_recovery_approval_quorum() {
         let mut state = setup_test_state_with_guardians();
-        let new_owner = new_owner_principal();
+        let id = open_request(&mut state);
         
-        // Create a recovery request
-        let recovery_id = 1;
-        state.recovery_reqs.push(RecoveryRequest {
-            id: recovery_id,
-            new_owner,
-            approvals: vec![],
-            open: true,
-        });
-        
-        // Test first approval (should not complete recovery)
-        let result = simulate_process_recovery_approval(&mut state, recovery_id, guardian1_principal());
-        assert!(result.is_ok());
-        assert_eq!(result.unwrap(), false); // Recovery not complete yet
-        assert_eq!(state.recovery_reqs[0].approvals.len(), 1);
-        assert!(state.recovery_reqs[0].open);
-        
-        // Test second approval (should complete recovery with quorum of 2)
-        let result = simulate_process_recovery_approval(&mut state, recovery_id, guardian2_principal());
-        assert!(result.is_ok());
-        assert_eq!(result.unwrap(), true); // Recovery complete
-        assert_eq!(state.recovery_reqs[0].approvals.len(), 2);
-        assert!(!state.recovery_reqs[0].open); // Request should be closed
-        
-        // Verify ownership transfer
-        assert_eq!(state.guardian_state.as_ref().unwrap().owner, new_owner);
+        assert_eq!(record_recovery_approval(&mut state, guardian1_principal(), id, 1).unwrap(), RecoveryStatus::Open);
+        assert_eq!(record_recovery_approval(&mut state, guardian2_principal(), id, 2).unwrap(), RecoveryStatus::QuorumReached);
+        assert!(record_recovery_approval(&mut state, guardian3_principal(), id, 3).is_err());
+
+        // Reaching quorum alone does not move ownership; the new owner must accept
+        assert_eq!(state.guardian_state.as_ref().unwrap().owner, owner_principal());
+        assert!(matches!(
+            state.notifications[&new_owner_principal()][0].kind,
+            NotificationKind::RecoveryAwaitingAcceptance { recovery_id } if recovery_id == id
+        ));
+        assert!(execute_recovery_request(&mut state, new_owner_principal(), id, 3).is_err());
+        assert_eq!(confirm_takeover(&mut state, new_owner_principal(), id, 4).unwrap(), RecoveryStatus::TimeLocked);
+        assert_eq!(state.recovery_reqs[0].unlocks_at, Some(4 + RECOVERY_TIME_LOCK_NANOS));
+        // The owner heard about the request, both approvals and the start of the veto window
+        assert_eq!(state.notifications[&owner_principal()].len(), 4);
     }
     
     #[test]
     fn test_duplicate_approval_handling() {
         let mut state = setup_test_state_with_guardians();
-        let new_owner = new_owner_principal();
+        let id = open_request(&mut state);
         
-        // Create a recovery request
-        let recovery_id = 1;
-        state.recovery_reqs.push(RecoveryRequest {
-            id: recovery_id,
-            new_owner,
-            approvals: vec![],
-            open: true,
-        });
-        
-        // First approval
-        let result = simulate_process_recovery_approval(&mut state, recovery_id, guardian1_principal());
-        assert!(result.is_ok());
+        record_recovery_approval(&mut state, guardian1_principal(), id, 1).unwrap();
+        record_recovery_approval(&mut state, guardian1_principal(), id, 2).unwrap();
         assert_eq!(state.recovery_reqs[0].approvals.len(), 1);
-        
-        // Duplicate approval from same guardian
-        let result = simulate_process_recovery_approval(&mut state, recovery_id, guardian1_principal());
-        assert!(result.is_ok());
-        assert_eq!(state.recovery_reqs[0].approvals.len(), 1); // Should remain 1
+        assert_eq!(status_of(&state, id), RecoveryStatus::Open);
     }
     
     #[test]
     fn test_unauthorized_approval() {
         let mut state = setup_test_state_with_guardians();
-        let new_owner = new_owner_principal();
+        let id = open_request(&mut state);
         
-        // Create a recovery request
-        let recovery_id = 1;
-        state.recovery_reqs.push(RecoveryRequest {
-            id: recovery_id,
-            new_owner,
-            approvals: vec![],
-            open: true,
+        assert!(record_recovery_approval(&mut state, Principal::anonymous(), id, 1).is_err());
+        assert!(record_recovery_approval(&mut state, owner_principal(), id, 1).is_err());
+        assert!(record_recovery_approval(&mut state, guardian1_principal(), 99, 1).is_err());
+    }
+
+    #[test]
+    fn test_rejections_close_doomed_request() {
+        let mut state = setup_test_state_with_guardians();
+        let id = open_request(&mut state);
+        let reject = |state: &mut VaultStateV2, g: Principal, now| {
+            record_recovery_rejection(state, g, id, "I did not hear from the owner".to_string(), now)
+        };
+
+        assert!(record_recovery_rejection(&mut state, guardian1_principal(), id, "  ".to_string(), 1).is_err());
+        assert!(reject(&mut state, owner_principal(), 1).is_err());
+        record_recovery_approval(&mut state, guardian1_principal(), id, 1).unwrap();
+        assert!(reject(&mut state, guardian1_principal(), 2).is_err());
+
+        // Two of three guardians can still reach the 2-of-3 quorum
+        assert_eq!(reject(&mut state, guardian2_principal(), 2).unwrap(), RecoveryStatus::Open);
+        assert!(reject(&mut state, guardian2_principal(), 3).is_err());
+        assert!(record_recovery_approval(&mut state, guardian2_principal(), id, 3).is_err());
+
+        let tally = recovery_tally(&state, &state.recovery_reqs[0]);
+        assert_eq!((tally.approval_weight, tally.rejection_weight, tally.total_weight), (1, 1, 3));
+        assert_eq!(tally.undecided, vec![guardian3_principal()]);
+
+        assert_eq!(reject(&mut state, guardian3_principal(), 4).unwrap(), RecoveryStatus::Rejected);
+        assert!(record_recovery_approval(&mut state, guardian1_principal(), id, 5).is_err());
+    }
+
+    #[test]
+    fn test_commit_reveal_voting() {
+        let mut state = setup_test_state_with_guardians();
+        let hour = MIN_VOTING_WINDOW_NANOS;
+        assert!(validate_voting(&RecoveryVoting::CommitReveal { commit_window_nanos: 1, reveal_window_nanos: hour }).is_err());
+        state.recovery_voting = RecoveryVoting::CommitReveal { commit_window_nanos: hour, reveal_window_nanos: hour };
+        let id = open_request(&mut state);
+        let (g1, g2, g3) = (guardian1_principal(), guardian2_principal(), guardian3_principal());
+
+        assert!(record_recovery_approval(&mut state, g1, id, 1).is_err());
+        commit_vote(&mut state, g1, id, vote_commitment(id, &g1, true, b"salt1"), 1).unwrap();
+        commit_vote(&mut state, g2, id, vote_commitment(id, &g2, true, b"salt2"), 2).unwrap();
+        commit_vote(&mut state, g3, id, vote_commitment(id, &g3, false, b"salt3"), 3).unwrap();
+        assert!(reveal_vote(&mut state, g1, id, true, b"salt1", None, 4).is_err());
+        assert!(commit_vote(&mut state, g1, id, vec![0; 32], hour).is_err());
+        assert!(state.recovery_reqs[0].approvals.is_empty());
+
+        // Reveals must match the commitment, and only revealed votes count
+        assert!(reveal_vote(&mut state, g1, id, false, b"salt1", None, hour).is_err());
+        assert_eq!(reveal_vote(&mut state, g1, id, true, b"salt1", None, hour).unwrap(), RecoveryStatus::Open);
+        assert!(reveal_vote(&mut state, g1, id, true, b"salt1", None, hour).is_err());
+        let tally = recovery_tally(&state, &state.recovery_reqs[0]);
+        assert_eq!(tally.unrevealed, vec![g2, g3]);
+        assert_eq!(reveal_vote(&mut state, g2, id, true, b"salt2", None, hour + 1).unwrap(), RecoveryStatus::QuorumReached);
+    }
+
+    #[test]
+    fn test_unrevealed_votes_let_the_request_lapse() {
+        let mut state = setup_test_state_with_guardians();
+        let hour = MIN_VOTING_WINDOW_NANOS;
+        state.recovery_voting = RecoveryVoting::CommitReveal { commit_window_nanos: hour, reveal_window_nanos: hour };
+        let (g1, g2) = (guardian1_principal(), guardian2_principal());
+
+        // Guardians who did not commit cannot help reach quorum, so one revealed rejection dooms it
+        let id = open_request(&mut state);
+        commit_vote(&mut state, g1, id, vote_commitment(id, &g1, true, b"a"), 1).unwrap();
+        commit_vote(&mut state, g2, id, vote_commitment(id, &g2, false, b"b"), 1).unwrap();
+        assert_eq!(reveal_vote(&mut state, g2, id, false, b"b", Some("not the owner".to_string()), hour).unwrap(), RecoveryStatus::Rejected);
+        assert_eq!(state.recovery_reqs[0].rejections[0].reason, "not the owner");
+
+        // A request still short of quorum when the reveal window ends expires
+        let id = open_request(&mut state);
+        commit_vote(&mut state, g1, id, vote_commitment(id, &g1, true, b"a"), 1).unwrap();
+        commit_vote(&mut state, g2, id, vote_commitment(id, &g2, true, b"b"), 1).unwrap();
+        reveal_vote(&mut state, g1, id, true, b"a", None, hour).unwrap();
+        assert!(reveal_vote(&mut state, g2, id, true, b"b", None, 2 * hour).is_err());
+        assert_eq!(status_of(&state, id), RecoveryStatus::Expired);
+    }
+
+    #[test]
+    fn test_takeover_needs_new_owner_and_linked_devices() {
+        let mut state = setup_test_state_with_guardians();
+        let device = Principal::self_authenticating(b"phone");
+        let id = create_recovery(&mut state, guardian1_principal(), new_owner_principal(), vec![device], 0).unwrap();
+
+        // Early acceptance is recorded while approvals are still coming in
+        assert!(confirm_takeover(&mut state, guardian1_principal(), id, 1).is_err());
+        assert_eq!(confirm_takeover(&mut state, new_owner_principal(), id, 1).unwrap(), RecoveryStatus::Open);
+        record_recovery_approval(&mut state, guardian1_principal(), id, 2).unwrap();
+        assert_eq!(record_recovery_approval(&mut state, guardian2_principal(), id, 3).unwrap(), RecoveryStatus::QuorumReached);
+
+        assert_eq!(confirm_takeover(&mut state, device, id, 4).unwrap(), RecoveryStatus::TimeLocked);
+        assert!(confirm_takeover(&mut state, device, id, 5).is_err());
+
+        // Without the acceptance a typo'd new owner simply lets the request expire
+        let mut state = setup_test_state_with_guardians();
+        let id = open_request(&mut state);
+        record_recovery_approval(&mut state, guardian1_principal(), id, 1).unwrap();
+        record_recovery_approval(&mut state, guardian2_principal(), id, 2).unwrap();
+        assert_eq!(with_lazy_expiry(&state.recovery_reqs[0], RECOVERY_TTL_NANOS).status, RecoveryStatus::Expired);
+    }
+
+    #[test]
+    fn test_execution_waits_for_time_lock() {
+        let mut state = setup_test_state_with_guardians();
+        let id = time_locked_request(&mut state);
+        let unlocks_at = 2 + RECOVERY_TIME_LOCK_NANOS;
+
+        assert!(execute_recovery_request(&mut state, new_owner_principal(), id, unlocks_at - 1).is_err());
+        assert!(execute_recovery_request(&mut state, Principal::anonymous(), id, unlocks_at).is_err());
+        execute_recovery_request(&mut state, new_owner_principal(), id, unlocks_at).unwrap();
+
+        assert_eq!(status_of(&state, id), RecoveryStatus::Executed);
+        assert_eq!(state.guardian_state.as_ref().unwrap().owner, new_owner_principal());
+        assert_eq!(crate::vault_id::vault_id_of(&state, &new_owner_principal()), owner_principal().as_slice().to_vec());
+        assert!(execute_recovery_request(&mut state, new_owner_principal(), id, unlocks_at).is_err());
+    }
+
+    #[test]
+    fn test_recovery_cancels_the_previous_owners_proposals() {
+        let mut state = setup_test_state_with_guardians();
+        let action = ProposalAction::RetrieveBtc { address: "bc1qtest".to_string(), amount: 500_000 };
+        open_proposal(&mut state, owner_principal(), action, 0).unwrap();
+        let id = time_locked_request(&mut state);
+
+        execute_recovery_request(&mut state, new_owner_principal(), id, 2 + RECOVERY_TIME_LOCK_NANOS).unwrap();
+        assert!(matches!(state.transfer_proposals[0].status, crate::proposals::ProposalStatus::Cancelled));
+    }
+
+    #[test]
+    fn test_guardian_cannot_be_named_new_owner() {
+        let mut state = setup_test_state_with_guardians();
+        let device = Principal::from_text("r7inp-6aaaa-aaaaa-aaabq-cai").unwrap();
+        assert!(create_recovery(&mut state, guardian3_principal(), guardian3_principal(), vec![], 0).is_err());
+        assert!(create_recovery(&mut state, guardian1_principal(), new_owner_principal(), vec![guardian2_principal()], 0).is_err());
+
+        state.guardian_invitations.push(GuardianInvitation {
+            guardian: device,
+            weight: 1,
+            code_hash: vec![],
+            invited_at: 0,
+            expires_at: 10,
         });
-        
-        // Test approval from non-guardian
-        let unauthorized = Principal::anonymous(); // Use anonymous principal for testing
-        let result = simulate_process_recovery_approval(&mut state, recovery_id, unauthorized);
-        assert!(result.is_err());
+        assert!(create_recovery(&mut state, guardian1_principal(), device, vec![], 0).is_err());
+        assert!(create_recovery(&mut state, guardian1_principal(), new_owner_principal(), vec![device], 0).is_err());
+
+        // Joining the guardian set during the time lock blocks execution too
+        let id = time_locked_request(&mut state);
+        state.guardian_state.as_mut().unwrap().guardians.push(new_owner_principal());
+        let unlocks_at = 2 + RECOVERY_TIME_LOCK_NANOS;
+        assert!(execute_recovery_request(&mut state, new_owner_principal(), id, unlocks_at).is_err());
+        assert_eq!(state.guardian_state.as_ref().unwrap().owner, owner_principal());
     }
-    
-    // Helper functions for testing
-    fn simulate_create_recovery_request(
-        state: &VaultStateV1,
-        caller: Principal,
-        new_owner: Principal,
-    ) -> Result<u64, String> {
-        let g = state.guardian_state.as_ref().ok_or("guardian state not initialized")?;
-        if caller != g.owner && !g.guardians.contains(&caller) {
-            return Err("only owner or guardian may open recovery".to_string());
-        }
-        Ok(state.next_recovery_id)
+
+    #[test]
+    fn test_new_owner_sees_progress_without_guardians() {
+        let mut state = setup_test_state_with_guardians();
+        let id = time_locked_request(&mut state);
+
+        assert!(member_request_view(&state, new_owner_principal(), id, 3).is_none());
+        assert_eq!(member_request_view(&state, guardian3_principal(), id, 3).unwrap().approvals.len(), 2);
+        let progress = recovery_progress(&state, new_owner_principal(), id, 3).unwrap();
+        assert_eq!(progress.status, RecoveryStatus::TimeLocked);
+        assert_eq!((progress.approval_weight, progress.rejection_weight, progress.total_weight), (2, 0, 3));
+        assert_eq!(progress.unlocks_at, Some(2 + RECOVERY_TIME_LOCK_NANOS));
+        assert!(recovery_progress(&state, Principal::anonymous(), id, 3).is_none());
     }
-    
-    fn simulate_process_recovery_approval(
-        state: &mut VaultStateV1,
-        id: u64,
-        caller: Principal,
-    ) -> Result<bool, String> {
-        let (quorum, owner) = {
-            let g = state.guardian_state.as_ref().ok_or("guardian state not initialized")?;
-            if !g.guardians.contains(&caller) {
-                return Err("only guardian may approve".to_string());
-            }
-            (g.quorum, g.owner)
+
+    #[test]
+    fn test_one_active_request_per_vault() {
+        let mut state = setup_test_state_with_guardians();
+        let id = open_request(&mut state);
+        assert!(create_recovery(&mut state, guardian3_principal(), new_owner_principal(), vec![], 1).is_err());
+
+        // An expired request no longer holds the slot
+        let next = create_recovery(&mut state, guardian3_principal(), new_owner_principal(), vec![], RECOVERY_TTL_NANOS).unwrap();
+        assert_eq!(status_of(&state, id), RecoveryStatus::Expired);
+        assert_eq!(status_of(&state, next), RecoveryStatus::Open);
+    }
+
+    #[test]
+    fn test_bond_is_refunded_or_forfeited() {
+        let mut state = setup_test_state_with_guardians();
+        state.recovery_bond = 10_000;
+        let vault = Principal::management_canister();
+
+        // The request waits in Draft until the bond arrives
+        let id = open_request(&mut state);
+        assert_eq!(status_of(&state, id), RecoveryStatus::Draft);
+        assert_eq!(pending_bond(&state, id), Some(10_000));
+        assert!(record_recovery_approval(&mut state, guardian1_principal(), id, 1).is_err());
+        finish_bond_collection(&mut state, id, Ok(7), 1).unwrap();
+        assert_eq!(status_of(&state, id), RecoveryStatus::Open);
+        assert!(begin_bond_settlement(&mut state, id, vault, 1).is_err());
+
+        // A good-faith cancel refunds the requester; a failed payout can be retried
+        cancel_recovery_request(&mut state, guardian1_principal(), id, 1).unwrap();
+        let payout = begin_bond_settlement(&mut state, id, vault, 2).unwrap().unwrap();
+        assert!(payout.refund);
+        assert_eq!(payout.to.owner, guardian1_principal());
+        assert!(begin_bond_settlement(&mut state, id, vault, 2).unwrap().is_none());
+        assert!(finish_bond_settlement(&mut state, id, true, Err("ledger down".to_string())).is_err());
+        let payout = begin_bond_settlement(&mut state, id, vault, 3).unwrap().unwrap();
+        finish_bond_settlement(&mut state, id, payout.refund, Ok(Some(9))).unwrap();
+        assert_eq!(state.recovery_reqs[0].bond.as_ref().unwrap().status, BondStatus::Refunded { block_index: Some(9) });
+
+        // An owner veto refunds the bond
+        let id = open_request(&mut state);
+        finish_bond_collection(&mut state, id, Ok(11), 4).unwrap();
+        owner_reject_recovery(&mut state, owner_principal(), id, 4).unwrap();
+        let payout = begin_bond_settlement(&mut state, id, vault, 5).unwrap().unwrap();
+        assert!(payout.refund);
+        assert_eq!(payout.to.owner, guardian1_principal());
+
+        // A request the guardians reject forfeits its bond to the vault
+        let id = open_request(&mut state);
+        finish_bond_collection(&mut state, id, Ok(12), 5).unwrap();
+        let reject = |state: &mut VaultStateV2, guardian| {
+            record_recovery_rejection(state, guardian, id, "not the owner".to_string(), 5)
         };
-        
-        let req = state.recovery_reqs.iter_mut()
-            .find(|r| r.id == id && r.open)
-            .ok_or("recovery request not found or closed")?;
-        
-        if !req.approvals.contains(&caller) {
-            req.approvals.push(caller);
-        }
-        
-        if (req.approvals.len() as u8) >= quorum {
-            // Transfer ownership
-            if let Some(g) = state.guardian_state.as_mut() {
-                if g.owner == owner {
-                    g.owner = req.new_owner;
-                }
+        assert_eq!(reject(&mut state, guardian2_principal()).unwrap(), RecoveryStatus::Open);
+        assert_eq!(reject(&mut state, guardian3_principal()).unwrap(), RecoveryStatus::Rejected);
+        // It lands in the vault's own subaccount, not a named pocket or the shared account
+        state.subaccounts.insert(owner_principal(), vec![crate::state::NamedSubaccount {
+            name: "spending".to_string(),
+            subaccount: vec![9; 32],
+            created_at: 0,
+            archived: false,
+        }]);
+        let payout = begin_bond_settlement(&mut state, id, vault, 5).unwrap().unwrap();
+        assert!(!payout.refund);
+        assert_eq!(payout.to.owner, vault);
+        let vault_id = crate::vault_id::vault_id_of(&state, &owner_principal());
+        assert_eq!(payout.to.subaccount, Some(crate::ckbtc::derive_subaccount_from_vault_id(&vault_id)));
+
+        // A bond that never arrived cancels the draft
+        let id = open_request(&mut state);
+        assert!(finish_bond_collection(&mut state, id, Err("insufficient allowance".to_string()), 6).is_err());
+        assert_eq!(status_of(&state, id), RecoveryStatus::Cancelled);
+        assert!(begin_bond_settlement(&mut state, id, vault, 6).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_share_submission_feeds_the_same_quorum() {
+        let mut state = setup_test_state_with_guardians();
+        let id = open_request(&mut state);
+
+        assert!(record_share_submission(&mut state, owner_principal(), id, SECRET_A, vec![1], 1).is_err());
+        confirm_takeover(&mut state, new_owner_principal(), id, 0).unwrap();
+        assert_eq!(record_share_submission(&mut state, guardian1_principal(), id, SECRET_A, vec![1], 1).unwrap(), RecoveryStatus::Open);
+        assert_eq!(record_recovery_approval(&mut state, guardian2_principal(), id, 2).unwrap(), RecoveryStatus::TimeLocked);
+
+        // Late shares are kept for reconstruction without changing the state
+        assert_eq!(record_share_submission(&mut state, guardian3_principal(), id, SECRET_A, vec![3], 3).unwrap(), RecoveryStatus::TimeLocked);
+        assert_eq!(state.submitted_recovery_shares[&id].len(), 2);
+
+        execute_recovery_request(&mut state, guardian1_principal(), id, 2 + RECOVERY_TIME_LOCK_NANOS).unwrap();
+        assert!(!state.submitted_recovery_shares.contains_key(&id));
+    }
+
+    #[test]
+    fn test_reshare_only_drops_shares_for_that_secret() {
+        let mut state = setup_test_state_with_guardians();
+        let id = open_request(&mut state);
+        record_share_submission(&mut state, guardian1_principal(), id, SECRET_A, vec![1], 1).unwrap();
+
+        // A recovery's shares all decrypt the same secret
+        let mixed = record_share_submission(&mut state, guardian2_principal(), id, SECRET_B, vec![2], 2);
+        assert!(mixed.is_err());
+
+        // Shares carried over without a secret go with any re-share
+        state.submitted_recovery_shares.insert(99, HashMap::from([(guardian2_principal(), vec![9])]));
+        forget_shares_for_secret(&mut state, SECRET_B);
+        assert_eq!(state.submitted_recovery_shares[&id].len(), 1);
+        assert!(!state.submitted_recovery_shares.contains_key(&99));
+
+        forget_shares_for_secret(&mut state, SECRET_A);
+        assert!(!state.submitted_recovery_shares.contains_key(&id));
+        assert!(!state.recovery_share_secrets.contains_key(&id));
+    }
+
+    #[test]
+    fn test_recovery_will_not_hide_the_new_owners_own_vault() {
+        let mut state = setup_test_state_with_guardians();
+        state.btc_addresses.insert(new_owner_principal(), "bc1qnew".to_string());
+        assert!(create_recovery(&mut state, guardian1_principal(), new_owner_principal(), vec![], 0).is_err());
+
+        // Also checked when the takeover runs
+        state.btc_addresses.clear();
+        let id = time_locked_request(&mut state);
+        state.btc_addresses.insert(new_owner_principal(), "bc1qnew".to_string());
+        let unlocked = 2 + RECOVERY_TIME_LOCK_NANOS;
+        assert!(execute_recovery_request(&mut state, new_owner_principal(), id, unlocked).is_err());
+        assert_eq!(status_of(&state, id), RecoveryStatus::TimeLocked);
+        assert_eq!(state.guardian_state.as_ref().unwrap().owner, owner_principal());
+    }
+
+    #[test]
+    fn test_cancel_and_reject() {
+        let mut state = setup_test_state_with_guardians();
+        let id = open_request(&mut state);
+        assert!(cancel_recovery_request(&mut state, guardian2_principal(), id, 1).is_err());
+        cancel_recovery_request(&mut state, guardian1_principal(), id, 1).unwrap();
+        assert_eq!(status_of(&state, id), RecoveryStatus::Cancelled);
+        assert!(record_recovery_approval(&mut state, guardian2_principal(), id, 2).is_err());
+
+        // The owner can still stop a recovery during its time lock
+        let id = time_locked_request(&mut state);
+        assert!(owner_reject_recovery(&mut state, guardian3_principal(), id, 3).is_err());
+        owner_reject_recovery(&mut state, owner_principal(), id, 3).unwrap();
+        assert_eq!(status_of(&state, id), RecoveryStatus::Rejected);
+        assert!(execute_recovery_request(&mut state, new_owner_principal(), id, 2 + RECOVERY_TIME_LOCK_NANOS).is_err());
+    }
+
+    #[test]
+    fn test_requests_expire_before_quorum() {
+        let mut state = setup_test_state_with_guardians();
+        let id = open_request(&mut state);
+        record_recovery_approval(&mut state, guardian1_principal(), id, 1).unwrap();
+
+        assert_eq!(with_lazy_expiry(&state.recovery_reqs[0], RECOVERY_TTL_NANOS).status, RecoveryStatus::Expired);
+        assert!(record_recovery_approval(&mut state, guardian2_principal(), id, RECOVERY_TTL_NANOS).is_err());
+        assert_eq!(status_of(&state, id), RecoveryStatus::Expired);
+
+        // A time-locked request is past the approval window and does not expire
+        let id = time_locked_request(&mut state);
+        assert!(execute_recovery_request(&mut state, new_owner_principal(), id, RECOVERY_TTL_NANOS * 2).is_ok());
+    }
+
+    #[test]
+    fn test_status_transitions() {
+        use RecoveryStatus::*;
+        let all = [Draft, Open, QuorumReached, TimeLocked, Executed, Cancelled, Expired, Rejected];
+        let allowed = [
+            (Draft, Open), (Draft, Cancelled), (Draft, Expired),
+            (Open, QuorumReached), (Open, Cancelled), (Open, Expired), (Open, Rejected),
+            (QuorumReached, TimeLocked), (QuorumReached, Cancelled), (QuorumReached, Expired), (QuorumReached, Rejected),
+            (TimeLocked, Executed), (TimeLocked, Cancelled), (TimeLocked, Rejected),
+        ];
+        for from in &all {
+            for to in &all {
+                let expected = allowed.contains(&(from.clone(), to.clone()));
+                assert_eq!(from.can_transition_to(to), expected, "{:?} -> {:?}", from, to);
             }
-            req.open = false;
-            Ok(true)
-        } else {
-            Ok(false)
         }
+        assert!(all.iter().filter(|s| s.is_terminal()).all(|s| all.iter().all(|t| !s.can_transition_to(t))));
     }
-}
 
+    #[test]
+    fn test_canister_vote_is_bound_to_new_owner() {
+        let mut state = setup_test_state_with_guardians();
+        let id = open_request(&mut state);
+
+        // Only canister guardians use this path, and only for the owner they voted on
+        let user = Principal::self_authenticating(b"user key");
+        assert!(check_canister_vote(&state, user, id, new_owner_principal()).is_err());
+        assert!(check_canister_vote(&state, guardian1_principal(), id, owner_principal()).is_err());
+        assert!(check_canister_vote(&state, guardian1_principal(), id + 1, new_owner_principal()).is_err());
+        assert!(check_canister_vote(&state, guardian1_principal(), id, new_owner_principal()).is_ok());
+    }
+}
@@ -0,0 +1,316 @@
+use candid::{CandidType, Deserialize, Principal};
+use serde::Serialize;
+use crate::recovery::record_recovery_approval;
+use crate::state::{with_state, with_state_mut, VaultStateV2};
+use crate::types::RecoveryStatus;
+
+/// Domain separator for off-chain recovery approvals.
+const SIGNED_APPROVAL_DOMAIN: &[u8] = b"guardian_vault_signed_approval_v1";
+/// Signatures may not outlive a recovery request, so they cannot be stockpiled.
+const MAX_SIGNATURE_VALIDITY_NANOS: u64 = 14 * 24 * 60 * 60 * 1_000_000_000;
+const MAX_APPROVALS_PER_BATCH: usize = 16;
+
+// Guardians who never use the dapp can approve recoveries with a key they
+// registered once. They sign `signed_approval_message` off-chain and anyone
+// relays the signature to `submit_signed_approvals`. The message binds this
+// canister, the request and its new owner, a nonce that must increase with
+// every signature from that guardian, and an expiry.
+
+#[derive(Clone, Debug, PartialEq, CandidType, Deserialize, Serialize)]
+pub enum GuardianSigningKey {
+    /// SEC1-encoded public key; signs ECDSA over SHA-256 of the message.
+    Secp256k1 { public_key: Vec<u8> },
+    /// Signs the message itself.
+    Ed25519 { public_key: Vec<u8> },
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct SignedApproval {
+    pub recovery_id: u64,
+    pub guardian: Principal,
+    pub nonce: u64,
+    pub expires_at: u64,
+    pub signature: Vec<u8>, // 64 bytes: r || s for secp256k1, R || S for Ed25519
+}
+
+/// Registers, or with `None` removes, the key the calling guardian signs
+/// approvals with.
+#[ic_cdk::update]
+pub fn set_guardian_signing_key(key: Option<GuardianSigningKey>) -> Result<(), String> {
+    let caller = ic_cdk::api::msg_caller();
+    with_state_mut(|state| set_signing_key(state, caller, key))
+}
+
+/// Visible to the owner and to the guardian the key belongs to, so the query
+/// does not reveal who guards the vault.
+#[ic_cdk::query]
+pub fn get_guardian_signing_key(guardian: Principal) -> Result<Option<GuardianSigningKey>, String> {
+    let caller = ic_cdk::api::msg_caller();
+    with_state(|state| signing_key_of(state, caller, guardian))
+}
+
+/// The bytes `guardian` must sign to approve recovery `recovery_id`.
+#[ic_cdk::query]
+pub fn signed_approval_message(
+    recovery_id: u64,
+    guardian: Principal,
+    nonce: u64,
+    expires_at: u64,
+) -> Result<Vec<u8>, String> {
+    with_state(|state| approval_message(state, ic_cdk::api::canister_self(), recovery_id, guardian, nonce, expires_at))
+}
+
+/// Counts signed approvals on behalf of their guardians. Anyone may submit;
+/// each approval gets its own result, in order.
+#[ic_cdk::update]
+pub fn submit_signed_approvals(approvals: Vec<SignedApproval>) -> Result<Vec<Result<RecoveryStatus, String>>, String> {
+    if approvals.len() > MAX_APPROVALS_PER_BATCH {
+        return Err(format!("at most {} approvals per batch", MAX_APPROVALS_PER_BATCH));
+    }
+    let canister = ic_cdk::api::canister_self();
+    let now = ic_cdk::api::time();
+    Ok(with_state_mut(|state| {
+        approvals.iter()
+            .map(|approval| apply_signed_approval(state, canister, approval, now))
+            .collect()
+    }))
+}
+
+fn apply_signed_approval(
+    state: &mut VaultStateV2,
+    canister: Principal,
+    approval: &SignedApproval,
+    now: u64,
+) -> Result<RecoveryStatus, String> {
+    let key = state.guardian_signing_keys.get(&approval.guardian)
+        .cloned()
+        .ok_or("guardian has no signing key")?;
+    if now >= approval.expires_at {
+        return Err("signed approval expired".to_string());
+    }
+    if approval.expires_at - now > MAX_SIGNATURE_VALIDITY_NANOS {
+        return Err("signed approval is valid for too long".to_string());
+    }
+    if state.signed_approval_nonces.get(&approval.guardian).is_some_and(|last| approval.nonce <= *last) {
+        return Err("nonce already used".to_string());
+    }
+    let message = approval_message(
+        state,
+        canister,
+        approval.recovery_id,
+        approval.guardian,
+        approval.nonce,
+        approval.expires_at,
+    )?;
+    verify_signature(&key, &message, &approval.signature)?;
+
+    let status = record_recovery_approval(state, approval.guardian, approval.recovery_id, now)?;
+    state.signed_approval_nonces.insert(approval.guardian, approval.nonce);
+    Ok(status)
+}
+
+// Helper functions
+fn set_signing_key(state: &mut VaultStateV2, caller: Principal, key: Option<GuardianSigningKey>) -> Result<(), String> {
+    let g = state.guardian_state.as_ref().ok_or("guardian state not initialized")?;
+    if !g.guardians.contains(&caller) {
+        return Err("only guardian may set a signing key".to_string());
+    }
+    match key {
+        Some(key) => {
+            parse_key(&key)?;
+            state.guardian_signing_keys.insert(caller, key);
+        }
+        None => {
+            state.guardian_signing_keys.remove(&caller);
+        }
+    }
+    Ok(())
+}
+
+fn signing_key_of(
+    state: &VaultStateV2,
+    caller: Principal,
+    guardian: Principal,
+) -> Result<Option<GuardianSigningKey>, String> {
+    let g = state.guardian_state.as_ref().ok_or("guardian state not initialized")?;
+    if caller != g.owner && caller != guardian {
+        return Err("only owner or the guardian itself may view a signing key".to_string());
+    }
+    Ok(state.guardian_signing_keys.get(&guardian).cloned())
+}
+
+/// domain || canister || recovery id || new owner || guardian || nonce ||
+/// expires_at, where each principal is prefixed with its length as one byte
+/// and integers are 8 bytes big-endian.
+fn approval_message(
+    state: &VaultStateV2,
+    canister: Principal,
+    recovery_id: u64,
+    guardian: Principal,
+    nonce: u64,
+    expires_at: u64,
+) -> Result<Vec<u8>, String> {
+    let req = state.recovery_reqs.iter()
+        .find(|r| r.id == recovery_id)
+        .ok_or("recovery request not found")?;
+    let mut message = SIGNED_APPROVAL_DOMAIN.to_vec();
+    push_principal(&mut message, &canister);
+    message.extend_from_slice(&recovery_id.to_be_bytes());
+    push_principal(&mut message, &req.new_owner);
+    push_principal(&mut message, &guardian);
+    message.extend_from_slice(&nonce.to_be_bytes());
+    message.extend_from_slice(&expires_at.to_be_bytes());
+    Ok(message)
+}
+
+fn push_principal(message: &mut Vec<u8>, principal: &Principal) {
+    message.push(principal.as_slice().len() as u8);
+    message.extend_from_slice(principal.as_slice());
+}
+
+enum ParsedKey {
+    Secp256k1(k256::ecdsa::VerifyingKey),
+    Ed25519(ed25519_dalek::VerifyingKey),
+}
+
+fn parse_key(key: &GuardianSigningKey) -> Result<ParsedKey, String> {
+    match key {
+        GuardianSigningKey::Secp256k1 { public_key } => k256::ecdsa::VerifyingKey::from_sec1_bytes(public_key)
+            .map(ParsedKey::Secp256k1)
+            .map_err(|_| "invalid secp256k1 public key".to_string()),
+        GuardianSigningKey::Ed25519 { public_key } => {
+            let bytes: [u8; 32] = public_key.as_slice().try_into().map_err(|_| "ed25519 public key must be 32 bytes")?;
+            ed25519_dalek::VerifyingKey::from_bytes(&bytes)
+                .map(ParsedKey::Ed25519)
+                .map_err(|_| "invalid ed25519 public key".to_string())
+        }
+    }
+}
+
+fn verify_signature(key: &GuardianSigningKey, message: &[u8], signature: &[u8]) -> Result<(), String> {
+    let valid = match parse_key(key)? {
+        ParsedKey::Secp256k1(key) => {
+            use k256::ecdsa::signature::Verifier;
+            k256::ecdsa::Signature::from_slice(signature)
+                .is_ok_and(|sig| key.verify(message, &sig).is_ok())
+        }
+        ParsedKey::Ed25519(key) => ed25519_dalek::Signature::from_slice(signature)
+            .is_ok_and(|sig| key.verify_strict(message, &sig).is_ok()),
+    };
+    if valid {
+        Ok(())
+    } else {
+        Err("invalid signature".to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::recovery::create_recovery;
+    use crate::types::GuardianState;
+    use ed25519_dalek::Signer as _;
+    use std::collections::BTreeMap;
+
+    fn owner_principal() -> Principal {
+        Principal::from_text("rdmx6-jaaaa-aaaaa-aaadq-cai").unwrap()
+    }
+
+    fn guardian1_principal() -> Principal {
+        Principal::from_text("rrkah-fqaaa-aaaaa-aaaaq-cai").unwrap()
+    }
+
+    fn guardian2_principal() -> Principal {
+        Principal::from_text("ryjl3-tyaaa-aaaaa-aaaba-cai").unwrap()
+    }
+
+    fn new_owner_principal() -> Principal {
+        Principal::from_text("renrk-eyaaa-aaaaa-aaada-cai").unwrap()
+    }
+
+    fn canister_principal() -> Principal {
+        Principal::from_text("rno2w-sqaaa-aaaaa-aaacq-cai").unwrap()
+    }
+
+    fn setup_test_state_with_open_request() -> (VaultStateV2, u64) {
+        let mut state = VaultStateV2 {
+            guardian_state: Some(GuardianState {
+                guardians: vec![guardian1_principal(), guardian2_principal()],
+                quorum: 2,
+                owner: owner_principal(),
+                weights: BTreeMap::new(),
+                categories: BTreeMap::new(),
+                policy: None,
+            }),
+            ..Default::default()
+        };
+        let id = create_recovery(&mut state, guardian1_principal(), new_owner_principal(), vec![], 0).unwrap();
+        (state, id)
+    }
+
+    fn signed(state: &VaultStateV2, id: u64, guardian: Principal, nonce: u64, sign: impl Fn(&[u8]) -> Vec<u8>) -> SignedApproval {
+        let expires_at = 1_000;
+        let message = approval_message(state, canister_principal(), id, guardian, nonce, expires_at).unwrap();
+        SignedApproval { recovery_id: id, guardian, nonce, expires_at, signature: sign(&message) }
+    }
+
+    #[test]
+    fn test_signed_approvals_reach_quorum() {
+        let (mut state, id) = setup_test_state_with_open_request();
+        let (g1, g2) = (guardian1_principal(), guardian2_principal());
+        let ecdsa_key = k256::ecdsa::SigningKey::from_slice(&[1; 32]).unwrap();
+        let ed_key = ed25519_dalek::SigningKey::from_bytes(&[2; 32]);
+
+        let public_key = ecdsa_key.verifying_key().to_encoded_point(true).as_bytes().to_vec();
+        assert!(set_signing_key(&mut state, owner_principal(), Some(GuardianSigningKey::Secp256k1 { public_key: public_key.clone() })).is_err());
+        assert!(set_signing_key(&mut state, g1, Some(GuardianSigningKey::Ed25519 { public_key: vec![0; 31] })).is_err());
+        set_signing_key(&mut state, g1, Some(GuardianSigningKey::Secp256k1 { public_key })).unwrap();
+        let public_key = ed_key.verifying_key().to_bytes().to_vec();
+        set_signing_key(&mut state, g2, Some(GuardianSigningKey::Ed25519 { public_key })).unwrap();
+
+        // Only the owner and the key's own guardian can look a key up
+        assert!(signing_key_of(&state, g1, g1).unwrap().is_some());
+        assert!(signing_key_of(&state, owner_principal(), g2).unwrap().is_some());
+        assert!(signing_key_of(&state, g2, g1).is_err());
+        assert!(signing_key_of(&state, new_owner_principal(), g1).is_err());
+
+        let sign_ecdsa = |m: &[u8]| -> Vec<u8> {
+            let sig: k256::ecdsa::Signature = ecdsa_key.sign(m);
+            sig.to_bytes().to_vec()
+        };
+        let sign_ed = |m: &[u8]| ed_key.sign(m).to_bytes().to_vec();
+
+        // Another guardian's key does not verify
+        let forged = signed(&state, id, g1, 1, sign_ed);
+        assert_eq!(apply_signed_approval(&mut state, canister_principal(), &forged, 0), Err("invalid signature".to_string()));
+
+        let first = signed(&state, id, g1, 1, sign_ecdsa);
+        assert_eq!(apply_signed_approval(&mut state, canister_principal(), &first, 0).unwrap(), RecoveryStatus::Open);
+        let second = signed(&state, id, g2, 7, sign_ed);
+        assert_eq!(apply_signed_approval(&mut state, canister_principal(), &second, 0).unwrap(), RecoveryStatus::QuorumReached);
+        assert_eq!(state.signed_approval_nonces[&g2], 7);
+    }
+
+    #[test]
+    fn test_signed_approval_replay_and_expiry() {
+        let (mut state, id) = setup_test_state_with_open_request();
+        let g1 = guardian1_principal();
+        let ed_key = ed25519_dalek::SigningKey::from_bytes(&[3; 32]);
+        let public_key = ed_key.verifying_key().to_bytes().to_vec();
+        set_signing_key(&mut state, g1, Some(GuardianSigningKey::Ed25519 { public_key })).unwrap();
+        let sign = |m: &[u8]| ed_key.sign(m).to_bytes().to_vec();
+
+        let approval = signed(&state, id, g1, 5, sign);
+        assert!(apply_signed_approval(&mut state, canister_principal(), &approval, 1_000).is_err());
+        // Bound to this canister
+        assert!(apply_signed_approval(&mut state, owner_principal(), &approval, 0).is_err());
+        apply_signed_approval(&mut state, canister_principal(), &approval, 0).unwrap();
+        assert_eq!(apply_signed_approval(&mut state, canister_principal(), &approval, 0), Err("nonce already used".to_string()));
+
+        // Tampering with the signed fields breaks the signature
+        let mut tampered = signed(&state, id, g1, 6, sign);
+        tampered.expires_at += 1;
+        assert!(apply_signed_approval(&mut state, canister_principal(), &tampered, 0).is_err());
+        assert_eq!(state.signed_approval_nonces[&g1], 5);
+    }
+}
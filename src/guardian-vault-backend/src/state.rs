@@ -6,25 +6,62 @@ use ic_stable_structures::{
 };
 use serde::Serialize;
 use std::{borrow::Cow, cell::RefCell, collections::{BTreeMap, HashMap}};
-use crate::types::{Config, GuardianState, RecoveryRequest};
+use crate::types::{Config, GuardianInvitation, GuardianProfile, GuardianState, Icrc1Account, RecoveryRequest, RecoveryVoting};
 use crate::vetkd::RecoverySecret;
+use crate::proposals::TransferProposal;
+use crate::whitelist::{WhitelistEntry, WhitelistPolicy};
+use crate::guardians::{GuardianChange, GuardianRole};
+use crate::notifications::Notification;
+use crate::signed_approvals::GuardianSigningKey;
+use crate::drills::RecoveryDrill;
+use crate::freeze::FreezeLogEntry;
+use crate::migration::upgrade_v1;
 
 type Memory = VirtualMemory<DefaultMemoryImpl>;
 
-const STATE_VERSION: u32 = 1;
+const STATE_VERSION: u32 = 2;
 
+/// Stable state as of `STATE_VERSION`. Candid cannot decode a stored record
+/// into one with more required fields, so any change to this type or the
+/// records it holds needs a new version and a conversion in `migration`.
 #[derive(Clone, Debug, CandidType, Deserialize, Serialize)]
-pub struct VaultStateV1 {
+pub struct VaultStateV2 {
     pub version: u32,
     pub config: Option<Config>,
     pub guardian_state: Option<GuardianState>,
+    pub guardian_invitations: Vec<GuardianInvitation>,
+    pub guardian_transport_keys: BTreeMap<Principal, Vec<u8>>, // guardian -> VetKD transport public key
+    pub guardian_profiles: BTreeMap<Principal, GuardianProfile>,
+    pub guardian_signing_keys: BTreeMap<Principal, GuardianSigningKey>, // guardian -> key for off-chain approvals
+    pub signed_approval_nonces: BTreeMap<Principal, u64>, // guardian -> last nonce used; kept after the key is removed
+    pub guardian_index: BTreeMap<Principal, GuardianRole>, // reverse lookup kept in sync with the guardian set
+    pub next_guardian_change_id: u64,
+    pub guardian_changes: Vec<GuardianChange>,
     pub next_recovery_id: u64,
     pub recovery_reqs: Vec<RecoveryRequest>,
-    pub subaccounts: BTreeMap<Principal, Vec<u8>>, // user -> subaccount
+    pub recovery_bond: u64, // ckBTC a requester locks per recovery request; 0 disables the bond
+    pub recovery_voting: RecoveryVoting, // applies to requests opened after it is set
+    pub next_drill_id: u64,
+    pub recovery_drills: Vec<RecoveryDrill>, // most recent last
+    pub subaccounts: BTreeMap<Principal, Vec<NamedSubaccount>>, // user -> named subaccounts
+    pub legacy_accounts: BTreeMap<Principal, Icrc1Account>, // user -> user-owned account its version 1 deposits went to
     pub recovery_secrets: HashMap<Vec<u8>, RecoverySecret>, // secret_id -> recovery_secret
     pub submitted_recovery_shares: HashMap<u64, HashMap<Principal, Vec<u8>>>, // recovery_id -> guardian -> share
+    pub recovery_share_secrets: HashMap<u64, Vec<u8>>, // recovery_id -> secret_id its submitted shares belong to
     pub btc_addresses: HashMap<Principal, String>, // user -> btc_address
+    pub vault_ids: BTreeMap<Principal, Vec<u8>>, // principal -> vault id, when not the principal's own bytes
     pub transaction_history: Vec<TransactionRecord>,
+    pub transfer_intents: BTreeMap<(Principal, String), TransferIntent>, // (caller, idempotency_key) -> intent
+    pub transfer_approval_threshold: Option<u64>, // amounts above this need guardian approval
+    pub next_proposal_id: u64,
+    pub transfer_proposals: Vec<TransferProposal>,
+    pub next_whitelist_id: u64,
+    pub whitelist: Vec<WhitelistEntry>,
+    pub whitelist_policy: WhitelistPolicy,
+    pub frozen_until: u64, // outgoing funds are blocked before this time
+    pub freeze_log: Vec<FreezeLogEntry>,
+    pub next_notification_id: u64,
+    pub notifications: BTreeMap<Principal, Vec<Notification>>, // recipient -> inbox, oldest first
 }
 
 #[derive(Clone, Debug, CandidType, Deserialize, Serialize)]
@@ -37,6 +74,33 @@ pub struct TransactionRecord {
     pub memo: Option<Vec<u8>>,
     pub timestamp: u64,
     pub status: TransactionStatus,
+    pub kind: TransactionKind,
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize, Serialize)]
+pub enum TransactionKind {
+    Transfer,
+    InternalTransfer { from_pocket: String, to_pocket: String },
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize, Serialize)]
+pub struct NamedSubaccount {
+    pub name: String,
+    pub subaccount: Vec<u8>,
+    pub created_at: u64,
+    /// Archived subaccounts are hidden from default listings but never forgotten,
+    /// so funds left in them stay discoverable.
+    pub archived: bool,
+}
+
+/// Ledger arguments pinned to a caller-supplied idempotency key, so that a
+/// retried `ckbtc_transfer` is deduplicated by the ledger instead of paying twice.
+#[derive(Clone, Debug, CandidType, Deserialize, Serialize)]
+pub struct TransferIntent {
+    pub args_hash: Vec<u8>,
+    pub created_at_time: u64,
+    pub memo: Option<Vec<u8>>,
+    pub block_index: Option<u128>,
 }
 
 #[derive(Clone, Debug, CandidType, Deserialize, Serialize)]
@@ -46,52 +110,97 @@ pub enum TransactionStatus {
     Failed,
 }
 
-impl Default for VaultStateV1 {
+impl Default for VaultStateV2 {
     fn default() -> Self {
         Self {
             version: STATE_VERSION,
             config: None,
             guardian_state: None,
+            guardian_invitations: Vec::new(),
+            guardian_transport_keys: BTreeMap::new(),
+            guardian_profiles: BTreeMap::new(),
+            guardian_signing_keys: BTreeMap::new(),
+            signed_approval_nonces: BTreeMap::new(),
+            guardian_index: BTreeMap::new(),
+            next_guardian_change_id: 1,
+            guardian_changes: Vec::new(),
             next_recovery_id: 1,
             recovery_reqs: Vec::new(),
+            recovery_bond: 0,
+            recovery_voting: RecoveryVoting::Open,
+            next_drill_id: 1,
+            recovery_drills: Vec::new(),
             subaccounts: BTreeMap::new(),
+            legacy_accounts: BTreeMap::new(),
             recovery_secrets: HashMap::new(),
             submitted_recovery_shares: HashMap::new(),
+            recovery_share_secrets: HashMap::new(),
             btc_addresses: HashMap::new(),
+            vault_ids: BTreeMap::new(),
             transaction_history: Vec::new(),
+            transfer_intents: BTreeMap::new(),
+            transfer_approval_threshold: None,
+            next_proposal_id: 1,
+            transfer_proposals: Vec::new(),
+            next_whitelist_id: 1,
+            whitelist: Vec::new(),
+            whitelist_policy: WhitelistPolicy::default(),
+            frozen_until: 0,
+            freeze_log: Vec::new(),
+            next_notification_id: 1,
+            notifications: BTreeMap::new(),
         }
     }
 }
 
-impl Storable for VaultStateV1 {
-    fn to_bytes(&self) -> std::borrow::Cow<[u8]> {
+impl Storable for VaultStateV2 {
+    fn to_bytes(&self) -> std::borrow::Cow<'_, [u8]> {
         Cow::Owned(candid::encode_one(self).unwrap())
     }
 
     fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
-        candid::decode_one(&bytes).unwrap()
+        decode_state(&bytes, ic_cdk::api::time()).unwrap_or_else(|e| ic_cdk::trap(&e))
     }
 
     const BOUND: Bound = Bound::Unbounded;
 }
 
+/// Just the version field, which every stored layout starts with.
+#[derive(CandidType, Deserialize)]
+struct StoredVersion {
+    version: u32,
+}
+
+/// Decodes stable state written by any version of the canister, converting
+/// older layouts to the current one.
+pub(crate) fn decode_state(bytes: &[u8], now: u64) -> Result<VaultStateV2, String> {
+    let StoredVersion { version } = candid::decode_one(bytes)
+        .map_err(|e| format!("unreadable stable state: {}", e))?;
+    match version {
+        1 => candid::decode_one(bytes)
+            .map(|old| upgrade_v1(old, now))
+            .map_err(|e| format!("unreadable version 1 state: {}", e)),
+        _ => candid::decode_one(bytes).map_err(|e| format!("unreadable version {} state: {}", version, e)),
+    }
+}
+
 thread_local! {
     static MEMORY_MANAGER: RefCell<MemoryManager<DefaultMemoryImpl>> =
         RefCell::new(MemoryManager::init(DefaultMemoryImpl::default()));
 
-    static STATE: RefCell<StableCell<VaultStateV1, Memory>> = RefCell::new(
+    static STATE: RefCell<StableCell<VaultStateV2, Memory>> = RefCell::new(
         StableCell::init(
             MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(0))),
-            VaultStateV1::default()
+            VaultStateV2::default()
         ).unwrap()
     );
 }
 
-pub fn with_state<R>(f: impl FnOnce(&VaultStateV1) -> R) -> R {
-    STATE.with(|state| f(&state.borrow().get()))
+pub fn with_state<R>(f: impl FnOnce(&VaultStateV2) -> R) -> R {
+    STATE.with(|state| f(state.borrow().get()))
 }
 
-pub fn with_state_mut<R>(f: impl FnOnce(&mut VaultStateV1) -> R) -> R {
+pub fn with_state_mut<R>(f: impl FnOnce(&mut VaultStateV2) -> R) -> R {
     STATE.with(|state| {
         let mut cell = state.borrow_mut();
         let mut current_state = cell.get().clone();
@@ -102,17 +211,17 @@ pub fn with_state_mut<R>(f: impl FnOnce(&mut VaultStateV1) -> R) -> R {
 }
 
 // Legacy compatibility functions for existing code
-pub fn state() -> VaultStateV1 {
+pub fn state() -> VaultStateV2 {
     with_state(|s| s.clone())
 }
 
-pub fn state_mut() -> VaultStateV1 {
+pub fn state_mut() -> VaultStateV2 {
     with_state(|s| s.clone())
 }
 
 pub fn update_state<F>(f: F) 
 where
-    F: FnOnce(&mut VaultStateV1),
+    F: FnOnce(&mut VaultStateV2),
 {
     with_state_mut(f);
 }
@@ -121,19 +230,15 @@ where
 pub fn migrate_state() -> Result<(), String> {
     with_state_mut(|state| {
         match state.version {
-            1 => {
-                // Current version, no migration needed
+            STATE_VERSION => {
+                // Older layouts were already converted by `decode_state` while loading
                 Ok(())
             },
             v if v > STATE_VERSION => {
                 Err(format!("Cannot downgrade from version {} to {}", v, STATE_VERSION))
             },
             v => {
-                // Migration from older versions would go here
-                // For now, just update version
-                state.version = STATE_VERSION;
-                ic_cdk::println!("Migrated state from version {} to {}", v, STATE_VERSION);
-                Ok(())
+                Err(format!("No migration from version {} to {}", v, STATE_VERSION))
             }
         }
     })
@@ -1,5 +1,6 @@
 use candid::{CandidType, Deserialize, Principal};
 use serde::Serialize;
+use std::collections::{BTreeMap, BTreeSet};
 
 #[derive(Clone, Debug, CandidType, Deserialize, Serialize)]
 pub struct Config {
@@ -11,16 +12,336 @@ pub struct Config {
 #[derive(Clone, Debug, CandidType, Deserialize, Serialize)]
 pub struct GuardianState {
     pub guardians: Vec<Principal>,
-    pub quorum: u8,
+    pub quorum: u8, // approval weight needed while no policy is set
     pub owner: Principal,
+    pub weights: BTreeMap<Principal, u8>, // guardians without an entry weigh 1
+    pub categories: BTreeMap<Principal, Vec<String>>,
+    pub policy: Option<GuardianPolicy>, // supersedes `quorum` when set
+}
+
+impl GuardianState {
+    pub fn weight_of(&self, guardian: &Principal) -> u32 {
+        if !self.guardians.contains(guardian) {
+            return 0;
+        }
+        self.weights.get(guardian).copied().unwrap_or(1) as u32
+    }
+
+    pub fn total_weight(&self) -> u32 {
+        self.guardians.iter().map(|g| self.weight_of(g)).sum()
+    }
+
+    /// Combined weight of the given principals; non-guardians and repeats count nothing.
+    pub fn approval_weight<'a>(&self, approvers: impl IntoIterator<Item = &'a Principal>) -> u32 {
+        let unique: BTreeSet<&Principal> = approvers.into_iter().collect();
+        unique.into_iter().map(|p| self.weight_of(p)).sum()
+    }
+
+    /// The rule approvals are checked against: the structured policy, or a
+    /// plain weight threshold over all guardians.
+    pub fn effective_policy(&self) -> GuardianPolicy {
+        self.policy.clone().unwrap_or(GuardianPolicy::Threshold {
+            from: GuardianSelector::AnyGuardian,
+            weight: self.quorum as u32,
+        })
+    }
+
+    pub fn quorum_reached<'a>(&self, approvers: impl IntoIterator<Item = &'a Principal>) -> bool {
+        (self.policy.is_some() || self.quorum > 0) && self.unmet_requirements(approvers).is_empty()
+    }
+
+    /// Human-readable requirements of the effective policy the approvers do not
+    /// yet satisfy; empty once the policy is met.
+    pub fn unmet_requirements<'a>(&self, approvers: impl IntoIterator<Item = &'a Principal>) -> Vec<String> {
+        let unique: BTreeSet<&Principal> = approvers.into_iter().collect();
+        self.effective_policy().unmet(self, &unique)
+    }
+
+    pub fn selects(&self, selector: &GuardianSelector, guardian: &Principal) -> bool {
+        match selector {
+            GuardianSelector::AnyGuardian => true,
+            GuardianSelector::Category(category) => self.categories
+                .get(guardian)
+                .is_some_and(|c| c.contains(category)),
+            GuardianSelector::Guardian(p) => p == guardian,
+        }
+    }
+}
+
+/// Which guardians a policy group draws its approvals from.
+#[derive(Clone, Debug, PartialEq, CandidType, Deserialize, Serialize)]
+pub enum GuardianSelector {
+    AnyGuardian,
+    Category(String),
+    Guardian(Principal),
+}
+
+/// Approval rule over guardian weights. With unit weights a `Threshold` is a
+/// k-of-n group, and a mandatory guardian is `Threshold { from: Guardian(p), weight: 1 }`.
+#[derive(Clone, Debug, PartialEq, CandidType, Deserialize, Serialize)]
+pub enum GuardianPolicy {
+    Threshold { from: GuardianSelector, weight: u32 },
+    All(Vec<GuardianPolicy>),
+    Any(Vec<GuardianPolicy>),
+}
+
+impl GuardianPolicy {
+    fn unmet(&self, g: &GuardianState, approvers: &BTreeSet<&Principal>) -> Vec<String> {
+        match self {
+            GuardianPolicy::Threshold { from, weight } => {
+                let have: u32 = approvers.iter()
+                    .filter(|p| g.selects(from, p))
+                    .map(|p| g.weight_of(p))
+                    .sum();
+                if have >= *weight {
+                    return vec![];
+                }
+                let source = match from {
+                    GuardianSelector::AnyGuardian => "any guardian".to_string(),
+                    GuardianSelector::Category(c) => format!("guardians in category \"{}\"", c),
+                    GuardianSelector::Guardian(p) => format!("guardian {}", p),
+                };
+                vec![format!("{} more approval weight from {}", weight - have, source)]
+            }
+            GuardianPolicy::All(policies) => policies.iter().flat_map(|p| p.unmet(g, approvers)).collect(),
+            GuardianPolicy::Any(policies) => {
+                let mut options = Vec::new();
+                for policy in policies {
+                    let unmet = policy.unmet(g, approvers);
+                    if unmet.is_empty() {
+                        return vec![];
+                    }
+                    options.push(unmet.join(" and "));
+                }
+                vec![format!("one of: {}", options.join("; or "))]
+            }
+        }
+    }
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize, Serialize)]
+pub struct GuardianCategories {
+    pub guardian: Principal,
+    pub categories: Vec<String>,
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize, Serialize)]
+pub struct GuardianWeight {
+    pub guardian: Principal,
+    pub weight: u8,
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize, Serialize)]
+pub struct GuardianProfile {
+    pub guardian: Principal,
+    pub nickname: Option<String>,
+    pub added_at: u64,
+    pub last_seen: u64,
+    pub attestation_requested_at: Option<u64>, // cleared by the next heartbeat
+    pub is_canister: bool,
+    pub callback_method: Option<String>, // canister guardians only
+    pub membership_salt: Vec<u8>,
+}
+
+/// What anyone may learn about the guardian set: counts and salted
+/// commitments, never the principals themselves.
+#[derive(Clone, Debug, CandidType, Deserialize, Serialize)]
+pub struct GuardianSummary {
+    pub guardians: u32,
+    pub invited: u32,
+    pub quorum: u8,
+    pub total_weight: u32,
+    pub has_policy: bool,
+    pub commitments: Vec<Vec<u8>>,
+}
+
+/// Opens one of the published commitments, revealing a guardian.
+#[derive(Clone, Debug, CandidType, Deserialize, Serialize)]
+pub struct MembershipProof {
+    pub guardian: Principal,
+    pub salt: Vec<u8>,
+    pub commitment: Vec<u8>,
+}
+
+/// Sent to a guardian canister's registered callback when a recovery is opened.
+#[derive(Clone, Debug, CandidType, Deserialize, Serialize)]
+pub struct RecoveryVoteRequest {
+    pub vault: Principal,
+    pub recovery_id: u64,
+    pub new_owner: Principal,
+    pub requested_at: u64,
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize, Serialize)]
+pub struct VaultHealth {
+    pub guardians: u32,
+    pub live_guardians: u32,
+    pub stale_guardians: Vec<Principal>,
+    pub warnings: Vec<String>,
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize, Serialize)]
+pub struct GuardianInvitation {
+    pub guardian: Principal,
+    pub weight: u8,
+    pub code_hash: Vec<u8>, // sha256 of the one-time code
+    pub invited_at: u64,
+    pub expires_at: u64,
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize, Serialize)]
+pub struct InvitationCode {
+    pub guardian: Principal,
+    pub code: String,
 }
 
 #[derive(Clone, Debug, CandidType, Deserialize, Serialize)]
 pub struct RecoveryRequest {
     pub id: u64,
     pub new_owner: Principal,
+    pub linked_devices: Vec<Principal>, // must confirm alongside the new owner
+    pub confirmations: Vec<Principal>,
+    pub requested_by: Principal,
+    pub approvals: Vec<Principal>, // guardians who approved or submitted a share
+    pub rejections: Vec<RecoveryRejection>,
+    pub bond: Option<RecoveryBond>,
+    pub commit_reveal: Option<CommitRevealRound>, // set when the request votes by commit-reveal
+    pub status: RecoveryStatus,
+    pub created_at: u64,
+    pub expires_at: u64,
+    pub unlocks_at: Option<u64>, // set on entering the time lock
+}
+
+impl RecoveryRequest {
+    pub fn is_active(&self) -> bool {
+        !self.status.is_terminal()
+    }
+
+    pub fn has_voted(&self, guardian: &Principal) -> bool {
+        self.approvals.contains(guardian) || self.rejections.iter().any(|r| r.guardian == *guardian)
+    }
+
+    /// Whether the new owner and every linked device have confirmed the takeover.
+    pub fn is_accepted(&self) -> bool {
+        self.confirmations.contains(&self.new_owner)
+            && self.linked_devices.iter().all(|d| self.confirmations.contains(d))
+    }
+
+    /// Moves to `next`, rejecting any edge the recovery lifecycle does not allow.
+    pub fn transition(&mut self, next: RecoveryStatus) -> Result<(), String> {
+        if !self.status.can_transition_to(&next) {
+            return Err(format!("recovery cannot move from {:?} to {:?}", self.status, next));
+        }
+        self.status = next;
+        Ok(())
+    }
+}
+
+/// How guardians vote on recovery requests. Under commit-reveal, guardians
+/// first submit a hash of their vote and only reveal it once the commit
+/// window has closed, so no one sees how others voted before committing.
+#[derive(Clone, Debug, PartialEq, CandidType, Deserialize, Serialize)]
+pub enum RecoveryVoting {
+    Open,
+    CommitReveal { commit_window_nanos: u64, reveal_window_nanos: u64 },
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize, Serialize)]
+pub struct CommitRevealRound {
+    pub commit_ends_at: u64,
+    pub reveal_ends_at: u64,
+    pub commitments: Vec<VoteCommitment>,
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize, Serialize)]
+pub struct VoteCommitment {
+    pub guardian: Principal,
+    pub commitment: Vec<u8>,
+    pub revealed: bool,
+}
+
+/// ckBTC locked by the requester while a recovery is open.
+#[derive(Clone, Debug, CandidType, Deserialize, Serialize)]
+pub struct RecoveryBond {
+    pub payer: Principal,
+    pub amount: u64,
+    pub collected_block: Option<u128>,
+    pub status: BondStatus,
+    pub forfeit: bool, // set when guardian rejections close the request
+}
+
+#[derive(Clone, Debug, PartialEq, CandidType, Deserialize, Serialize)]
+pub enum BondStatus {
+    Pending, // waiting for the ICRC-2 transfer_from
+    Held,
+    Settling,
+    Refunded { block_index: Option<u128> },
+    Forfeited { block_index: Option<u128> },
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize, Serialize)]
+pub struct RecoveryRejection {
+    pub guardian: Principal,
+    pub reason: String,
+    pub rejected_at: u64,
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize, Serialize)]
+pub struct RecoveryTally {
+    pub recovery_id: u64,
+    pub status: RecoveryStatus,
     pub approvals: Vec<Principal>,
-    pub open: bool,
+    pub approval_weight: u32,
+    pub rejections: Vec<RecoveryRejection>,
+    pub rejection_weight: u32,
+    pub undecided: Vec<Principal>,
+    pub total_weight: u32,
+    pub committed: Vec<Principal>, // commit-reveal only
+    pub unrevealed: Vec<Principal>,
+}
+
+/// What the prospective owner of a request may see: no guardian identities,
+/// only how far the vote has come.
+#[derive(Clone, Debug, CandidType, Deserialize, Serialize)]
+pub struct RecoveryProgress {
+    pub recovery_id: u64,
+    pub status: RecoveryStatus,
+    pub approval_weight: u32,
+    pub rejection_weight: u32,
+    pub total_weight: u32,
+    pub unlocks_at: Option<u64>,
+}
+
+#[derive(Clone, Debug, PartialEq, CandidType, Deserialize, Serialize)]
+pub enum RecoveryStatus {
+    Draft,
+    Open,
+    QuorumReached,
+    TimeLocked,
+    Executed,
+    Cancelled,
+    Expired,
+    Rejected,
+}
+
+impl RecoveryStatus {
+    pub fn is_terminal(&self) -> bool {
+        matches!(
+            self,
+            RecoveryStatus::Executed | RecoveryStatus::Cancelled | RecoveryStatus::Expired | RecoveryStatus::Rejected
+        )
+    }
+
+    pub fn can_transition_to(&self, next: &RecoveryStatus) -> bool {
+        use RecoveryStatus::*;
+        matches!(
+            (self, next),
+            (Draft, Open | Cancelled | Expired)
+                | (Open, QuorumReached | Cancelled | Expired | Rejected)
+                | (QuorumReached, TimeLocked | Cancelled | Expired | Rejected)
+                | (TimeLocked, Executed | Cancelled | Rejected)
+        )
+    }
 }
 
 #[derive(Clone, Debug, CandidType, Deserialize, Serialize)]
@@ -41,6 +362,19 @@ pub enum TransferError {
     GenericError { error_code: candid::Nat, message: String },
 }
 
+#[derive(CandidType, Deserialize, Debug)]
+pub enum TransferFromError {
+    BadFee { expected_fee: candid::Nat },
+    BadBurn { min_burn_amount: candid::Nat },
+    InsufficientFunds { balance: candid::Nat },
+    InsufficientAllowance { allowance: candid::Nat },
+    TooOld,
+    CreatedInFuture { ledger_time: u64 },
+    Duplicate { duplicate_of: candid::Nat },
+    TemporarilyUnavailable,
+    GenericError { error_code: candid::Nat, message: String },
+}
+
 // ckBTC Minter Types
 #[derive(Clone, Debug, CandidType, Deserialize, Serialize)]
 pub struct GetDepositAddressArgs {
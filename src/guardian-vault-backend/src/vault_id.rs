@@ -0,0 +1,106 @@
+use candid::Principal;
+use sha2::{Sha256, Digest};
+use crate::state::{with_state, VaultStateV2};
+
+/// Domain separator for the fresh vault id a principal gets when recovery
+/// hands its vault to someone else.
+const RETIRED_VAULT_DOMAIN: &[u8] = b"guardian_vault_retired_v1";
+
+// Every tECDSA derivation path and ledger subaccount is derived from a vault
+// id rather than from the caller. A principal's vault id defaults to its own
+// bytes, so addresses derived before vault ids existed stay where they are.
+// Recovery points the new owner at the old owner's vault id and gives the old
+// principal a fresh, empty one.
+
+#[ic_cdk::query]
+pub fn get_vault_id() -> Vec<u8> {
+    let caller = ic_cdk::api::msg_caller();
+    with_state(|state| vault_id_of(state, &caller))
+}
+
+pub(crate) fn vault_id_of(state: &VaultStateV2, principal: &Principal) -> Vec<u8> {
+    state.vault_ids.get(principal)
+        .cloned()
+        .unwrap_or_else(|| principal.as_slice().to_vec())
+}
+
+/// A vault can only be handed to a principal without subaccounts or a Bitcoin
+/// address of its own, which rebinding would otherwise hide.
+pub(crate) fn check_rebind_target(state: &VaultStateV2, to: &Principal) -> Result<(), String> {
+    if state.subaccounts.get(to).is_some_and(|subs| !subs.is_empty()) || state.btc_addresses.contains_key(to) {
+        return Err("new owner already holds vault subaccounts or a bitcoin address".to_string());
+    }
+    Ok(())
+}
+
+/// Hands `from`'s vault, with its Bitcoin address and named subaccounts, to
+/// `to`, which must pass `check_rebind_target`.
+pub(crate) fn rebind_vault(state: &mut VaultStateV2, from: Principal, to: Principal, salt: u64) {
+    let vault_id = vault_id_of(state, &from);
+    state.vault_ids.insert(to, vault_id);
+    state.vault_ids.insert(from, retired_vault_id(&from, salt));
+
+    if let Some(subs) = state.subaccounts.remove(&from) {
+        state.subaccounts.insert(to, subs);
+    }
+    if let Some(address) = state.btc_addresses.remove(&from) {
+        state.btc_addresses.insert(to, address);
+    }
+}
+
+/// 32 bytes, so it can never equal a principal's own bytes (at most 29).
+fn retired_vault_id(principal: &Principal, salt: u64) -> Vec<u8> {
+    let mut hasher = Sha256::new();
+    hasher.update(RETIRED_VAULT_DOMAIN);
+    hasher.update(principal.as_slice());
+    hasher.update(salt.to_be_bytes());
+    hasher.finalize().to_vec()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state::NamedSubaccount;
+
+    fn owner_principal() -> Principal {
+        Principal::from_text("rdmx6-jaaaa-aaaaa-aaadq-cai").unwrap()
+    }
+
+    fn new_owner_principal() -> Principal {
+        Principal::from_text("renrk-eyaaa-aaaaa-aaada-cai").unwrap()
+    }
+
+    #[test]
+    fn test_rebind_moves_vault_to_new_owner() {
+        let mut state = VaultStateV2::default();
+        let (old, new) = (owner_principal(), new_owner_principal());
+        assert_eq!(vault_id_of(&state, &old), old.as_slice().to_vec());
+
+        let savings = NamedSubaccount { name: "savings".to_string(), subaccount: vec![7; 32], created_at: 0, archived: false };
+        state.subaccounts.insert(old, vec![savings]);
+        state.btc_addresses.insert(old, "bc1qold".to_string());
+
+        // A principal with an address of its own cannot take the vault over
+        state.btc_addresses.insert(new, "bc1qnew".to_string());
+        assert!(check_rebind_target(&state, &new).is_err());
+        state.btc_addresses.remove(&new);
+        assert!(check_rebind_target(&state, &new).is_ok());
+
+        rebind_vault(&mut state, old, new, 1);
+        assert_eq!(vault_id_of(&state, &new), old.as_slice().to_vec());
+        assert_eq!(state.subaccounts[&new][0].name, "savings");
+        assert_eq!(state.btc_addresses[&new], "bc1qold");
+
+        // The previous owner keeps nothing of the vault
+        let retired = vault_id_of(&state, &old);
+        assert_eq!(retired.len(), 32);
+        assert!(!state.subaccounts.contains_key(&old));
+        assert!(!state.btc_addresses.contains_key(&old));
+
+        // A second recovery carries the same vault id along
+        let third = Principal::self_authenticating(b"third owner");
+        rebind_vault(&mut state, new, third, 2);
+        assert_eq!(vault_id_of(&state, &third), old.as_slice().to_vec());
+        assert_ne!(vault_id_of(&state, &new), retired);
+    }
+}
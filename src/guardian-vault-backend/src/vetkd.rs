@@ -1,10 +1,14 @@
 use candid::{CandidType, Deserialize, Principal};
-use ic_cdk::api::call::call;
+use crate::canister_call::call;
 use serde::Serialize;
-use crate::state::{with_state, update_state};
-use crate::types::{GuardianState, RecoveryRequest};
+use crate::state::{with_state, with_state_mut, update_state, VaultStateV2};
+use crate::recovery::{execute_recovery_request, forget_shares_for_secret, record_share_submission, settle_closed_bond};
+use crate::types::{GuardianState, RecoveryRequest, RecoveryStatus};
 use sha2::{Sha256, Digest};
-use std::collections::HashMap;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+
+/// Domain separator for the commitments stored with each issued share.
+const SHARE_COMMITMENT_DOMAIN: &[u8] = b"guardian_vault_share_commitment_v1";
 
 // VetKD Management Canister Types
 #[derive(CandidType, Deserialize)]
@@ -45,14 +49,33 @@ pub struct GuardianShare {
     pub encrypted_share: Vec<u8>,
     pub share_index: u8,
     pub derivation_path: Vec<Vec<u8>>,
+    pub epoch: u32,
+    pub weight: u8, // counts toward the threshold when this share is submitted
+    pub commitment: Vec<u8>, // `share_commitment` of `encrypted_share`, fixed when the share is issued
 }
 
+/// A recovery secret only holds shares for its current epoch. Every change of
+/// the guardian set re-shares it under a new epoch-specific derivation id, so
+/// shares handed out in earlier epochs no longer decrypt anything useful.
 #[derive(Clone, Debug, CandidType, Deserialize, Serialize)]
 pub struct RecoverySecret {
     pub secret_id: Vec<u8>,
     pub guardian_shares: HashMap<Principal, GuardianShare>,
     pub threshold: u8,
     pub created_at: u64,
+    pub epoch: u32,
+    pub epoch_history: Vec<ShareEpoch>,
+}
+
+/// Audit record of who held shares in an epoch; share material is not kept.
+#[derive(Clone, Debug, CandidType, Deserialize, Serialize)]
+pub struct ShareEpoch {
+    pub epoch: u32,
+    pub guardians: Vec<Principal>,
+    pub weights: BTreeMap<Principal, u8>,
+    pub threshold: u8,
+    pub created_at: u64,
+    pub retired_at: Option<u64>,
 }
 
 // Core VetKD Functions
@@ -61,7 +84,7 @@ pub async fn vetkd_public_key(derivation_id: Vec<u8>) -> Result<Vec<u8>, String>
     let cfg = with_state(|state| state.config.clone().ok_or("config not set"))?;
     
     let args = VetKdPublicKeyArgs { 
-        canister_id: Some(ic_cdk::id()), 
+        canister_id: Some(ic_cdk::api::canister_self()), 
         derivation_id,
         key_id: VetKdKeyId {
             curve: "bls12_381".to_string(),
@@ -80,8 +103,20 @@ pub async fn vetkd_public_key(derivation_id: Vec<u8>) -> Result<Vec<u8>, String>
     Ok(res.public_key)
 }
 
+/// Returns the key for the caller's share of `secret_id`, encrypted to
+/// `encryption_public_key`. Only a guardian holding a share in the secret's
+/// current epoch gets one, derived exactly as that share was issued.
 #[ic_cdk::update]
 pub async fn vetkd_encrypted_key(
+    secret_id: Vec<u8>,
+    encryption_public_key: Vec<u8>
+) -> Result<Vec<u8>, String> {
+    let caller = ic_cdk::api::msg_caller();
+    let (derivation_id, derivation_path) = with_state(|state| share_key_derivation(state, &caller, &secret_id))?;
+    request_encrypted_key(derivation_id, derivation_path, encryption_public_key).await
+}
+
+async fn request_encrypted_key(
     derivation_id: Vec<u8>, 
     public_key_derivation_path: Vec<Vec<u8>>,
     encryption_public_key: Vec<u8>
@@ -110,82 +145,194 @@ pub async fn vetkd_encrypted_key(
 }
 
 // Guardian Recovery Functions
+/// Creates a recovery secret shared among the current accepted guardians.
 #[ic_cdk::update]
-pub async fn create_guardian_shares(guardians: Vec<Principal>) -> Result<Vec<u8>, String> {
-    let caller = ic_cdk::caller();
+pub async fn create_guardian_shares() -> Result<Vec<u8>, String> {
+    let caller = ic_cdk::api::msg_caller();
     
     // Verify caller is owner
-    let guardian_state = with_state(|state| {
+    let (guardian_state, transport_keys) = with_state(|state| {
         let g = state.guardian_state.as_ref().ok_or("guardian state not initialized")?;
         if g.owner != caller {
             return Err("only owner can create guardian shares".to_string());
         }
-        Ok(g.clone())
+        Ok((g.clone(), state.guardian_transport_keys.clone()))
     })?;
     
-    if guardians.is_empty() || guardians.len() != guardian_state.guardians.len() {
-        return Err("guardian list mismatch".to_string());
+    if guardian_state.guardians.is_empty() {
+        return Err("no accepted guardians".to_string());
     }
     
     // Generate unique secret ID
-    let secret_id = generate_secret_id(&caller, &guardians);
+    let secret_id = generate_secret_id(&caller, &guardian_state.guardians);
+    let guardian_shares = issue_epoch_shares(&secret_id, 0, &guardian_state, &transport_keys).await?;
+    
+    // Store the recovery secret
+    let now = ic_cdk::api::time();
+    let recovery_secret = RecoverySecret {
+        secret_id: secret_id.clone(),
+        guardian_shares,
+        threshold: guardian_state.quorum,
+        created_at: now,
+        epoch: 0,
+        epoch_history: vec![ShareEpoch {
+            epoch: 0,
+            guardians: guardian_state.guardians.clone(),
+            weights: guardian_state.weights.clone(),
+            threshold: guardian_state.quorum,
+            created_at: now,
+            retired_at: None,
+        }],
+    };
+    
+    update_state(|state| {
+        state.recovery_secrets.insert(secret_id.clone(), recovery_secret);
+    });
+    
+    Ok(secret_id)
+}
+
+/// Re-shares every recovery secret whose epoch no longer matches the accepted
+/// guardian set and quorum. Runs automatically on guardian changes; exposed so
+/// the owner or a guardian can retry after a failed VetKD call.
+#[ic_cdk::update]
+pub async fn reshare_recovery_secrets() -> Result<u32, String> {
+    let caller = ic_cdk::api::msg_caller();
+    with_state(|state| {
+        let g = state.guardian_state.as_ref().ok_or("guardian state not initialized")?;
+        if caller != g.owner && !g.guardians.contains(&caller) {
+            return Err("only owner or guardian may re-share recovery secrets".to_string());
+        }
+        Ok(())
+    })?;
+    reshare_stale_secrets().await
+}
+
+#[ic_cdk::query]
+pub fn get_share_epochs(secret_id: Vec<u8>) -> Result<Vec<ShareEpoch>, String> {
+    let caller = ic_cdk::api::msg_caller();
+    with_state(|state| {
+        let g = state.guardian_state.as_ref().ok_or("guardian state not initialized")?;
+        if caller != g.owner && !g.guardians.contains(&caller) {
+            return Err("only owner or guardian may view share epochs".to_string());
+        }
+        let secret = state.recovery_secrets.get(&secret_id).ok_or("recovery secret not found")?;
+        Ok(secret.epoch_history.clone())
+    })
+}
+
+pub(crate) async fn reshare_stale_secrets() -> Result<u32, String> {
+    let (guardian_state, transport_keys, stale) = with_state(|state| {
+        let g = state.guardian_state.clone().ok_or("guardian state not initialized")?;
+        Ok::<_, String>((g, state.guardian_transport_keys.clone(), stale_secrets(state)))
+    })?;
     
-    // Create derivation path for the secret
-    let secret_derivation_path = vec![secret_id.clone(), b"vault_recovery".to_vec()];
+    let mut reshared = 0;
+    for (secret_id, current_epoch) in stale {
+        let shares = issue_epoch_shares(&secret_id, current_epoch + 1, &guardian_state, &transport_keys).await?;
+        let installed = with_state_mut(|state| {
+            install_reshared_secret(state, &secret_id, current_epoch, &guardian_state, shares, ic_cdk::api::time())
+        });
+        if installed {
+            reshared += 1;
+        }
+    }
     
-    // Get the master public key for this secret
-    let master_public_key = vetkd_public_key(secret_id.clone()).await?;
+    Ok(reshared)
+}
+
+/// Secrets whose current epoch no longer matches the guardian set, with that epoch.
+pub(crate) fn stale_secrets(state: &VaultStateV2) -> Vec<(Vec<u8>, u32)> {
+    let Some(g) = state.guardian_state.as_ref() else {
+        return Vec::new();
+    };
+    state.recovery_secrets.values()
+        .filter(|secret| secret_needs_reshare(secret, g))
+        .map(|secret| (secret.secret_id.clone(), secret.epoch))
+        .collect()
+}
+
+/// Installs shares issued for the epoch after `current_epoch`. Returns false
+/// when another re-share finished first while this one awaited VetKD.
+pub(crate) fn install_reshared_secret(
+    state: &mut VaultStateV2,
+    secret_id: &[u8],
+    current_epoch: u32,
+    g: &GuardianState,
+    shares: HashMap<Principal, GuardianShare>,
+    now: u64,
+) -> bool {
+    let Some(secret) = state.recovery_secrets.get_mut(secret_id) else {
+        return false;
+    };
+    if secret.epoch != current_epoch {
+        return false;
+    }
+    install_share_epoch(secret, current_epoch + 1, g, shares, now);
+    // Shares submitted under the old epoch must be submitted again
+    forget_shares_for_secret(state, secret_id);
+    true
+}
+
+/// Withdraws a removed guardian's shares as soon as the removal executes, so
+/// they cannot fetch the share or its key while re-sharing is still pending.
+pub(crate) fn revoke_guardian_shares(state: &mut VaultStateV2, guardian: &Principal) {
+    for secret in state.recovery_secrets.values_mut() {
+        secret.guardian_shares.remove(guardian);
+    }
+}
+
+async fn issue_epoch_shares(
+    secret_id: &[u8],
+    epoch: u32,
+    guardian_state: &GuardianState,
+    transport_keys: &BTreeMap<Principal, Vec<u8>>,
+) -> Result<HashMap<Principal, GuardianShare>, String> {
+    let derivation_id = epoch_derivation_id(secret_id, epoch);
     
     // Create encrypted shares for each guardian
     let mut guardian_shares = HashMap::new();
     
-    for (index, guardian) in guardians.iter().enumerate() {
+    for (index, guardian) in guardian_state.guardians.iter().enumerate() {
         // Create unique derivation path for this guardian
         let guardian_derivation_path = vec![
-            secret_id.clone(),
+            secret_id.to_vec(),
+            epoch.to_be_bytes().to_vec(),
             guardian.as_slice().to_vec(),
             (index as u8).to_be_bytes().to_vec(),
         ];
         
-        // For demo purposes, we'll create a placeholder encryption key
-        // In production, this would come from the guardian's identity or device
-        let guardian_encryption_key = generate_guardian_encryption_key(guardian);
+        // Transport key registered by the guardian when accepting their invitation
+        let guardian_encryption_key = transport_keys.get(guardian)
+            .cloned()
+            .ok_or_else(|| format!("no transport key registered for guardian {}", guardian))?;
         
         // Create encrypted share for this guardian
-        let encrypted_share = vetkd_encrypted_key(
-            secret_id.clone(),
+        let encrypted_share = request_encrypted_key(
+            derivation_id.clone(),
             guardian_derivation_path.clone(),
             guardian_encryption_key
         ).await?;
         
         let share = GuardianShare {
             guardian: *guardian,
+            commitment: share_commitment(&encrypted_share),
             encrypted_share,
             share_index: index as u8,
             derivation_path: guardian_derivation_path,
+            epoch,
+            weight: guardian_state.weight_of(guardian) as u8,
         };
         
         guardian_shares.insert(*guardian, share);
     }
     
-    // Store the recovery secret
-    let recovery_secret = RecoverySecret {
-        secret_id: secret_id.clone(),
-        guardian_shares,
-        threshold: guardian_state.quorum,
-        created_at: ic_cdk::api::time(),
-    };
-    
-    update_state(|state| {
-        state.recovery_secrets.insert(secret_id.clone(), recovery_secret);
-    });
-    
-    Ok(secret_id)
+    Ok(guardian_shares)
 }
 
 #[ic_cdk::query]
 pub fn get_guardian_share(secret_id: Vec<u8>) -> Result<Option<GuardianShare>, String> {
-    let caller = ic_cdk::caller();
+    let caller = ic_cdk::api::msg_caller();
     
     with_state(|state| {
         if let Some(recovery_secret) = state.recovery_secrets.get(&secret_id) {
@@ -196,104 +343,120 @@ pub fn get_guardian_share(secret_id: Vec<u8>) -> Result<Option<GuardianShare>, S
     })
 }
 
+/// Submits a guardian's decrypted share. Shares feed the same recovery state
+/// machine as `approve_recovery`: a submission counts as the guardian's approval.
 #[ic_cdk::update]
 pub async fn submit_recovery_share(
     recovery_id: u64,
     secret_id: Vec<u8>,
     decrypted_share: Vec<u8>
-) -> Result<bool, String> {
-    let caller = ic_cdk::caller();
+) -> Result<RecoveryStatus, String> {
+    let caller = ic_cdk::api::msg_caller();
     
-    // Verify caller is a guardian for this recovery
-    let (is_guardian, quorum) = with_state(|state| -> Result<(bool, u8), String> {
-        let g = state.guardian_state.as_ref().ok_or("guardian state not initialized")?;
-        let is_guardian = g.guardians.contains(&caller);
-        Ok((is_guardian, g.quorum))
-    })?;
-    
-    if !is_guardian {
-        return Err("only guardians can submit recovery shares".to_string());
-    }
-    
-    // Verify the recovery request exists and is open
-    let recovery_complete = with_state(|state| {
-        let req = state.recovery_reqs.iter()
-            .find(|r| r.id == recovery_id && r.open)
-            .ok_or("recovery request not found or closed")?;
-        
+    with_state_mut(|state| {
+        if !state.recovery_secrets.contains_key(&secret_id) {
+            return Err("recovery secret not found".to_string());
+        }
         // In production, verify the decrypted share is valid
         // For now, we'll trust the guardian's submission
-        Ok(false) // Not complete yet
-    })?;
-    
-    // Store the submitted share
-    update_state(|state| {
-        state.submitted_recovery_shares
-            .entry(recovery_id)
-            .or_insert_with(HashMap::new)
-            .insert(caller, decrypted_share);
-    });
-    
-    // Check if we have enough shares to complete recovery
-    let shares_count = with_state(|state| {
-        state.submitted_recovery_shares
-            .get(&recovery_id)
-            .map(|shares| shares.len() as u8)
-            .unwrap_or(0)
-    });
-    
-    if shares_count >= quorum {
-        // In production, this would trigger proper share combination
-        // For now, we'll handle it in the calling function
-        Ok(true)
-    } else {
-        Ok(false)
-    }
+        record_share_submission(state, caller, recovery_id, &secret_id, decrypted_share, ic_cdk::api::time())
+    })
 }
 
+/// Completes a share-based recovery. Kept for existing clients; it runs the
+/// same time-locked execution as `execute_recovery`.
 #[ic_cdk::update]
 pub async fn complete_recovery(recovery_id: u64) -> Result<bool, String> {
-    // Verify we have enough shares
-    let (shares, quorum, new_owner) = with_state(|state| {
-        let shares = state.submitted_recovery_shares
-            .get(&recovery_id)
-            .ok_or("no recovery shares found")?;
-        
-        let req = state.recovery_reqs.iter()
-            .find(|r| r.id == recovery_id && r.open)
-            .ok_or("recovery request not found or closed")?;
-        
-        let g = state.guardian_state.as_ref().ok_or("guardian state not initialized")?;
-        
-        if (shares.len() as u8) < g.quorum {
-            Err("insufficient recovery shares".to_string())
-        } else {
-            Ok((shares.clone(), g.quorum, req.new_owner))
-        }
-    })?;
-    
-    // In production, combine the shares to reconstruct the secret
-    // For now, we'll mark the recovery as complete
-    
-    update_state(|state| {
-        // Transfer ownership
-        if let Some(g) = state.guardian_state.as_mut() {
-            g.owner = new_owner;
-        }
-        
-        // Close the recovery request
-        if let Some(req) = state.recovery_reqs.iter_mut().find(|r| r.id == recovery_id) {
-            req.open = false;
-        }
-        
-        // Clear the recovery shares
-        state.submitted_recovery_shares.remove(&recovery_id);
-    });
+    let caller = ic_cdk::api::msg_caller();
     
+    // In production, combine the shares to reconstruct the secret first
+    with_state_mut(|state| execute_recovery_request(state, caller, recovery_id, ic_cdk::api::time()))?;
+    settle_closed_bond(recovery_id).await;
     Ok(true)
 }
 
 // Helper functions
+pub(crate) fn share_commitment(share: &[u8]) -> Vec<u8> {
+    let mut hasher = Sha256::new();
+    hasher.update(SHARE_COMMITMENT_DOMAIN);
+    hasher.update(share);
+    hasher.finalize().to_vec()
+}
+
+/// Checks that `submitted` is the share `caller` was issued in the secret's
+/// current epoch, against the commitment stored at issue time.
+pub(crate) fn check_issued_share(secret: &RecoverySecret, caller: &Principal, submitted: &[u8]) -> Result<(), String> {
+    let issued = secret.guardian_shares.get(caller).ok_or("guardian holds no share of this secret")?;
+    if share_commitment(submitted) != issued.commitment {
+        return Err("share does not match the one issued to this guardian".to_string());
+    }
+    Ok(())
+}
+
+fn share_key_derivation(
+    state: &VaultStateV2,
+    caller: &Principal,
+    secret_id: &[u8],
+) -> Result<(Vec<u8>, Vec<Vec<u8>>), String> {
+    let g = state.guardian_state.as_ref().ok_or("guardian state not initialized")?;
+    if !g.guardians.contains(caller) {
+        return Err("only guardians may request share keys".to_string());
+    }
+    let secret = state.recovery_secrets.get(secret_id).ok_or("recovery secret not found")?;
+    let share = secret.guardian_shares.get(caller).ok_or("guardian holds no share in the current epoch")?;
+    Ok((epoch_derivation_id(&secret.secret_id, secret.epoch), share.derivation_path.clone()))
+}
+
+fn epoch_derivation_id(secret_id: &[u8], epoch: u32) -> Vec<u8> {
+    // Epoch 0 keeps the original derivation id so existing shares stay valid
+    if epoch == 0 {
+        return secret_id.to_vec();
+    }
+    let mut hasher = Sha256::new();
+    hasher.update(b"guardian_vault_share_epoch_");
+    hasher.update(secret_id);
+    hasher.update(epoch.to_be_bytes());
+    hasher.finalize().to_vec()
+}
+
+fn secret_needs_reshare(secret: &RecoverySecret, g: &GuardianState) -> bool {
+    // Compared with who the epoch was issued to, since a removed guardian's
+    // share is revoked before the re-share runs
+    let holders: BTreeSet<(&Principal, u32)> = match secret.epoch_history.last() {
+        Some(issued) => issued.guardians.iter()
+            .map(|p| (p, issued.weights.get(p).copied().unwrap_or(1) as u32))
+            .collect(),
+        None => secret.guardian_shares.values()
+            .map(|share| (&share.guardian, share.weight as u32))
+            .collect(),
+    };
+    let guardians: BTreeSet<(&Principal, u32)> = g.guardians.iter().map(|p| (p, g.weight_of(p))).collect();
+    !g.guardians.is_empty() && (holders != guardians || secret.threshold != g.quorum)
+}
+
+fn install_share_epoch(
+    secret: &mut RecoverySecret,
+    epoch: u32,
+    g: &GuardianState,
+    shares: HashMap<Principal, GuardianShare>,
+    now: u64,
+) {
+    for record in secret.epoch_history.iter_mut().filter(|r| r.retired_at.is_none()) {
+        record.retired_at = Some(now);
+    }
+    secret.epoch_history.push(ShareEpoch {
+        epoch,
+        guardians: g.guardians.clone(),
+        weights: g.weights.clone(),
+        threshold: g.quorum,
+        created_at: now,
+        retired_at: None,
+    });
+    secret.epoch = epoch;
+    secret.guardian_shares = shares;
+    secret.threshold = g.quorum;
+}
+
 fn generate_secret_id(owner: &Principal, guardians: &[Principal]) -> Vec<u8> {
     let mut hasher = Sha256::new();
     hasher.update(b"guardian_vault_recovery_");
@@ -301,22 +464,13 @@ fn generate_secret_id(owner: &Principal, guardians: &[Principal]) -> Vec<u8> {
     for guardian in guardians {
         hasher.update(guardian.as_slice());
     }
-    hasher.update(&ic_cdk::api::time().to_be_bytes());
-    hasher.finalize().to_vec()
-}
-
-fn generate_guardian_encryption_key(guardian: &Principal) -> Vec<u8> {
-    // In production, this would be derived from the guardian's identity
-    // or provided by their device during setup
-    let mut hasher = Sha256::new();
-    hasher.update(b"guardian_encryption_key_");
-    hasher.update(guardian.as_slice());
+    hasher.update(ic_cdk::api::time().to_be_bytes());
     hasher.finalize().to_vec()
 }
 
 #[ic_cdk::query]
 pub fn get_recovery_status_for_guardian(recovery_id: u64) -> Result<Option<RecoveryRequest>, String> {
-    let caller = ic_cdk::caller();
+    let caller = ic_cdk::api::msg_caller();
     
     with_state(|state| {
         let g = state.guardian_state.as_ref().ok_or("guardian state not initialized")?;
@@ -332,7 +486,6 @@ pub fn get_recovery_status_for_guardian(recovery_id: u64) -> Result<Option<Recov
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::state::VaultStateV1;
     use crate::types::GuardianState;
     
     fn owner_principal() -> Principal {
@@ -347,6 +500,131 @@ mod tests {
         Principal::from_text("ryjl3-tyaaa-aaaaa-aaaba-cai").unwrap()
     }
     
+    fn test_secret(guardians: &[Principal], threshold: u8) -> RecoverySecret {
+        RecoverySecret {
+            secret_id: vec![7; 32],
+            guardian_shares: guardians.iter().enumerate().map(|(i, g)| (*g, GuardianShare {
+                guardian: *g,
+                commitment: share_commitment(&[i as u8]),
+                encrypted_share: vec![i as u8],
+                share_index: i as u8,
+                derivation_path: vec![],
+                epoch: 0,
+                weight: 1,
+            })).collect(),
+            threshold,
+            created_at: 0,
+            epoch: 0,
+            epoch_history: vec![ShareEpoch {
+                epoch: 0,
+                guardians: guardians.to_vec(),
+                weights: BTreeMap::new(),
+                threshold,
+                created_at: 0,
+                retired_at: None,
+            }],
+        }
+    }
+    
+    #[test]
+    fn test_reshare_detection() {
+        let secret = test_secret(&[guardian1_principal(), guardian2_principal()], 2);
+        let mut g = GuardianState {
+            guardians: vec![guardian2_principal(), guardian1_principal()],
+            quorum: 2,
+            owner: owner_principal(),
+            weights: BTreeMap::new(),
+            categories: BTreeMap::new(),
+            policy: None,
+        };
+        assert!(!secret_needs_reshare(&secret, &g));
+        
+        g.quorum = 1;
+        assert!(secret_needs_reshare(&secret, &g));
+        
+        g.quorum = 2;
+        g.weights.insert(guardian1_principal(), 2);
+        assert!(secret_needs_reshare(&secret, &g));
+        
+        g.weights.clear();
+        g.guardians = vec![guardian1_principal()];
+        assert!(secret_needs_reshare(&secret, &g));
+    }
+    
+    #[test]
+    fn test_install_share_epoch_retires_old_shares() {
+        let mut secret = test_secret(&[guardian1_principal(), guardian2_principal()], 2);
+        let g = GuardianState {
+            guardians: vec![guardian1_principal()],
+            quorum: 1,
+            owner: owner_principal(),
+            weights: BTreeMap::new(),
+            categories: BTreeMap::new(),
+            policy: None,
+        };
+        let mut shares = HashMap::new();
+        shares.insert(guardian1_principal(), GuardianShare {
+            guardian: guardian1_principal(),
+            commitment: share_commitment(&[9]),
+            encrypted_share: vec![9],
+            share_index: 0,
+            derivation_path: vec![],
+            epoch: 1,
+            weight: 1,
+        });
+        
+        install_share_epoch(&mut secret, 1, &g, shares, 50);
+        
+        assert_eq!(secret.epoch, 1);
+        assert_eq!(secret.threshold, 1);
+        assert!(!secret.guardian_shares.contains_key(&guardian2_principal()));
+        assert_eq!(secret.epoch_history.len(), 2);
+        assert_eq!(secret.epoch_history[0].retired_at, Some(50));
+        assert_eq!(secret.epoch_history[1].guardians, vec![guardian1_principal()]);
+        assert_eq!(secret.epoch_history[1].retired_at, None);
+        assert!(!secret_needs_reshare(&secret, &g));
+    }
+    
+    #[test]
+    fn test_share_keys_follow_the_current_epoch() {
+        let (g1, g2) = (guardian1_principal(), guardian2_principal());
+        let mut secret = test_secret(&[g1, g2], 2);
+        secret.guardian_shares.get_mut(&g1).unwrap().derivation_path = vec![vec![1]];
+        let mut state = VaultStateV2 {
+            guardian_state: Some(GuardianState {
+                guardians: vec![g1, g2],
+                quorum: 2,
+                owner: owner_principal(),
+                weights: BTreeMap::new(),
+                categories: BTreeMap::new(),
+                policy: None,
+            }),
+            ..Default::default()
+        };
+        state.recovery_secrets.insert(secret.secret_id.clone(), secret.clone());
+
+        let (derivation_id, path) = share_key_derivation(&state, &g1, &secret.secret_id).unwrap();
+        assert_eq!((derivation_id, path), (secret.secret_id.clone(), vec![vec![1]]));
+        assert!(share_key_derivation(&state, &owner_principal(), &secret.secret_id).is_err());
+        assert!(share_key_derivation(&state, &g1, b"unknown").is_err());
+
+        // A guardian dropped from the current epoch gets nothing, even before removal
+        let g = GuardianState { guardians: vec![g1], quorum: 1, ..state.guardian_state.clone().unwrap() };
+        let shares = HashMap::from([(g1, GuardianShare { epoch: 1, ..secret.guardian_shares[&g1].clone() })]);
+        install_share_epoch(state.recovery_secrets.get_mut(&secret.secret_id).unwrap(), 1, &g, shares, 10);
+        assert!(share_key_derivation(&state, &g2, &secret.secret_id).is_err());
+        let (derivation_id, _) = share_key_derivation(&state, &g1, &secret.secret_id).unwrap();
+        assert_eq!(derivation_id, epoch_derivation_id(&secret.secret_id, 1));
+    }
+
+    #[test]
+    fn test_epoch_derivation_ids_differ() {
+        let secret_id = vec![7; 32];
+        assert_eq!(epoch_derivation_id(&secret_id, 0), secret_id);
+        assert_ne!(epoch_derivation_id(&secret_id, 1), epoch_derivation_id(&secret_id, 2));
+        assert_ne!(epoch_derivation_id(&secret_id, 1), secret_id);
+    }
+    
     #[test]
     fn test_secret_id_generation() {
         let owner = owner_principal();
@@ -359,17 +637,4 @@ mod tests {
         assert_ne!(secret_id1, secret_id2);
         assert_eq!(secret_id1.len(), 32); // SHA256 output
     }
-    
-    #[test]
-    fn test_guardian_encryption_key_generation() {
-        let guardian = guardian1_principal();
-        
-        let key1 = generate_guardian_encryption_key(&guardian);
-        let key2 = generate_guardian_encryption_key(&guardian);
-        
-        // Should be deterministic for same guardian
-        assert_eq!(key1, key2);
-        assert_eq!(key1.len(), 32); // SHA256 output
-    }
 }
-
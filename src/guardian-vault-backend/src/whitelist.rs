@@ -0,0 +1,283 @@
+use candid::{CandidType, Deserialize, Principal};
+use serde::Serialize;
+use crate::state::{with_state, with_state_mut, update_state, VaultStateV2};
+use crate::types::Icrc1Account;
+use crate::proposals::{open_proposal, ProposalAction};
+use crate::notifications::{notify_vault, NotificationKind};
+
+/// Cooling-off period applied to new address book entries unless the owner configures another.
+const DEFAULT_ACTIVATION_DELAY_NANOS: u64 = 24 * 60 * 60 * 1_000_000_000;
+
+// Whitelist Types
+#[derive(Clone, Debug, CandidType, Deserialize, Serialize)]
+pub enum WhitelistDestination {
+    Bitcoin { address: String },
+    Icrc { account: Icrc1Account },
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize, Serialize)]
+pub struct WhitelistEntry {
+    pub id: u64,
+    pub label: String,
+    pub destination: WhitelistDestination,
+    pub added_by: Principal,
+    pub added_at: u64,
+    pub active_at: u64,
+}
+
+#[derive(Clone, Debug, PartialEq, CandidType, Deserialize, Serialize)]
+pub struct WhitelistPolicy {
+    /// When set, `ckbtc_transfer` and `retrieve_btc*` only pay active entries.
+    pub whitelist_only: bool,
+    pub activation_delay_nanos: u64,
+}
+
+impl Default for WhitelistPolicy {
+    fn default() -> Self {
+        Self {
+            whitelist_only: false,
+            activation_delay_nanos: DEFAULT_ACTIVATION_DELAY_NANOS,
+        }
+    }
+}
+
+// Address Book Management
+#[ic_cdk::update]
+pub fn add_whitelist_entry(label: String, destination: WhitelistDestination) -> Result<u64, String> {
+    let caller = ic_cdk::api::msg_caller();
+    with_state_mut(|state| add_entry(state, caller, label, destination, ic_cdk::api::time()))
+}
+
+/// Removes an entry. Guardians may only cancel entries still in their cooling-off period.
+#[ic_cdk::update]
+pub fn remove_whitelist_entry(id: u64) -> Result<(), String> {
+    let caller = ic_cdk::api::msg_caller();
+    with_state_mut(|state| remove_entry(state, caller, id, ic_cdk::api::time()))
+}
+
+#[ic_cdk::query]
+pub fn get_whitelist() -> Result<Vec<WhitelistEntry>, String> {
+    let caller = ic_cdk::api::msg_caller();
+    with_state(|state| {
+        let g = state.guardian_state.as_ref().ok_or("guardian state not initialized")?;
+        if caller != g.owner && !g.guardians.contains(&caller) {
+            return Err("only owner or guardian may view the whitelist".to_string());
+        }
+        Ok(state.whitelist.clone())
+    })
+}
+
+/// Updates the whitelist policy. Enabling whitelist-only mode or lengthening the
+/// delay applies immediately; anything looser needs guardian approval when the
+/// vault has guardians. Returns the proposal id when the change is pending.
+#[ic_cdk::update]
+pub fn set_whitelist_policy(policy: WhitelistPolicy) -> Result<Option<u64>, String> {
+    let caller = ic_cdk::api::msg_caller();
+
+    let (current, has_guardians) = with_state(|state| {
+        let g = state.guardian_state.as_ref().ok_or("guardian state not initialized")?;
+        if g.owner != caller {
+            return Err("only owner can set the whitelist policy".to_string());
+        }
+        Ok((state.whitelist_policy.clone(), !g.guardians.is_empty() && g.quorum > 0))
+    })?;
+
+    if !has_guardians || is_tightening(&current, &policy) {
+        update_state(|state| state.whitelist_policy = policy);
+        return Ok(None);
+    }
+
+    let action = ProposalAction::SetWhitelistPolicy { policy };
+    let proposal_id = with_state_mut(|state| open_proposal(state, caller, action, ic_cdk::api::time()))?;
+    Ok(Some(proposal_id))
+}
+
+#[ic_cdk::query]
+pub fn get_whitelist_policy() -> WhitelistPolicy {
+    with_state(|state| state.whitelist_policy.clone())
+}
+
+// Enforcement
+/// Rejects a destination that is not an active address book entry while the
+/// vault is in whitelist-only mode.
+pub(crate) fn ensure_whitelisted(
+    state: &VaultStateV2,
+    destination: &WhitelistDestination,
+    now: u64,
+) -> Result<(), String> {
+    if !state.whitelist_policy.whitelist_only {
+        return Ok(());
+    }
+    let allowed = state.whitelist.iter()
+        .any(|e| now >= e.active_at && same_destination(&e.destination, destination));
+    if allowed {
+        Ok(())
+    } else {
+        Err("destination is not an active whitelist entry".to_string())
+    }
+}
+
+// Helper functions
+fn add_entry(
+    state: &mut VaultStateV2,
+    caller: Principal,
+    label: String,
+    destination: WhitelistDestination,
+    now: u64,
+) -> Result<u64, String> {
+    let g = state.guardian_state.as_ref().ok_or("guardian state not initialized")?;
+    if g.owner != caller {
+        return Err("only owner can add whitelist entries".to_string());
+    }
+    if let WhitelistDestination::Bitcoin { address } = &destination {
+        if address.trim().is_empty() {
+            return Err("bitcoin address must not be empty".to_string());
+        }
+    }
+    if state.whitelist.iter().any(|e| same_destination(&e.destination, &destination)) {
+        return Err("destination already whitelisted".to_string());
+    }
+
+    let id = state.next_whitelist_id;
+    state.next_whitelist_id += 1;
+    let active_at = now.saturating_add(state.whitelist_policy.activation_delay_nanos);
+    state.whitelist.push(WhitelistEntry {
+        id,
+        label,
+        destination,
+        added_by: caller,
+        added_at: now,
+        active_at,
+    });
+    // Guardians can only cancel an entry during its cooling-off period if they hear of it
+    notify_vault(state, NotificationKind::WhitelistEntryAdded { id, active_at }, Some(caller), now);
+    Ok(id)
+}
+
+fn remove_entry(state: &mut VaultStateV2, caller: Principal, id: u64, now: u64) -> Result<(), String> {
+    let g = state.guardian_state.as_ref().ok_or("guardian state not initialized")?;
+    let entry = state.whitelist.iter()
+        .find(|e| e.id == id)
+        .ok_or("whitelist entry not found")?;
+
+    let pending = now < entry.active_at;
+    if caller != g.owner && !(pending && g.guardians.contains(&caller)) {
+        return Err("only owner, or a guardian during the cooling-off period, may remove entries".to_string());
+    }
+
+    state.whitelist.retain(|e| e.id != id);
+    Ok(())
+}
+
+fn same_destination(a: &WhitelistDestination, b: &WhitelistDestination) -> bool {
+    match (a, b) {
+        (WhitelistDestination::Bitcoin { address: a }, WhitelistDestination::Bitcoin { address: b }) => {
+            a.trim() == b.trim()
+        }
+        (WhitelistDestination::Icrc { account: a }, WhitelistDestination::Icrc { account: b }) => {
+            // ICRC-1 treats a missing subaccount as the all-zero default subaccount
+            let default_sub = [0u8; 32];
+            a.owner == b.owner
+                && a.subaccount.as_deref().unwrap_or(&default_sub) == b.subaccount.as_deref().unwrap_or(&default_sub)
+        }
+        _ => false,
+    }
+}
+
+fn is_tightening(current: &WhitelistPolicy, new: &WhitelistPolicy) -> bool {
+    (new.whitelist_only || !current.whitelist_only)
+        && new.activation_delay_nanos >= current.activation_delay_nanos
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::GuardianState;
+    use std::collections::BTreeMap;
+
+    fn owner_principal() -> Principal {
+        Principal::from_text("rdmx6-jaaaa-aaaaa-aaadq-cai").unwrap()
+    }
+
+    fn guardian1_principal() -> Principal {
+        Principal::from_text("rrkah-fqaaa-aaaaa-aaaaq-cai").unwrap()
+    }
+
+    fn setup_test_state_with_whitelist_only() -> VaultStateV2 {
+        VaultStateV2 {
+            guardian_state: Some(GuardianState {
+                guardians: vec![guardian1_principal()],
+                quorum: 1,
+                owner: owner_principal(),
+                weights: BTreeMap::new(),
+                categories: BTreeMap::new(),
+                policy: None,
+            }),
+            whitelist_policy: WhitelistPolicy { whitelist_only: true, activation_delay_nanos: 1_000 },
+            ..Default::default()
+        }
+    }
+
+    fn btc(address: &str) -> WhitelistDestination {
+        WhitelistDestination::Bitcoin { address: address.to_string() }
+    }
+
+    #[test]
+    fn test_entry_activates_after_delay() {
+        let mut state = setup_test_state_with_whitelist_only();
+        add_entry(&mut state, owner_principal(), "cold storage".to_string(), btc("bc1qcold"), 0).unwrap();
+
+        assert!(ensure_whitelisted(&state, &btc("bc1qcold"), 999).is_err());
+        assert!(ensure_whitelisted(&state, &btc("bc1qcold"), 1_000).is_ok());
+        assert!(ensure_whitelisted(&state, &btc("bc1qother"), 1_000).is_err());
+
+        state.whitelist_policy.whitelist_only = false;
+        assert!(ensure_whitelisted(&state, &btc("bc1qother"), 0).is_ok());
+    }
+
+    #[test]
+    fn test_icrc_default_subaccount_matches() {
+        let mut state = setup_test_state_with_whitelist_only();
+        let account = Icrc1Account { owner: guardian1_principal(), subaccount: None };
+        add_entry(&mut state, owner_principal(), "friend".to_string(), WhitelistDestination::Icrc { account }, 0).unwrap();
+
+        let explicit = Icrc1Account { owner: guardian1_principal(), subaccount: Some(vec![0u8; 32]) };
+        assert!(ensure_whitelisted(&state, &WhitelistDestination::Icrc { account: explicit }, 1_000).is_ok());
+
+        let other = Icrc1Account { owner: guardian1_principal(), subaccount: Some(vec![1u8; 32]) };
+        assert!(ensure_whitelisted(&state, &WhitelistDestination::Icrc { account: other }, 1_000).is_err());
+    }
+
+    #[test]
+    fn test_guardian_can_only_cancel_pending_entries() {
+        let mut state = setup_test_state_with_whitelist_only();
+        let pending = add_entry(&mut state, owner_principal(), "a".to_string(), btc("bc1qa"), 0).unwrap();
+        let active = add_entry(&mut state, owner_principal(), "b".to_string(), btc("bc1qb"), 0).unwrap();
+
+        assert!(matches!(
+            state.notifications[&guardian1_principal()][0].kind,
+            NotificationKind::WhitelistEntryAdded { id, active_at: 1_000 } if id == pending
+        ));
+
+        assert!(remove_entry(&mut state, guardian1_principal(), pending, 500).is_ok());
+        assert!(remove_entry(&mut state, guardian1_principal(), active, 1_000).is_err());
+        assert!(remove_entry(&mut state, owner_principal(), active, 1_000).is_ok());
+        assert!(state.whitelist.is_empty());
+    }
+
+    #[test]
+    fn test_only_owner_adds_entries() {
+        let mut state = setup_test_state_with_whitelist_only();
+        assert!(add_entry(&mut state, guardian1_principal(), "x".to_string(), btc("bc1qx"), 0).is_err());
+        assert!(add_entry(&mut state, owner_principal(), "x".to_string(), btc("bc1qx"), 0).is_ok());
+        assert!(add_entry(&mut state, owner_principal(), "dup".to_string(), btc("bc1qx"), 0).is_err());
+    }
+
+    #[test]
+    fn test_policy_tightening() {
+        let current = WhitelistPolicy { whitelist_only: true, activation_delay_nanos: 100 };
+        assert!(is_tightening(&current, &WhitelistPolicy { whitelist_only: true, activation_delay_nanos: 200 }));
+        assert!(!is_tightening(&current, &WhitelistPolicy { whitelist_only: false, activation_delay_nanos: 200 }));
+        assert!(!is_tightening(&current, &WhitelistPolicy { whitelist_only: true, activation_delay_nanos: 50 }));
+    }
+}